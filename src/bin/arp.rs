@@ -1,59 +1,32 @@
-use std::io::{Cursor, Read, Write};
+use std::{env, process};
 
 use tendium::protocol::{
-    internet::address::IPAddress,
-    link::{
-        address::MacAddress,
-        ethernet::{EtherType, EthernetFrame, EthernetHeader, EthernetPayload},
-    },
+    internet,
+    link::{self, address::MacAddress},
     physical::{tuntap::TunTap, Device},
 };
 
 fn main() -> tun::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 2 {
+        eprintln!("Usage: {} <ip address>[/len]", args[0]);
+        process::exit(1);
+    }
+
     let mac_addr = MacAddress([0x44, 0xc4, 0xc3, 0xf1, 0x15, 0x5b]);
-    let ip_addr = IPAddress([10, 0, 0, 4]);
+    let mut config = internet::Config::default();
+    let ip_addr = config.parse_addr(&args[1])?;
 
-    let mut dev = TunTap::new("tap0".into())?;
+    let dev = TunTap::new("tap0".into())?;
     println!("[{}] {}", dev.name(), dev.address()?);
 
-    let mut buf = [0; 4096];
+    let link_iface = link::Interface::with_mac_addr(Box::new(dev), mac_addr);
+    let mut iface = internet::Interface::with_config(link_iface, ip_addr, config)?;
     loop {
-        let len = dev.read(&mut buf).unwrap();
-
-        let mut cursor = Cursor::new(&buf[0..len]);
-        let frame = EthernetFrame::read_from(&mut cursor)?;
-
-        println!("--- [tap0] {} bytes ---", len);
-
-        match frame.payload {
-            EthernetPayload::Arp(mut arp) => {
-                println!("Received:");
-                println!("{}", frame.header);
-                println!("{}", arp);
-                arp.target_hardware_addr = arp.sender_hardware_addr;
-                arp.target_protocol_addr = arp.sender_protocol_addr;
-                arp.sender_hardware_addr = mac_addr.clone();
-                arp.sender_protocol_addr = ip_addr.clone();
-                arp.opcode = tendium::protocol::link::arp::Opcode::Reply;
-
-                let new_frame_header = EthernetHeader {
-                    dst_addr: frame.header.src_addr,
-                    src_addr: mac_addr.clone(),
-                    typ: EtherType::Arp,
-                };
-                println!("Sent:");
-                println!("{}", new_frame_header);
-                println!("{}", arp);
-
-                let new_frame = EthernetFrame {
-                    header: new_frame_header,
-                    payload: EthernetPayload::Arp(arp),
-                };
-                let mut raw_frame = Vec::new();
-                new_frame.write_to(&mut raw_frame)?;
-                dev.write_all(&mut raw_frame)?;
-            }
-            _ => println!("==> unknown type. dropping..."),
-        }
+        let datagram = iface.recv()?;
+        println!("--- [{}] ---", iface.name());
+        println!("{}", datagram.header);
+        println!("{}", iface.arp_table());
+        println!();
     }
 }
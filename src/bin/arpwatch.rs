@@ -0,0 +1,46 @@
+use std::{
+    env, io,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tendium::protocol::{
+    link::{self, arp::ArpWatch, ethernet::EthernetPayload},
+    physical::{raw_socket::RawSocket, Device},
+};
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 2 {
+        println!("Usage: {} <ifname>", args[0]);
+        return Ok(());
+    }
+
+    let dev = RawSocket::new(args[1].clone())?;
+    println!("[{}] {}", dev.name(), dev.address()?);
+
+    let mut link_iface = link::Interface::new(Box::new(dev))?;
+    let mut watch = ArpWatch::new();
+    loop {
+        let frame = match link_iface.recv() {
+            Ok(frame) => frame,
+            // one bad frame on the wire does not stop the watch
+            Err(e)
+                if e.kind() == io::ErrorKind::InvalidData
+                    || e.kind() == io::ErrorKind::UnexpectedEof =>
+            {
+                eprintln!("iface={} malformed frame: {}", link_iface.name(), e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        if let EthernetPayload::Arp(arp) = frame.payload {
+            let time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            for event in watch.process(&arp) {
+                println!("time={} iface={} {}", time, link_iface.name(), event);
+            }
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use std::{env, io, io::Cursor};
+
+use tendium::protocol::{
+    dump,
+    link::{bridge::Bridge, ethernet::EthernetFrame, stp::Protocol},
+    physical::{raw_socket::RawSocket, tuntap::TunTap, Device},
+};
+
+fn open(name: &str) -> io::Result<Box<dyn Device>> {
+    if let Some(name) = name.strip_prefix("tap:") {
+        let dev = TunTap::new(name.into()).map_err(io::Error::other)?;
+        return Ok(Box::new(dev));
+    }
+
+    let dev = RawSocket::new(name.into())?;
+    dev.set_promiscuous()?;
+    dev.ignore_outgoing()?;
+    Ok(Box::new(dev))
+}
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    let protocol = match args.get(1).map(|s| s.as_str()) {
+        Some("--stp") => Some(Protocol::Stp),
+        Some("--rstp") => Some(Protocol::Rstp),
+        _ => None,
+    };
+    let names = &args[if protocol.is_some() { 2 } else { 1 }..];
+    if names.len() < 2 {
+        println!(
+            "Usage: {} [--stp|--rstp] <ifname|tap:name> <ifname|tap:name>...",
+            args[0]
+        );
+        return Ok(());
+    }
+
+    let mut bridge = Bridge::new();
+    for name in names {
+        let dev = open(name)?;
+        println!("[{}] {}", dev.name(), dev.address()?);
+        bridge.add_port(dev);
+    }
+    if let Some(protocol) = protocol {
+        bridge.enable_stp(protocol)?;
+    }
+
+    loop {
+        for (port, frame) in bridge.poll(None)? {
+            println!(
+                "--- [{}] {} bytes ---",
+                bridge.ports()[port].name(),
+                frame.len()
+            );
+            match EthernetFrame::read_from(&mut Cursor::new(&frame)) {
+                Ok(frame) => dump(&frame),
+                Err(e) => println!("==> {}", e),
+            }
+            println!();
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use std::{env, io, time::Duration};
+
+use tendium::protocol::{
+    link::{self, ethernet::EthernetPayload, lldp::LldpAgent},
+    physical::{raw_socket::RawSocket, Device},
+};
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 || args.len() > 3 {
+        println!("Usage: {} <ifname> [system name]", args[0]);
+        return Ok(());
+    }
+
+    let dev = RawSocket::new(args[1].clone())?;
+    println!("[{}] {}", dev.name(), dev.address()?);
+
+    let mut link_iface = link::Interface::new(Box::new(dev))?;
+    let mut agent = LldpAgent::new(link_iface.mac_addr().clone(), link_iface.name());
+    agent.system_name = args.get(2).cloned();
+
+    loop {
+        agent.poll(&mut link_iface)?;
+
+        let frame = match link_iface.recv_timeout(Some(Duration::from_secs(1))) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => continue,
+            Err(e)
+                if e.kind() == io::ErrorKind::InvalidData
+                    || e.kind() == io::ErrorKind::UnexpectedEof =>
+            {
+                eprintln!("[{}] malformed frame: {}", link_iface.name(), e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        if frame.header.src_addr == *link_iface.mac_addr() {
+            continue;
+        }
+        if let EthernetPayload::Lldp(lldpdu) = frame.payload {
+            println!("--- [{}] {} ---", link_iface.name(), frame.header.src_addr);
+            println!("{}", lldpdu);
+            agent.receive(lldpdu);
+        }
+    }
+}
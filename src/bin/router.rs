@@ -0,0 +1,48 @@
+use std::{env, fs::File, io::BufReader, process};
+
+use tendium::protocol::{
+    internet::{self, firewall::Firewall, Stack},
+    link::{self, address::MacAddress},
+    physical::{tuntap::TunTap, Device},
+};
+
+fn main() -> tun::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 || args.len() > 4 {
+        eprintln!(
+            "Usage: {} <tap0 ip address>[/len] <tap1 ip address>[/len] [rules file]",
+            args[0]
+        );
+        process::exit(1);
+    }
+
+    let mut stack = Stack::new();
+    for (i, (name, arg)) in ["tap0", "tap1"].iter().zip(&args[1..3]).enumerate() {
+        let mut config = internet::Config::default();
+        let ip_addr = config.parse_addr(arg)?;
+        let dev = TunTap::new(name.to_string())?;
+        // the router is not the host side of the TAP device
+        let mac_addr = MacAddress([0x44, 0xc4, 0xc3, 0xf1, 0x15, 0x5b + i as u8]);
+        println!("[{}] {} {}", dev.name(), mac_addr, ip_addr);
+        let link_iface = link::Interface::with_mac_addr(Box::new(dev), mac_addr);
+        stack.add_interface(internet::Interface::with_config(
+            link_iface, ip_addr, config,
+        )?);
+    }
+    stack.set_forwarding(true);
+    println!("{}", stack.routes());
+
+    // the firewall rules, if a file of them is given
+    if let Some(path) = args.get(3) {
+        *stack.firewall() = Firewall::read_from(BufReader::new(File::open(path)?))?;
+        println!("{}", stack.firewall());
+    }
+
+    loop {
+        let (index, datagram) = stack.recv()?;
+        println!("--- [{}] ---", stack.interfaces()[index].name());
+        println!("{}", datagram.header);
+        println!("{:?}", stack.stats());
+        println!();
+    }
+}
@@ -0,0 +1,35 @@
+use std::{env, io};
+
+use tendium::protocol::{
+    link::{self, address::MacAddress, ethernet::EthernetPayload, wol::MagicPacket},
+    physical::{raw_socket::RawSocket, Device},
+};
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 || args.len() > 4 {
+        println!("Usage: {} <ifname> <mac address> [password]", args[0]);
+        return Ok(());
+    }
+
+    let mac_addr = args[2].parse::<MacAddress>()?;
+    let packet = match args.get(3) {
+        // the password is written like a MAC address, or as 4 bytes
+        Some(password) => {
+            let password = password
+                .split([':', '-', '.'])
+                .map(|b| u8::from_str_radix(b, 16))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            MagicPacket::with_password(mac_addr, password)?
+        }
+        None => MagicPacket::new(mac_addr),
+    };
+
+    let dev = RawSocket::new(args[1].clone())?;
+    println!("[{}] {}", dev.name(), dev.address()?);
+
+    let mut link_iface = link::Interface::new(Box::new(dev))?;
+    println!("{}", packet);
+    link_iface.send(MacAddress::broadcast(), EthernetPayload::WakeOnLan(packet))
+}
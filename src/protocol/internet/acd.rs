@@ -0,0 +1,71 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::protocol::link::address::MacAddress;
+
+// https://tools.ietf.org/html/rfc5227#section-1.1
+pub const PROBE_WAIT: Duration = Duration::from_secs(1);
+pub const PROBE_NUM: u32 = 3;
+pub const PROBE_MIN: Duration = Duration::from_secs(1);
+pub const PROBE_MAX: Duration = Duration::from_secs(2);
+pub const ANNOUNCE_WAIT: Duration = Duration::from_secs(2);
+pub const ANNOUNCE_NUM: u32 = 2;
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(2);
+pub const DEFEND_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Spreads the probes of hosts which start at the same time. The RFC suggests
+/// seeding the generator with the hardware address.
+#[derive(Debug)]
+pub struct Jitter(u64);
+
+// the time is mixed in after the address so that none of its bits are
+// shifted out
+fn seed(mac_addr: &MacAddress, nanos: u32) -> u64 {
+    let mac_seed = mac_addr.0.iter().fold(0, |acc, &b| (acc << 8) | b as u64);
+    (mac_seed ^ (nanos as u64) << 16) | 1
+}
+
+impl Jitter {
+    pub fn new(mac_addr: &MacAddress) -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        Self(seed(mac_addr, nanos))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        // xorshift64
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Returns a duration in `min..=max`.
+    pub fn between(&mut self, min: Duration, max: Duration) -> Duration {
+        let range = (max - min).as_millis() as u64;
+        min + Duration::from_millis(self.next_u64() % (range + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seed_depends_on_every_bit_of_time() {
+        let mac_addr = MacAddress([0x44, 0xc4, 0xc3, 0xf1, 0x15, 0x5b]);
+        let seeds = (0..30)
+            .map(|bit| seed(&mac_addr, 1 << bit))
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(seeds.len(), 30);
+        assert!(!seeds.contains(&seed(&mac_addr, 0)));
+    }
+
+    #[test]
+    fn seed_depends_on_address() {
+        let a = MacAddress([0x44, 0xc4, 0xc3, 0xf1, 0x15, 0x5b]);
+        let b = MacAddress([0x44, 0xc4, 0xc3, 0xf1, 0x15, 0x5c]);
+        assert_ne!(seed(&a, 12345), seed(&b, 12345));
+    }
+}
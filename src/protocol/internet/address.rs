@@ -1,21 +1,219 @@
 use std::{
     fmt,
+    hash::{Hash, Hasher},
     io::{self, Read},
+    net::Ipv4Addr,
+    str::FromStr,
 };
 
-use byteorder::ReadBytesExt;
-
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, PartialOrd, Ord)]
 pub struct IPAddress(pub [u8; 4]);
 
+/// An address with the length of its network prefix, like `10.0.0.4/24`.
+/// Prefixes are equal if their networks are, whatever their host bits.
+#[derive(Debug, Clone)]
+pub struct Ipv4Cidr {
+    pub addr: IPAddress,
+    pub len: u8,
+}
+
+/// The addresses of an `Ipv4Cidr`, in order.
+#[derive(Debug, Clone)]
+pub struct Iter {
+    next: u64,
+    end: u64,
+}
+
 impl IPAddress {
+    pub const UNSPECIFIED: Self = Self([0; 4]);
+    pub const BROADCAST: Self = Self([255; 4]);
+
     pub fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
         let mut b = [0; 4];
-        for i in 0..4 {
-            b[i] = r.read_u8()?;
-        }
+        r.read_exact(&mut b)?;
         Ok(Self(b))
     }
+
+    pub fn is_unspecified(&self) -> bool {
+        *self == Self::UNSPECIFIED
+    }
+
+    /// 127.0.0.0/8
+    pub fn is_loopback(&self) -> bool {
+        self.0[0] == 127
+    }
+
+    /// 10.0.0.0/8, 172.16.0.0/12 and 192.168.0.0/16, from RFC 1918.
+    pub fn is_private(&self) -> bool {
+        match self.0 {
+            [10, ..] => true,
+            [172, b, ..] => b & 0xf0 == 16,
+            [192, 168, ..] => true,
+            _ => false,
+        }
+    }
+
+    /// 169.254.0.0/16, from RFC 3927.
+    pub fn is_link_local(&self) -> bool {
+        self.0[0] == 169 && self.0[1] == 254
+    }
+
+    /// 224.0.0.0/4
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0xf0 == 0xe0
+    }
+
+    /// The limited broadcast, 255.255.255.255. Whether an address is the
+    /// broadcast of a subnet depends on the subnet, see
+    /// `Ipv4Cidr::broadcast`.
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+}
+
+impl Ipv4Cidr {
+    pub fn new(addr: IPAddress, len: u8) -> io::Result<Self> {
+        if len > 32 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid prefix length: {}", len),
+            ));
+        }
+        Ok(Self { addr, len })
+    }
+
+    pub fn netmask(&self) -> u32 {
+        match self.len {
+            0 => 0,
+            len => !0 << (32 - len),
+        }
+    }
+
+    /// The prefix with the host bits of its address cleared.
+    pub fn network(&self) -> Self {
+        let addr = u32::from_be_bytes(self.addr.0) & self.netmask();
+        Self {
+            addr: IPAddress(addr.to_be_bytes()),
+            len: self.len,
+        }
+    }
+
+    /// The address that reaches every host of the prefix.
+    pub fn broadcast(&self) -> IPAddress {
+        let addr = u32::from_be_bytes(self.addr.0) | !self.netmask();
+        IPAddress(addr.to_be_bytes())
+    }
+
+    pub fn contains(&self, addr: &IPAddress) -> bool {
+        let mask = self.netmask();
+        u32::from_be_bytes(self.addr.0) & mask == u32::from_be_bytes(addr.0) & mask
+    }
+
+    /// Every address of the prefix, from the network to the broadcast.
+    pub fn iter(&self) -> Iter {
+        let start = u32::from_be_bytes(self.network().addr.0) as u64;
+        let end = u32::from_be_bytes(self.broadcast().0) as u64 + 1;
+        Iter { next: start, end }
+    }
+
+    /// The addresses that can be given to hosts, which are all but the
+    /// network and the broadcast, except for /31 and /32 that have no such
+    /// addresses (RFC 3021).
+    pub fn hosts(&self) -> Iter {
+        let mut iter = self.iter();
+        if self.len < 31 {
+            iter.next += 1;
+            iter.end -= 1;
+        }
+        iter
+    }
+}
+
+impl PartialEq for Ipv4Cidr {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.network().addr == other.network().addr
+    }
+}
+
+impl Eq for Ipv4Cidr {}
+
+impl Hash for Ipv4Cidr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.network().addr.hash(state);
+        self.len.hash(state);
+    }
+}
+
+impl Iterator for Iter {
+    type Item = IPAddress;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.end {
+            return None;
+        }
+        let addr = IPAddress((self.next as u32).to_be_bytes());
+        self.next += 1;
+        Some(addr)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = (self.end - self.next) as usize;
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for Iter {}
+
+impl From<Ipv4Addr> for IPAddress {
+    fn from(v: Ipv4Addr) -> Self {
+        Self(v.octets())
+    }
+}
+
+impl From<IPAddress> for Ipv4Addr {
+    fn from(v: IPAddress) -> Self {
+        Ipv4Addr::from(v.0)
+    }
+}
+
+impl FromStr for IPAddress {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<Ipv4Addr>().map(Self::from).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid IP address: {}", s),
+            )
+        })
+    }
+}
+
+/// Reads `a.b.c.d/len`, or a single address as a /32.
+impl FromStr for Ipv4Cidr {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (s, None),
+        };
+        let addr = addr.parse()?;
+        let len = match len {
+            Some(len) => len
+                .parse::<u8>()
+                .ok()
+                .filter(|&len| len <= 32)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid prefix length: {}", s),
+                    )
+                })?,
+            None => 32,
+        };
+        Self::new(addr, len)
+    }
 }
 
 impl fmt::Display for IPAddress {
@@ -31,3 +229,84 @@ impl fmt::Display for IPAddress {
         )
     }
 }
+
+impl fmt::Display for Ipv4Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Ipv4Cidr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_cidrs() {
+        assert_eq!(
+            cidr("10.0.0.4/24"),
+            Ipv4Cidr::new(IPAddress([10, 0, 0, 4]), 24).unwrap()
+        );
+        let e = Ipv4Cidr::new(IPAddress([10, 0, 0, 4]), 33).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(cidr("10.0.0.4").len, 32);
+        assert_eq!(cidr("0.0.0.0/0").len, 0);
+        for s in [
+            "10.0.0.4/33",
+            "10.0.0.4/",
+            "10.0.0.4/x",
+            "10.0.0/8",
+            "/8",
+            "",
+        ] {
+            let e = s.parse::<Ipv4Cidr>().unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput, "{}", s);
+        }
+    }
+
+    #[test]
+    fn prefixes_are_equal_whatever_their_host_bits() {
+        use std::collections::HashSet;
+
+        assert_eq!(cidr("10.0.0.1/24"), cidr("10.0.0.0/24"));
+        assert_ne!(cidr("10.0.0.1/24"), cidr("10.0.0.1/25"));
+        assert_ne!(cidr("10.0.0.1/32"), cidr("10.0.0.2/32"));
+        let set = [cidr("10.0.0.1/24"), cidr("10.0.0.200/24")];
+        assert_eq!(set.iter().collect::<HashSet<_>>().len(), 1);
+        // the host bits are still there
+        assert_eq!(cidr("10.0.0.1/24").to_string(), "10.0.0.1/24");
+    }
+
+    #[test]
+    fn hosts_leave_out_the_network_and_broadcast() {
+        let hosts = cidr("10.0.0.4/30").hosts().collect::<Vec<_>>();
+        assert_eq!(hosts, [IPAddress([10, 0, 0, 5]), IPAddress([10, 0, 0, 6])]);
+        let hosts = cidr("10.0.0.4/31").hosts().collect::<Vec<_>>();
+        assert_eq!(hosts, [IPAddress([10, 0, 0, 4]), IPAddress([10, 0, 0, 5])]);
+        let hosts = cidr("10.0.0.4/32").hosts().collect::<Vec<_>>();
+        assert_eq!(hosts, [IPAddress([10, 0, 0, 4])]);
+
+        // without going through them all
+        let all = cidr("0.0.0.0/0");
+        assert_eq!(all.iter().len(), 1 << 32);
+        assert_eq!(all.broadcast(), IPAddress::BROADCAST);
+        let mut hosts = all.hosts();
+        assert_eq!(hosts.len(), (1 << 32) - 2);
+        assert_eq!(hosts.next(), Some(IPAddress([0, 0, 0, 1])));
+    }
+
+    #[test]
+    fn private_addresses_are_those_of_rfc_1918() {
+        let private = |s: &str| s.parse::<IPAddress>().unwrap().is_private();
+        assert!(!private("172.15.255.255"));
+        assert!(private("172.16.0.0"));
+        assert!(private("172.31.255.255"));
+        assert!(!private("172.32.0.0"));
+        assert!(private("10.255.255.255"));
+        assert!(private("192.168.0.1"));
+        assert!(!private("192.169.0.1"));
+    }
+}
@@ -0,0 +1,61 @@
+// https://tools.ietf.org/html/rfc1071
+
+/// The Internet checksum: the one's complement of the one's complement sum
+/// of the 16-bit words of `data`, padded with a zero byte if odd.
+pub fn checksum(data: &[u8]) -> u16 {
+    !fold(sum(data))
+}
+
+/// Data that carries its own checksum sums to 0xffff, so its checksum is 0.
+pub fn verify(data: &[u8]) -> bool {
+    checksum(data) == 0
+}
+
+/// The checksum after a 16-bit word covered by `checksum` changes from `old`
+/// to `new`, without summing the data again.
+// https://tools.ietf.org/html/rfc1624#section-3 (eqn. 3)
+pub fn update(checksum: u16, old: u16, new: u16) -> u16 {
+    !fold(!checksum as u32 + !old as u32 + new as u32)
+}
+
+fn sum(data: &[u8]) -> u32 {
+    let mut sum = data
+        .chunks_exact(2)
+        .map(|w| u16::from_be_bytes([w[0], w[1]]) as u32)
+        .sum::<u32>();
+    if let Some(&b) = data.chunks_exact(2).remainder().first() {
+        sum += (b as u32) << 8;
+    }
+    sum
+}
+
+fn fold(mut sum: u32) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_matches_recomputing() {
+        let mut data = vec![
+            0x45, 0, 0, 84, 0x12, 0x34, 0x40, 0, 64, 1, 0, 0, 10, 0, 0, 1,
+        ];
+        let sum = checksum(&data);
+        data[10..12].copy_from_slice(&sum.to_be_bytes());
+        for (i, new) in [(8, 0x3f01), (12, 0xc0a8), (14, 0xffff), (2, 0)] {
+            let old = u16::from_be_bytes([data[i], data[i + 1]]);
+            let sum = u16::from_be_bytes([data[10], data[11]]);
+            let updated = update(sum, old, new);
+            data[i..i + 2].copy_from_slice(&new.to_be_bytes());
+            data[10..12].copy_from_slice(&[0, 0]);
+            assert_eq!(updated, checksum(&data));
+            data[10..12].copy_from_slice(&updated.to_be_bytes());
+            assert!(verify(&data));
+        }
+    }
+}
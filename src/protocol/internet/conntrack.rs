@@ -0,0 +1,509 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    io::Cursor,
+    time::{Duration, Instant},
+};
+
+use super::{
+    address::IPAddress,
+    icmp::{IcmpData, IcmpType},
+    ip::{IPDatagram, IPHeader, IPPayload, Protocol},
+};
+
+// the flags of a TCP header that end a connection
+const TCP_FIN: u8 = 0x01;
+const TCP_RST: u8 = 0x04;
+
+/// What tells a flow apart. For ICMP echo both ports are the identifier, and
+/// other protocols have no ports.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Tuple {
+    pub protocol: Protocol,
+    pub src_addr: IPAddress,
+    pub src_port: u16,
+    pub dst_addr: IPAddress,
+    pub dst_port: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// The way the first datagram went.
+    Original,
+    Reply,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Nothing has come back yet.
+    New,
+    Established,
+    /// An ICMP error about a tracked connection.
+    Related,
+}
+
+#[derive(Debug, Clone)]
+pub struct Connection {
+    pub original: Tuple,
+    /// What replies look like, which is not the reverse of `original` once
+    /// the connection is translated.
+    pub reply: Tuple,
+    pub replied: bool,
+    /// TCP FIN or RST was seen.
+    pub closing: bool,
+    last_seen: Instant,
+}
+
+#[derive(Debug, Clone)]
+pub struct Timeouts {
+    pub tcp: Duration,
+    /// For TCP that is unanswered or closing.
+    pub tcp_closing: Duration,
+    pub udp: Duration,
+    /// For UDP that has seen replies.
+    pub udp_stream: Duration,
+    pub icmp: Duration,
+    pub other: Duration,
+    /// How long later fragments are matched to the first one of their
+    /// datagram.
+    pub fragment: Duration,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConntrackStats {
+    /// Connections forgotten to make room within `max_connections`.
+    pub evicted: u64,
+}
+
+/// Follows the flows going through, in both directions.
+#[derive(Debug)]
+pub struct Conntrack {
+    // by the original tuple
+    conns: HashMap<Tuple, Connection>,
+    // the reply tuple to the original one
+    replies: HashMap<Tuple, Tuple>,
+    // the original tuples in the order they are evicted in: unreplied
+    // first, then the idle the longest
+    by_age: BTreeSet<(bool, Instant, Tuple)>,
+    // the tuple of the first fragment of a datagram, for the later ones
+    // that have no ports
+    fragments: HashMap<FragmentKey, (Tuple, Instant)>,
+    last_gc: Instant,
+    stats: ConntrackStats,
+    pub timeouts: Timeouts,
+    /// The connections that may be tracked at once.
+    pub max_connections: usize,
+}
+
+// what the fragments of a datagram have in common
+type FragmentKey = (Protocol, IPAddress, IPAddress, u16);
+
+impl Default for Timeouts {
+    // like the defaults of Linux, except for established TCP
+    fn default() -> Self {
+        Self {
+            tcp: Duration::from_secs(2 * 60 * 60),
+            tcp_closing: Duration::from_secs(120),
+            udp: Duration::from_secs(30),
+            udp_stream: Duration::from_secs(120),
+            icmp: Duration::from_secs(30),
+            other: Duration::from_secs(600),
+            fragment: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Default for Conntrack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tuple {
+    /// The tuple of a datagram. ICMP other than echo has none, and neither
+    /// have fragments but the first, which start in the middle of the data.
+    pub fn of(datagram: &IPDatagram) -> Option<Self> {
+        let header = &datagram.header;
+        if header.offset() != 0 {
+            return None;
+        }
+        let (src_port, dst_port) = match &datagram.payload {
+            IPPayload::Icmp(icmp) => match (&icmp.typ, &icmp.data) {
+                (IcmpType::Echo, IcmpData::Echo { id, .. })
+                | (IcmpType::EchoReply, IcmpData::Echo { id, .. }) => (*id, *id),
+                _ => return None,
+            },
+            IPPayload::Raw(protocol, data) => ports(*protocol, data)?,
+        };
+        Some(Self::new(header, src_port, dst_port))
+    }
+
+    /// The tuple of the datagram quoted by an ICMP error, which is the
+    /// header and the first 8 bytes after it.
+    pub fn of_quote(quote: &[u8]) -> Option<Self> {
+        let mut r = Cursor::new(quote);
+        let header = IPHeader::read_from(&mut r).ok()?;
+        let data = &quote[r.position() as usize..];
+        let (src_port, dst_port) = ports(header.protocol, data)?;
+        Some(Self::new(&header, src_port, dst_port))
+    }
+
+    fn new(header: &IPHeader, src_port: u16, dst_port: u16) -> Self {
+        Self {
+            protocol: header.protocol,
+            src_addr: header.src_addr.clone(),
+            src_port,
+            dst_addr: header.dst_addr.clone(),
+            dst_port,
+        }
+    }
+
+    pub fn reverse(&self) -> Self {
+        Self {
+            protocol: self.protocol,
+            src_addr: self.dst_addr.clone(),
+            src_port: self.dst_port,
+            dst_addr: self.src_addr.clone(),
+            dst_port: self.src_port,
+        }
+    }
+}
+
+// TCP and UDP start with the ports, ICMP echo has its identifier, and other
+// protocols have none
+fn ports(protocol: Protocol, data: &[u8]) -> Option<(u16, u16)> {
+    match protocol {
+        Protocol::Tcp | Protocol::Udp if data.len() < 4 => None,
+        Protocol::Tcp | Protocol::Udp => Some((
+            u16::from_be_bytes([data[0], data[1]]),
+            u16::from_be_bytes([data[2], data[3]]),
+        )),
+        // only echo is tracked
+        Protocol::Icmp => match data.first() {
+            Some(0) | Some(8) if data.len() >= 6 => {
+                let id = u16::from_be_bytes([data[4], data[5]]);
+                Some((id, id))
+            }
+            _ => None,
+        },
+        _ => Some((0, 0)),
+    }
+}
+
+fn fragment_key(header: &IPHeader) -> FragmentKey {
+    (
+        header.protocol,
+        header.src_addr.clone(),
+        header.dst_addr.clone(),
+        header.identification,
+    )
+}
+
+impl Connection {
+    pub fn is_translated(&self) -> bool {
+        self.reply != self.original.reverse()
+    }
+
+    fn state(&self) -> State {
+        if self.replied {
+            State::Established
+        } else {
+            State::New
+        }
+    }
+
+    fn timeout(&self, timeouts: &Timeouts) -> Duration {
+        match self.original.protocol {
+            Protocol::Tcp if self.closing || !self.replied => timeouts.tcp_closing,
+            Protocol::Tcp => timeouts.tcp,
+            Protocol::Udp if self.replied => timeouts.udp_stream,
+            Protocol::Udp => timeouts.udp,
+            Protocol::Icmp => timeouts.icmp,
+            _ => timeouts.other,
+        }
+    }
+}
+
+impl Conntrack {
+    pub fn new() -> Self {
+        Self {
+            conns: HashMap::new(),
+            replies: HashMap::new(),
+            by_age: BTreeSet::new(),
+            fragments: HashMap::new(),
+            last_gc: Instant::now(),
+            stats: ConntrackStats::default(),
+            timeouts: Timeouts::default(),
+            max_connections: 65536,
+        }
+    }
+
+    pub fn stats(&self) -> &ConntrackStats {
+        &self.stats
+    }
+
+    pub fn get(&self, original: &Tuple) -> Option<&Connection> {
+        self.conns.get(original)
+    }
+
+    pub fn connections(&self) -> impl Iterator<Item = &Connection> {
+        self.conns.values()
+    }
+
+    /// The connection a tuple belongs to, and the way it goes.
+    pub fn find(&self, tuple: &Tuple) -> Option<(&Connection, Direction)> {
+        if let Some(conn) = self.conns.get(tuple) {
+            return Some((conn, Direction::Original));
+        }
+        let original = self.replies.get(tuple)?;
+        Some((&self.conns[original], Direction::Reply))
+    }
+
+    /// The connection of the first fragment of the datagram that a later
+    /// fragment belongs to, and the way it goes. `None` until the first
+    /// fragment has been tracked.
+    pub fn fragment(&self, header: &IPHeader) -> Option<(&Connection, Direction)> {
+        if header.offset() == 0 {
+            return None;
+        }
+        let (tuple, _) = self.fragments.get(&fragment_key(header))?;
+        self.find(tuple)
+    }
+
+    /// Records a datagram, starting a connection if it belongs to none.
+    /// Returns the original tuple of the connection, the way the datagram
+    /// goes and the state of the connection. `None` for ICMP errors and
+    /// what has no tuple. Later fragments go by their first fragment.
+    pub fn track(&mut self, datagram: &IPDatagram) -> Option<(Tuple, Direction, State)> {
+        let header = &datagram.header;
+        if header.offset() != 0 {
+            let (conn, direction) = self.fragment(header)?;
+            return Some((conn.original.clone(), direction, conn.state()));
+        }
+        let tuple = Tuple::of(datagram)?;
+        if header.more_fragments() && self.fragments.len() < self.max_connections {
+            let now = Instant::now();
+            self.fragments
+                .insert(fragment_key(header), (tuple.clone(), now));
+        }
+        let fin = match (&datagram.payload, tuple.protocol) {
+            (IPPayload::Raw(_, data), Protocol::Tcp) => data
+                .get(13)
+                .is_some_and(|flags| flags & (TCP_FIN | TCP_RST) != 0),
+            _ => false,
+        };
+        let now = Instant::now();
+
+        let (original, direction) = match self.find(&tuple) {
+            Some((conn, direction)) => (conn.original.clone(), direction),
+            None => {
+                self.reserve();
+                let conn = Connection {
+                    original: tuple.clone(),
+                    reply: tuple.reverse(),
+                    replied: false,
+                    closing: false,
+                    last_seen: now,
+                };
+                // a reply tuple that is taken is left to the older one
+                self.replies
+                    .entry(conn.reply.clone())
+                    .or_insert_with(|| tuple.clone());
+                self.conns.insert(tuple.clone(), conn);
+                (tuple, Direction::Original)
+            }
+        };
+
+        let conn = self.conns.get_mut(&original).unwrap();
+        self.by_age
+            .remove(&(conn.replied, conn.last_seen, original.clone()));
+        conn.last_seen = now;
+        conn.closing |= fin;
+        if direction == Direction::Reply {
+            conn.replied = true;
+        }
+        self.by_age
+            .insert((conn.replied, conn.last_seen, original.clone()));
+        let state = conn.state();
+        Some((original, direction, state))
+    }
+
+    /// The connection an ICMP error is about, and the way the error goes,
+    /// which is opposite to the quoted datagram.
+    pub fn related(&self, datagram: &IPDatagram) -> Option<(&Connection, Direction)> {
+        let icmp = match &datagram.payload {
+            IPPayload::Icmp(icmp) if icmp.is_error() => icmp,
+            _ => return None,
+        };
+        let quoted = Tuple::of_quote(icmp.original()?)?;
+        let (conn, direction) = self.find(&quoted.reverse())?;
+        Some((conn, direction))
+    }
+
+    /// Whether no connection has replies that look like `reply`.
+    pub fn is_free(&self, reply: &Tuple) -> bool {
+        !self.replies.contains_key(reply)
+    }
+
+    /// Makes the replies of a connection look like `reply`, e.g. for NAT.
+    /// Returns false if another connection has that reply tuple.
+    pub fn set_reply(&mut self, original: &Tuple, reply: Tuple) -> bool {
+        if self.replies.get(&reply).is_some_and(|o| o != original) {
+            return false;
+        }
+        let conn = match self.conns.get_mut(original) {
+            Some(conn) => conn,
+            None => return false,
+        };
+        if self.replies.get(&conn.reply) == Some(original) {
+            self.replies.remove(&conn.reply);
+        }
+        conn.reply = reply.clone();
+        self.replies.insert(reply, original.clone());
+        true
+    }
+
+    /// Forgets the connections that have been idle for too long. This runs
+    /// at most once a second.
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        if now - self.last_gc < Duration::from_secs(1) {
+            return;
+        }
+        self.last_gc = now;
+
+        let timeouts = &self.timeouts;
+        let expired = self
+            .conns
+            .values()
+            .filter(|conn| now - conn.last_seen >= conn.timeout(timeouts))
+            .map(|conn| conn.original.clone())
+            .collect::<Vec<_>>();
+        for original in expired {
+            self.remove(&original);
+        }
+        let timeout = self.timeouts.fragment;
+        self.fragments
+            .retain(|_, (_, first_seen)| now - *first_seen < timeout);
+    }
+
+    // makes room for a new connection by dropping one that has not been
+    // replied to, else the one idle the longest
+    fn reserve(&mut self) {
+        while self.conns.len() >= self.max_connections {
+            let victim = match self.by_age.first() {
+                Some((_, _, original)) => original.clone(),
+                None => break,
+            };
+            self.remove(&victim);
+            self.stats.evicted += 1;
+        }
+    }
+
+    fn remove(&mut self, original: &Tuple) {
+        let conn = self.conns.remove(original).unwrap();
+        self.by_age
+            .remove(&(conn.replied, conn.last_seen, original.clone()));
+        if self.replies.get(&conn.reply) == Some(original) {
+            self.replies.remove(&conn.reply);
+        }
+    }
+}
+
+impl fmt::Display for Tuple {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}:{} -> {}:{}",
+            self.protocol, self.src_addr, self.src_port, self.dst_addr, self.dst_port
+        )
+    }
+}
+
+impl fmt::Display for Conntrack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Conntrack:")?;
+        for conn in self.conns.values() {
+            write!(f, "\n  {}", conn.original)?;
+            if conn.is_translated() {
+                write!(f, " (reply {})", conn.reply)?;
+            }
+            if !conn.replied {
+                write!(f, " [unreplied]")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn udp(src: ([u8; 4], u16), dst: ([u8; 4], u16)) -> IPDatagram {
+        let mut data = vec![0; 8];
+        data[0..2].copy_from_slice(&src.1.to_be_bytes());
+        data[2..4].copy_from_slice(&dst.1.to_be_bytes());
+        IPDatagram {
+            header: IPHeader {
+                version_ihl: 0x45,
+                tos: 0,
+                length: 28,
+                identification: 1,
+                flags_offset: 0,
+                ttl: 64,
+                protocol: Protocol::Udp,
+                checksum: 0,
+                src_addr: IPAddress(src.0),
+                dst_addr: IPAddress(dst.0),
+                options: Vec::new(),
+                raw: Vec::new(),
+            },
+            payload: IPPayload::Raw(Protocol::Udp, data),
+        }
+    }
+
+    #[test]
+    fn unreplied_connections_are_evicted_first() {
+        let mut conntrack = Conntrack::new();
+        conntrack.max_connections = 2;
+        let (client, server) = ([10, 0, 0, 1], ([192, 0, 2, 1], 53));
+
+        let first = conntrack.track(&udp((client, 1000), server)).unwrap().0;
+        conntrack.track(&udp(server, (client, 1000))).unwrap();
+        let second = conntrack.track(&udp((client, 1001), server)).unwrap().0;
+        let third = conntrack.track(&udp((client, 1002), server)).unwrap().0;
+
+        assert_eq!(conntrack.connections().count(), 2);
+        assert_eq!(conntrack.stats().evicted, 1);
+        assert!(conntrack.get(&first).is_some());
+        assert!(conntrack.get(&second).is_none());
+        assert!(conntrack.get(&third).is_some());
+        assert!(conntrack.is_free(&second.reverse()));
+    }
+
+    #[test]
+    fn the_connection_idle_the_longest_goes_when_all_were_replied() {
+        let mut conntrack = Conntrack::new();
+        conntrack.max_connections = 2;
+        let server = ([192, 0, 2, 1], 53);
+        let clients = [([10, 0, 0, 1], 1000), ([10, 0, 0, 2], 1000)];
+
+        for client in clients {
+            conntrack.track(&udp(client, server)).unwrap();
+            conntrack.track(&udp(server, client)).unwrap();
+        }
+        // the first one is seen again, which leaves the second idle
+        std::thread::sleep(Duration::from_millis(1));
+        let first = conntrack.track(&udp(clients[0], server)).unwrap().0;
+        let third = conntrack
+            .track(&udp(([10, 0, 0, 3], 1000), server))
+            .unwrap()
+            .0;
+
+        assert_eq!(conntrack.stats().evicted, 1);
+        assert!(conntrack.get(&first).is_some());
+        assert!(conntrack.get(&third).is_some());
+        assert_eq!(conntrack.by_age.len(), 2);
+    }
+}
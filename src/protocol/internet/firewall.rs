@@ -0,0 +1,644 @@
+use std::{
+    fmt,
+    io::{self, BufRead},
+    ops::RangeInclusive,
+};
+
+use super::{
+    address::Ipv4Cidr,
+    checksum,
+    conntrack::{State, Tuple},
+    ip::{IPDatagram, IPHeader, IPPayload, Protocol, FLAG_DONT_FRAGMENT},
+};
+
+// the flags of a TCP header
+const TCP_RST: u8 = 0x04;
+const TCP_SYN: u8 = 0x02;
+const TCP_FIN: u8 = 0x01;
+const TCP_ACK: u8 = 0x10;
+
+// what a rule may have after its chain and action, each with a value
+const CONDITIONS: [&str; 9] = [
+    "in", "out", "from", "to", "proto", "sport", "dport", "type", "state",
+];
+
+/// Where a datagram is filtered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chain {
+    /// Datagrams for us.
+    Input,
+    Forward,
+    /// Datagrams we send.
+    Output,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Accept,
+    Drop,
+    /// Drops the datagram and tells the sender, with TCP RST for TCP and
+    /// ICMP port unreachable otherwise.
+    Reject,
+}
+
+/// A rule matches the datagrams that meet all of its conditions, and
+/// unspecified conditions meet any.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub chain: Chain,
+    pub action: Action,
+    pub in_iface: Option<String>,
+    pub out_iface: Option<String>,
+    pub src: Option<Ipv4Cidr>,
+    pub dst: Option<Ipv4Cidr>,
+    pub protocol: Option<Protocol>,
+    pub src_ports: Option<RangeInclusive<u16>>,
+    pub dst_ports: Option<RangeInclusive<u16>>,
+    pub icmp_type: Option<u8>,
+    /// Any of these.
+    pub states: Vec<State>,
+}
+
+/// What is known of a datagram where it is filtered.
+#[derive(Debug)]
+pub struct Packet<'a> {
+    pub datagram: &'a IPDatagram,
+    pub in_iface: Option<&'a str>,
+    pub out_iface: Option<&'a str>,
+    /// Untracked datagrams have none. Fragments but the first have the
+    /// state of the first, once it has been seen.
+    pub state: Option<State>,
+}
+
+/// Filters datagrams by the first rule of the chain that matches them, or
+/// by the policy of the chain if none does.
+///
+/// Rules can be written one per line, like:
+///
+/// ```text
+/// policy input drop
+/// input accept state established,related
+/// input accept in tap0 proto icmp type 8
+/// forward reject from 10.0.0.0/24 proto tcp dport 6000-6063
+/// ```
+#[derive(Debug, Clone)]
+pub struct Firewall {
+    rules: Vec<Rule>,
+    input: Action,
+    forward: Action,
+    output: Action,
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn parse_cidr(s: &str) -> io::Result<Ipv4Cidr> {
+    s.parse()
+        .map_err(|e: io::Error| invalid_data(e.to_string()))
+}
+
+fn parse_number<T: std::str::FromStr>(s: &str) -> io::Result<T> {
+    s.parse()
+        .map_err(|_| invalid_data(format!("invalid number: {}", s)))
+}
+
+fn parse_ports(s: &str) -> io::Result<RangeInclusive<u16>> {
+    match s.split_once('-') {
+        Some((start, end)) => {
+            let ports = parse_number(start)?..=parse_number(end)?;
+            if ports.is_empty() {
+                return Err(invalid_data(format!("invalid port range: {}", s)));
+            }
+            Ok(ports)
+        }
+        None => {
+            let port = parse_number(s)?;
+            Ok(port..=port)
+        }
+    }
+}
+
+fn parse_protocol(s: &str) -> io::Result<Protocol> {
+    match s {
+        "icmp" => Ok(Protocol::Icmp),
+        "tcp" => Ok(Protocol::Tcp),
+        "udp" => Ok(Protocol::Udp),
+        _ => parse_number::<u8>(s).map(Protocol::from),
+    }
+}
+
+fn parse_chain(s: &str) -> io::Result<Chain> {
+    match s {
+        "input" => Ok(Chain::Input),
+        "forward" => Ok(Chain::Forward),
+        "output" => Ok(Chain::Output),
+        _ => Err(invalid_data(format!("invalid chain: {}", s))),
+    }
+}
+
+fn parse_action(s: &str) -> io::Result<Action> {
+    match s {
+        "accept" => Ok(Action::Accept),
+        "drop" => Ok(Action::Drop),
+        "reject" => Ok(Action::Reject),
+        _ => Err(invalid_data(format!("invalid action: {}", s))),
+    }
+}
+
+fn parse_state(s: &str) -> io::Result<State> {
+    match s {
+        "new" => Ok(State::New),
+        "established" => Ok(State::Established),
+        "related" => Ok(State::Related),
+        _ => Err(invalid_data(format!("invalid state: {}", s))),
+    }
+}
+
+impl Rule {
+    pub fn new(chain: Chain, action: Action) -> Self {
+        Self {
+            chain,
+            action,
+            in_iface: None,
+            out_iface: None,
+            src: None,
+            dst: None,
+            protocol: None,
+            src_ports: None,
+            dst_ports: None,
+            icmp_type: None,
+            states: Vec::new(),
+        }
+    }
+
+    /// Parses a rule in the format of `Firewall`, without the comments.
+    pub fn parse(s: &str) -> io::Result<Self> {
+        let mut words = s.split_whitespace();
+        let mut next = |what: &str| {
+            words
+                .next()
+                .ok_or_else(|| invalid_data(format!("missing {}: {}", what, s)))
+        };
+        let chain = parse_chain(next("chain")?)?;
+        let mut rule = Self::new(chain, parse_action(next("action")?)?);
+
+        while let Ok(key) = next("condition") {
+            if !CONDITIONS.contains(&key) {
+                return Err(invalid_data(format!("invalid condition: {}", key)));
+            }
+            // a condition is not the value of the one before
+            let value = match next(key)? {
+                value if CONDITIONS.contains(&value) => {
+                    return Err(invalid_data(format!("missing {}: {}", key, s)))
+                }
+                value => value,
+            };
+            match key {
+                "in" => rule.in_iface = Some(value.to_string()),
+                "out" => rule.out_iface = Some(value.to_string()),
+                "from" => rule.src = Some(parse_cidr(value)?),
+                "to" => rule.dst = Some(parse_cidr(value)?),
+                "proto" => rule.protocol = Some(parse_protocol(value)?),
+                "sport" => rule.src_ports = Some(parse_ports(value)?),
+                "dport" => rule.dst_ports = Some(parse_ports(value)?),
+                "type" => rule.icmp_type = Some(parse_number(value)?),
+                "state" => {
+                    rule.states = value
+                        .split(',')
+                        .map(parse_state)
+                        .collect::<io::Result<_>>()?
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        let has_ports = rule.src_ports.is_some() || rule.dst_ports.is_some();
+        if has_ports && !matches!(rule.protocol, Some(Protocol::Tcp) | Some(Protocol::Udp)) {
+            return Err(invalid_data(format!("ports need proto tcp or udp: {}", s)));
+        }
+        if rule.icmp_type.is_some() && rule.protocol != Some(Protocol::Icmp) {
+            return Err(invalid_data(format!("type needs proto icmp: {}", s)));
+        }
+        Ok(rule)
+    }
+
+    pub fn matches(&self, packet: &Packet) -> bool {
+        let header = &packet.datagram.header;
+        let iface_matches = |iface: &Option<String>, name: Option<&str>| {
+            iface.as_deref().is_none_or(|iface| Some(iface) == name)
+        };
+        if !iface_matches(&self.in_iface, packet.in_iface)
+            || !iface_matches(&self.out_iface, packet.out_iface)
+            || self
+                .src
+                .as_ref()
+                .is_some_and(|p| !p.contains(&header.src_addr))
+            || self
+                .dst
+                .as_ref()
+                .is_some_and(|p| !p.contains(&header.dst_addr))
+            || self
+                .protocol
+                .is_some_and(|protocol| protocol != header.protocol)
+        {
+            return false;
+        }
+        if !self.states.is_empty() && !packet.state.is_some_and(|s| self.states.contains(&s)) {
+            return false;
+        }
+
+        if self.src_ports.is_some() || self.dst_ports.is_some() {
+            // fragments but the first have no ports
+            let tuple = match Tuple::of(packet.datagram) {
+                Some(tuple) => tuple,
+                None => return false,
+            };
+            let port_matches = |ports: &Option<RangeInclusive<u16>>, port| {
+                ports.as_ref().is_none_or(|ports| ports.contains(&port))
+            };
+            if !port_matches(&self.src_ports, tuple.src_port)
+                || !port_matches(&self.dst_ports, tuple.dst_port)
+            {
+                return false;
+            }
+        }
+        if let Some(typ) = self.icmp_type {
+            match &packet.datagram.payload {
+                IPPayload::Icmp(icmp) if u8::from(icmp.typ) == typ => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+impl Default for Firewall {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Firewall {
+    /// A firewall that accepts everything.
+    pub fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+            input: Action::Accept,
+            forward: Action::Accept,
+            output: Action::Accept,
+        }
+    }
+
+    /// Reads rules, one per line. Empty lines and what follows `#` are
+    /// ignored.
+    pub fn read_from<R: BufRead>(r: R) -> io::Result<Self> {
+        let mut firewall = Self::new();
+        for (i, line) in r.lines().enumerate() {
+            let line = line?;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            firewall
+                .load_line(line)
+                .map_err(|e| invalid_data(format!("line {}: {}", i + 1, e)))?;
+        }
+        Ok(firewall)
+    }
+
+    fn load_line(&mut self, line: &str) -> io::Result<()> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        if words[0] != "policy" {
+            self.add_rule(Rule::parse(line)?);
+            return Ok(());
+        }
+        if words.len() != 3 {
+            return Err(invalid_data(format!("invalid policy: {}", line)));
+        }
+        self.set_policy(parse_chain(words[1])?, parse_action(words[2])?);
+        Ok(())
+    }
+
+    pub fn add_rule(&mut self, rule: Rule) {
+        self.rules.push(rule);
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Removes the rules, and accepts everything again.
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn policy(&self, chain: Chain) -> Action {
+        match chain {
+            Chain::Input => self.input,
+            Chain::Forward => self.forward,
+            Chain::Output => self.output,
+        }
+    }
+
+    pub fn set_policy(&mut self, chain: Chain, action: Action) {
+        match chain {
+            Chain::Input => self.input = action,
+            Chain::Forward => self.forward = action,
+            Chain::Output => self.output = action,
+        }
+    }
+
+    /// Whether anything can be filtered out.
+    pub fn is_enabled(&self) -> bool {
+        !self.rules.is_empty()
+            || [self.input, self.forward, self.output]
+                .iter()
+                .any(|&action| action != Action::Accept)
+    }
+
+    pub fn filter(&self, chain: Chain, packet: &Packet) -> Action {
+        self.rules
+            .iter()
+            .find(|rule| rule.chain == chain && rule.matches(packet))
+            .map_or(self.policy(chain), |rule| rule.action)
+    }
+}
+
+/// The TCP RST that answers a segment, sent from where it was going. `None`
+/// for what is not TCP, and for a RST.
+// https://tools.ietf.org/html/rfc793#section-3.4 "Reset Generation"
+pub fn tcp_reset(datagram: &IPDatagram) -> Option<IPDatagram> {
+    let header = &datagram.header;
+    let segment = match &datagram.payload {
+        IPPayload::Raw(Protocol::Tcp, data) if data.len() >= 20 => data,
+        _ => return None,
+    };
+    let flags = segment[13];
+    if flags & TCP_RST != 0 || header.offset() != 0 {
+        return None;
+    }
+
+    let seq = u32::from_be_bytes([segment[4], segment[5], segment[6], segment[7]]);
+    let ack = u32::from_be_bytes([segment[8], segment[9], segment[10], segment[11]]);
+    let (seq, ack, flags) = if flags & TCP_ACK != 0 {
+        (ack, 0, TCP_RST)
+    } else {
+        // SYN and FIN take a sequence number each
+        let data_offset = (segment[12] >> 4) as usize * 4;
+        let mut len = segment.len().saturating_sub(data_offset) as u32;
+        len += (flags & TCP_SYN != 0) as u32 + (flags & TCP_FIN != 0) as u32;
+        (0, seq.wrapping_add(len), TCP_RST | TCP_ACK)
+    };
+
+    let mut rst = Vec::with_capacity(20);
+    rst.extend_from_slice(&segment[2..4]);
+    rst.extend_from_slice(&segment[0..2]);
+    rst.extend_from_slice(&seq.to_be_bytes());
+    rst.extend_from_slice(&ack.to_be_bytes());
+    rst.extend_from_slice(&[5 << 4, flags, 0, 0, 0, 0, 0, 0]);
+
+    let mut pseudo = Vec::with_capacity(12 + rst.len());
+    pseudo.extend_from_slice(&header.dst_addr.0);
+    pseudo.extend_from_slice(&header.src_addr.0);
+    pseudo.extend_from_slice(&[0, Protocol::Tcp.into()]);
+    pseudo.extend_from_slice(&(rst.len() as u16).to_be_bytes());
+    pseudo.extend_from_slice(&rst);
+    let sum = checksum::checksum(&pseudo);
+    rst[16..18].copy_from_slice(&sum.to_be_bytes());
+
+    Some(IPDatagram {
+        header: IPHeader {
+            version_ihl: (4 << 4) | 5,
+            tos: 0,
+            length: 40,
+            identification: 0,
+            flags_offset: FLAG_DONT_FRAGMENT,
+            ttl: 64,
+            protocol: Protocol::Tcp,
+            checksum: 0,
+            src_addr: header.dst_addr.clone(),
+            dst_addr: header.src_addr.clone(),
+            options: Vec::new(),
+            raw: Vec::new(),
+        },
+        payload: IPPayload::Raw(Protocol::Tcp, rst),
+    })
+}
+
+impl fmt::Display for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chain::Input => write!(f, "input"),
+            Chain::Forward => write!(f, "forward"),
+            Chain::Output => write!(f, "output"),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Accept => write!(f, "accept"),
+            Action::Drop => write!(f, "drop"),
+            Action::Reject => write!(f, "reject"),
+        }
+    }
+}
+
+/// In the format that `Rule::parse` reads.
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.chain, self.action)?;
+        if let Some(iface) = &self.in_iface {
+            write!(f, " in {}", iface)?;
+        }
+        if let Some(iface) = &self.out_iface {
+            write!(f, " out {}", iface)?;
+        }
+        if let Some(src) = &self.src {
+            write!(f, " from {}", src)?;
+        }
+        if let Some(dst) = &self.dst {
+            write!(f, " to {}", dst)?;
+        }
+        match self.protocol {
+            Some(Protocol::Icmp) => write!(f, " proto icmp")?,
+            Some(Protocol::Tcp) => write!(f, " proto tcp")?,
+            Some(Protocol::Udp) => write!(f, " proto udp")?,
+            Some(Protocol::Unknown(x)) => write!(f, " proto {}", x)?,
+            None => {}
+        }
+        for (key, ports) in [("sport", &self.src_ports), ("dport", &self.dst_ports)] {
+            match ports {
+                Some(ports) if ports.start() == ports.end() => {
+                    write!(f, " {} {}", key, ports.start())?
+                }
+                Some(ports) => write!(f, " {} {}-{}", key, ports.start(), ports.end())?,
+                None => {}
+            }
+        }
+        if let Some(typ) = self.icmp_type {
+            write!(f, " type {}", typ)?;
+        }
+        if !self.states.is_empty() {
+            let states = self
+                .states
+                .iter()
+                .map(|state| match state {
+                    State::New => "new",
+                    State::Established => "established",
+                    State::Related => "related",
+                })
+                .collect::<Vec<_>>();
+            write!(f, " state {}", states.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Firewall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Firewall:")?;
+        for chain in [Chain::Input, Chain::Forward, Chain::Output] {
+            write!(f, "\n  policy {} {}", chain, self.policy(chain))?;
+        }
+        for rule in &self.rules {
+            write!(f, "\n  {}", rule)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::protocol::internet::address::IPAddress;
+
+    fn error(rule: &str) -> String {
+        Rule::parse(rule).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parse_reads_every_condition() {
+        let s = "forward reject in tap0 out tap1 from 10.0.0.0/24 to 10.1.0.0/16 \
+                 proto tcp sport 1024-65535 dport 80 state new,established";
+        let rule = Rule::parse(s).unwrap();
+        assert_eq!(rule.chain, Chain::Forward);
+        assert_eq!(rule.action, Action::Reject);
+        assert_eq!(rule.in_iface.as_deref(), Some("tap0"));
+        assert_eq!(rule.src_ports, Some(1024..=65535));
+        assert_eq!(rule.dst_ports, Some(80..=80));
+        assert_eq!(rule.states, vec![State::New, State::Established]);
+        assert_eq!(
+            rule.to_string(),
+            s.split_whitespace().collect::<Vec<_>>().join(" ")
+        );
+    }
+
+    #[test]
+    fn parse_rejects_what_does_not_make_sense() {
+        assert_eq!(
+            error("forward accept proto tcp dport 6063-6000"),
+            "invalid port range: 6063-6000"
+        );
+        assert_eq!(
+            error("forward accept in out tap1"),
+            "missing in: forward accept in out tap1"
+        );
+        assert_eq!(error("input drop proto"), "missing proto: input drop proto");
+        assert_eq!(error("input drop via tap0"), "invalid condition: via");
+        assert_eq!(
+            error("input drop dport 80"),
+            "ports need proto tcp or udp: input drop dport 80"
+        );
+        assert_eq!(
+            error("input drop proto udp type 8"),
+            "type needs proto icmp: input drop proto udp type 8"
+        );
+        assert_eq!(error("input"), "missing action: input");
+    }
+
+    #[test]
+    fn read_from_skips_comments_and_counts_lines() {
+        let rules = "# rules\n\npolicy input drop\ninput accept proto icmp  # ping\n";
+        let firewall = Firewall::read_from(Cursor::new(rules)).unwrap();
+        assert_eq!(firewall.policy(Chain::Input), Action::Drop);
+        assert_eq!(firewall.policy(Chain::Forward), Action::Accept);
+        assert_eq!(firewall.rules().len(), 1);
+        assert_eq!(firewall.rules()[0].to_string(), "input accept proto icmp");
+
+        let e = Firewall::read_from(Cursor::new("policy input drop\n\npolicy input\n"));
+        assert_eq!(
+            e.unwrap_err().to_string(),
+            "line 3: invalid policy: policy input"
+        );
+    }
+
+    // a TCP segment from 10.0.0.1:1234 to 10.0.0.2:80
+    fn segment(flags: u8, seq: u32, ack: u32, data: &[u8]) -> IPDatagram {
+        let mut segment = vec![0x04, 0xd2, 0, 80];
+        segment.extend_from_slice(&seq.to_be_bytes());
+        segment.extend_from_slice(&ack.to_be_bytes());
+        segment.extend_from_slice(&[5 << 4, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        segment.extend_from_slice(data);
+        IPDatagram {
+            header: IPHeader {
+                version_ihl: 0x45,
+                tos: 0,
+                length: 20 + segment.len() as u16,
+                identification: 1,
+                flags_offset: 0,
+                ttl: 64,
+                protocol: Protocol::Tcp,
+                checksum: 0,
+                src_addr: IPAddress([10, 0, 0, 1]),
+                dst_addr: IPAddress([10, 0, 0, 2]),
+                options: Vec::new(),
+                raw: Vec::new(),
+            },
+            payload: IPPayload::Raw(Protocol::Tcp, segment),
+        }
+    }
+
+    // the sequence number, acknowledgment number and flags of a reset
+    fn reset(datagram: &IPDatagram) -> (u32, u32, u8) {
+        let rst = tcp_reset(datagram).unwrap();
+        assert_eq!(rst.header.src_addr, IPAddress([10, 0, 0, 2]));
+        assert_eq!(rst.header.dst_addr, IPAddress([10, 0, 0, 1]));
+        let data = match &rst.payload {
+            IPPayload::Raw(_, data) => data,
+            _ => unreachable!(),
+        };
+        assert_eq!(data[0..4], [0, 80, 0x04, 0xd2]);
+
+        let mut pseudo = vec![10, 0, 0, 2, 10, 0, 0, 1, 0, 6, 0, 20];
+        pseudo.extend_from_slice(data);
+        assert!(checksum::verify(&pseudo));
+        let seq = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        let ack = u32::from_be_bytes([data[8], data[9], data[10], data[11]]);
+        (seq, ack, data[13])
+    }
+
+    #[test]
+    fn tcp_reset_answers_an_ack_with_its_number() {
+        let datagram = segment(TCP_ACK, 100, 5000, b"data");
+        assert_eq!(reset(&datagram), (5000, 0, TCP_RST));
+    }
+
+    #[test]
+    fn tcp_reset_acknowledges_what_came_without_an_ack() {
+        let syn = segment(TCP_SYN, u32::MAX, 0, &[]);
+        assert_eq!(reset(&syn), (0, 0, TCP_RST | TCP_ACK));
+        let fin = segment(TCP_FIN, 100, 0, b"data");
+        assert_eq!(reset(&fin), (0, 105, TCP_RST | TCP_ACK));
+    }
+
+    #[test]
+    fn tcp_reset_does_not_answer_a_reset() {
+        assert!(tcp_reset(&segment(TCP_RST, 100, 0, &[])).is_none());
+        assert!(tcp_reset(&segment(TCP_RST | TCP_ACK, 100, 5000, &[])).is_none());
+    }
+}
@@ -0,0 +1,175 @@
+use std::io;
+
+use super::ip::{IPDatagram, IPHeader, IPPayload, FLAG_MORE_FRAGMENTS};
+
+/// Whether the datagram is larger than `mtu`.
+pub fn is_oversized(datagram: &IPDatagram, mtu: usize) -> io::Result<bool> {
+    Ok(datagram.header.header_len()? + datagram.payload.encoded_len() > mtu)
+}
+
+/// Splits the datagram into fragments of at most `mtu` bytes. Only the first
+/// fragment keeps all the options; the rest carry the copied ones.
+// https://tools.ietf.org/html/rfc791#section-3.2 "Fragmentation"
+pub fn fragment(datagram: IPDatagram, mtu: usize) -> io::Result<Vec<IPDatagram>> {
+    if !is_oversized(&datagram, mtu)? {
+        return Ok(vec![datagram]);
+    }
+    if datagram.header.dont_fragment() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("datagram needs fragmentation for MTU {} but DF is set", mtu),
+        ));
+    }
+
+    let IPDatagram { header, payload } = datagram;
+    let mut data = Vec::new();
+    payload.write_to(&mut data)?;
+
+    let mut rest = header.clone();
+    rest.options.retain(|option| option.is_copied());
+
+    let mut fragments = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let mut header = if offset == 0 {
+            header.clone()
+        } else {
+            rest.clone()
+        };
+        // fragment data is counted in units of 8 bytes
+        let max = mtu.saturating_sub(header.header_len()?) & !7;
+        if max == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("MTU {} is too small to fragment", mtu),
+            ));
+        }
+        let end = data.len().min(offset + max);
+
+        // a fragment of a fragment is only the last if the original was
+        let last = end == data.len() && !header.more_fragments();
+        let flags = header.flags_offset & !0x1fff & !FLAG_MORE_FRAGMENTS;
+        let offset_units = header.offset() as usize + offset / 8;
+        header.flags_offset = flags | offset_units as u16;
+        if !last {
+            header.flags_offset |= FLAG_MORE_FRAGMENTS;
+        }
+
+        fragments.push(IPDatagram {
+            header: IPHeader {
+                length: 0,
+                checksum: 0,
+                raw: Vec::new(),
+                ..header
+            },
+            payload: IPPayload::Raw(header.protocol, data[offset..end].to_vec()),
+        });
+        offset = end;
+    }
+    Ok(fragments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::internet::{
+        address::IPAddress,
+        ip::{IPOption, Protocol, FLAG_DONT_FRAGMENT},
+    };
+
+    fn datagram(len: usize, options: Vec<IPOption>) -> IPDatagram {
+        IPDatagram {
+            header: IPHeader {
+                version_ihl: 0x45,
+                tos: 0,
+                length: 0,
+                identification: 7,
+                flags_offset: 0,
+                ttl: 64,
+                protocol: Protocol::Udp,
+                checksum: 0,
+                src_addr: IPAddress([10, 0, 0, 1]),
+                dst_addr: IPAddress([10, 0, 0, 2]),
+                options,
+                raw: Vec::new(),
+            },
+            payload: IPPayload::Raw(Protocol::Udp, (0..len).map(|x| x as u8).collect()),
+        }
+    }
+
+    fn data(fragment: &IPDatagram) -> &[u8] {
+        match &fragment.payload {
+            IPPayload::Raw(_, data) => data,
+            payload => panic!("{:?}", payload),
+        }
+    }
+
+    #[test]
+    fn fragments_are_offset_in_units_of_8_bytes() {
+        let fragments = fragment(datagram(100, Vec::new()), 63).unwrap();
+        let layout = fragments
+            .iter()
+            .map(|f| (f.header.offset(), data(f).len(), f.header.more_fragments()))
+            .collect::<Vec<_>>();
+        assert_eq!(layout, [(0, 40, true), (5, 40, true), (10, 20, false)]);
+
+        let joined = fragments.iter().flat_map(|f| data(f).to_vec());
+        assert!(joined.eq((0..100).map(|x| x as u8)));
+        for f in &fragments {
+            assert_eq!(f.header.identification, 7);
+            assert_eq!(f.payload.protocol(), Protocol::Udp);
+        }
+    }
+
+    #[test]
+    fn fragments_of_a_fragment_keep_its_offset_and_more_fragments() {
+        let mut original = datagram(32, Vec::new());
+        original.header.flags_offset = FLAG_MORE_FRAGMENTS | 4;
+        let fragments = fragment(original, 36).unwrap();
+        let layout = fragments
+            .iter()
+            .map(|f| (f.header.offset(), f.header.more_fragments()))
+            .collect::<Vec<_>>();
+        assert_eq!(layout, [(4, true), (6, true)]);
+    }
+
+    #[test]
+    fn dont_fragment_is_an_error() {
+        let mut original = datagram(100, Vec::new());
+        original.header.flags_offset = FLAG_DONT_FRAGMENT;
+        let err = fragment(original, 60).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn only_copied_options_go_past_the_first_fragment() {
+        let source_route = IPOption::LooseSourceRoute {
+            pointer: 4,
+            route: vec![IPAddress([10, 0, 0, 3])],
+        };
+        let record_route = IPOption::RecordRoute {
+            pointer: 4,
+            route: vec![IPAddress([0; 4])],
+        };
+        let options = vec![source_route.clone(), record_route.clone()];
+        let fragments = fragment(datagram(64, options), 60).unwrap();
+        assert_eq!(
+            fragments[0].header.options,
+            [source_route.clone(), record_route]
+        );
+        for f in &fragments[1..] {
+            assert_eq!(f.header.options, std::slice::from_ref(&source_route));
+        }
+        for f in &fragments {
+            assert!(!is_oversized(f, 60).unwrap());
+        }
+    }
+
+    #[test]
+    fn only_what_exceeds_the_mtu_is_fragmented() {
+        assert_eq!(fragment(datagram(80, Vec::new()), 100).unwrap().len(), 1);
+        assert_eq!(fragment(datagram(81, Vec::new()), 100).unwrap().len(), 2);
+        let err = fragment(datagram(81, Vec::new()), 27).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -1,10 +1,12 @@
 use std::{
     fmt,
-    io::{self, Read, Write},
+    io::{self, Cursor, Read, Write},
 };
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
+use super::{address::IPAddress, checksum, ip::IPDatagram};
+
 // https://www.iana.org/assignments/icmp-parameters/icmp-parameters.xhtml
 #[derive(Debug)]
 pub struct IcmpMessage {
@@ -12,30 +14,183 @@ pub struct IcmpMessage {
     pub code: u8,
     pub checksum: u16,
     pub data: IcmpData,
+    /// The message as it was read, which is written back as it is unless
+    /// the rest changed. Empty for messages built here.
+    pub raw: Vec<u8>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum IcmpType {
     EchoReply,
     DestinationUnreachable,
+    Redirect,
     Echo,
     TimeExceeded,
     Unknown(u8),
 }
 
 #[derive(Debug)]
+pub enum UnreachableCode {
+    Net,
+    Host,
+    Protocol,
+    Port,
+    FragmentationNeeded,
+    SourceRouteFailed,
+    Unknown(u8),
+}
+
+#[derive(Debug)]
+pub enum RedirectCode {
+    Net,
+    Host,
+    TosNet,
+    TosHost,
+    Unknown(u8),
+}
+
+#[derive(Debug)]
+pub enum TimeExceededCode {
+    Ttl,
+    FragmentReassembly,
+    Unknown(u8),
+}
+
+#[derive(Debug, PartialEq, Eq)]
 pub enum IcmpData {
-    None,
-    Echo { id: u16, sequence: u16 },
+    /// The rest of a message of a type not parsed here.
+    Raw(Vec<u8>),
+    Echo {
+        id: u16,
+        sequence: u16,
+        data: Vec<u8>,
+    },
+    Unreachable {
+        next_hop_mtu: u16,
+        original: Vec<u8>,
+    },
+    Redirect {
+        gateway: IPAddress,
+        original: Vec<u8>,
+    },
+    TimeExceeded {
+        original: Vec<u8>,
+    },
 }
 
 impl IcmpMessage {
+    pub fn unreachable(code: UnreachableCode, original: IPDatagram) -> io::Result<Self> {
+        Ok(Self {
+            typ: IcmpType::DestinationUnreachable,
+            code: code.into(),
+            checksum: 0,
+            raw: Vec::new(),
+            data: IcmpData::Unreachable {
+                next_hop_mtu: 0,
+                original: Self::quote(original)?,
+            },
+        })
+    }
+
+    /// Tells the sender of `original` to lower its datagrams to
+    /// `next_hop_mtu` (RFC 1191).
+    pub fn fragmentation_needed(next_hop_mtu: u16, original: IPDatagram) -> io::Result<Self> {
+        let mut msg = Self::unreachable(UnreachableCode::FragmentationNeeded, original)?;
+        if let IcmpData::Unreachable {
+            next_hop_mtu: mtu, ..
+        } = &mut msg.data
+        {
+            *mtu = next_hop_mtu;
+        }
+        Ok(msg)
+    }
+
+    /// Tells the sender of `original` to send to its destination through
+    /// `gateway` instead.
+    pub fn redirect(
+        code: RedirectCode,
+        gateway: IPAddress,
+        original: IPDatagram,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            typ: IcmpType::Redirect,
+            code: code.into(),
+            checksum: 0,
+            raw: Vec::new(),
+            data: IcmpData::Redirect {
+                gateway,
+                original: Self::quote(original)?,
+            },
+        })
+    }
+
+    pub fn time_exceeded(code: TimeExceededCode, original: IPDatagram) -> io::Result<Self> {
+        Ok(Self {
+            typ: IcmpType::TimeExceeded,
+            code: code.into(),
+            checksum: 0,
+            raw: Vec::new(),
+            data: IcmpData::TimeExceeded {
+                original: Self::quote(original)?,
+            },
+        })
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        4 + match &self.data {
+            IcmpData::Raw(data) => data.len(),
+            IcmpData::Echo { data, .. } => 4 + data.len(),
+            IcmpData::Unreachable { original, .. } => 4 + original.len(),
+            IcmpData::Redirect { original, .. } => 4 + original.len(),
+            IcmpData::TimeExceeded { original } => 4 + original.len(),
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self.typ,
+            IcmpType::DestinationUnreachable | IcmpType::Redirect | IcmpType::TimeExceeded
+        )
+    }
+
+    /// The quoted start of the datagram an error is about.
+    pub fn original(&self) -> Option<&[u8]> {
+        match &self.data {
+            IcmpData::Unreachable { original, .. }
+            | IcmpData::Redirect { original, .. }
+            | IcmpData::TimeExceeded { original } => Some(original),
+            _ => None,
+        }
+    }
+
+    pub fn original_mut(&mut self) -> Option<&mut Vec<u8>> {
+        match &mut self.data {
+            IcmpData::Unreachable { original, .. }
+            | IcmpData::Redirect { original, .. }
+            | IcmpData::TimeExceeded { original } => Some(original),
+            _ => None,
+        }
+    }
+
+    // the internet header + the first 64 bits of the original datagram
+    fn quote(original: IPDatagram) -> io::Result<Vec<u8>> {
+        let len = original.header.ihl() as usize * 4 + 8;
+        let mut v = Vec::new();
+        original.write_to(&mut v)?;
+        v.truncate(len);
+        Ok(v)
+    }
+
     pub fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut raw = Vec::new();
+        r.read_to_end(&mut raw)?;
+        let r = &mut Cursor::new(&raw[..]);
         let mut msg = Self {
             typ: r.read_u8()?.into(),
             code: r.read_u8()?,
             checksum: r.read_u16::<BigEndian>()?,
-            data: IcmpData::None,
+            data: IcmpData::Raw(Vec::new()),
+            raw: Vec::new(),
         };
 
         match msg.typ {
@@ -43,19 +198,103 @@ impl IcmpMessage {
                 msg.data = IcmpData::Echo {
                     id: r.read_u16::<BigEndian>()?,
                     sequence: r.read_u16::<BigEndian>()?,
+                    data: {
+                        let mut v = Vec::new();
+                        r.read_to_end(&mut v)?;
+                        v
+                    },
+                };
+            }
+            IcmpType::DestinationUnreachable => {
+                r.read_u16::<BigEndian>()?;
+                msg.data = IcmpData::Unreachable {
+                    next_hop_mtu: r.read_u16::<BigEndian>()?,
+                    original: {
+                        let mut v = Vec::new();
+                        r.read_to_end(&mut v)?;
+                        v
+                    },
+                };
+            }
+            IcmpType::Redirect => {
+                msg.data = IcmpData::Redirect {
+                    gateway: IPAddress::read_from(r)?,
+                    original: {
+                        let mut v = Vec::new();
+                        r.read_to_end(&mut v)?;
+                        v
+                    },
                 };
             }
-            _ => {}
+            IcmpType::TimeExceeded => {
+                r.read_u32::<BigEndian>()?;
+                msg.data = IcmpData::TimeExceeded {
+                    original: {
+                        let mut v = Vec::new();
+                        r.read_to_end(&mut v)?;
+                        v
+                    },
+                };
+            }
+            IcmpType::Unknown(_) => {
+                let mut v = Vec::new();
+                r.read_to_end(&mut v)?;
+                msg.data = IcmpData::Raw(v);
+            }
         }
 
+        msg.raw = raw;
         Ok(msg)
     }
 
+    /// Writes the message with its checksum filled in. One that is unchanged
+    /// since it was read goes as it came, e.g. when forwarded.
     pub fn write_to<W: Write>(self, w: &mut W) -> io::Result<()> {
-        w.write_u8(self.typ.into())?;
-        w.write_u8(self.code)?;
-        w.write_u16::<BigEndian>(self.checksum)?;
-        Ok(())
+        if !self.raw.is_empty() {
+            let read = Self::read_from(&mut &self.raw[..])?;
+            if read.typ == self.typ && read.code == self.code && read.data == self.data {
+                return w.write_all(&self.raw);
+            }
+        }
+
+        let mut v = Vec::new();
+        v.write_u8(self.typ.into())?;
+        v.write_u8(self.code)?;
+        v.write_u16::<BigEndian>(0)?;
+        self.data.write_to(&mut v)?;
+
+        let checksum = checksum::checksum(&v);
+        v[2..4].copy_from_slice(&checksum.to_be_bytes());
+        w.write_all(&v)
+    }
+}
+
+impl IcmpData {
+    pub fn write_to<W: Write>(self, w: &mut W) -> io::Result<()> {
+        match self {
+            Self::Raw(data) => w.write_all(&data),
+            Self::Echo { id, sequence, data } => {
+                w.write_u16::<BigEndian>(id)?;
+                w.write_u16::<BigEndian>(sequence)?;
+                w.write_all(&data)
+            }
+            Self::Unreachable {
+                next_hop_mtu,
+                original,
+            } => {
+                w.write_u16::<BigEndian>(0)?;
+                w.write_u16::<BigEndian>(next_hop_mtu)?;
+                w.write_all(&original)
+            }
+            Self::Redirect { gateway, original } => {
+                w.write_all(&gateway.0)?;
+                w.write_all(&original)
+            }
+            Self::TimeExceeded { original } => {
+                w.write_u32::<BigEndian>(0)?;
+                w.write_all(&original)
+            }
+        }
     }
 }
 
@@ -64,6 +303,7 @@ impl From<u8> for IcmpType {
         match v {
             0 => Self::EchoReply,
             3 => Self::DestinationUnreachable,
+            5 => Self::Redirect,
             8 => Self::Echo,
             11 => Self::TimeExceeded,
             x => Self::Unknown(x),
@@ -76,6 +316,7 @@ impl From<IcmpType> for u8 {
         match v {
             IcmpType::EchoReply => 0,
             IcmpType::DestinationUnreachable => 3,
+            IcmpType::Redirect => 5,
             IcmpType::Echo => 8,
             IcmpType::TimeExceeded => 11,
             IcmpType::Unknown(x) => x,
@@ -83,6 +324,78 @@ impl From<IcmpType> for u8 {
     }
 }
 
+impl From<u8> for UnreachableCode {
+    fn from(v: u8) -> Self {
+        match v {
+            0 => Self::Net,
+            1 => Self::Host,
+            2 => Self::Protocol,
+            3 => Self::Port,
+            4 => Self::FragmentationNeeded,
+            5 => Self::SourceRouteFailed,
+            x => Self::Unknown(x),
+        }
+    }
+}
+
+impl From<UnreachableCode> for u8 {
+    fn from(v: UnreachableCode) -> Self {
+        match v {
+            UnreachableCode::Net => 0,
+            UnreachableCode::Host => 1,
+            UnreachableCode::Protocol => 2,
+            UnreachableCode::Port => 3,
+            UnreachableCode::FragmentationNeeded => 4,
+            UnreachableCode::SourceRouteFailed => 5,
+            UnreachableCode::Unknown(x) => x,
+        }
+    }
+}
+
+impl From<u8> for RedirectCode {
+    fn from(v: u8) -> Self {
+        match v {
+            0 => Self::Net,
+            1 => Self::Host,
+            2 => Self::TosNet,
+            3 => Self::TosHost,
+            x => Self::Unknown(x),
+        }
+    }
+}
+
+impl From<RedirectCode> for u8 {
+    fn from(v: RedirectCode) -> Self {
+        match v {
+            RedirectCode::Net => 0,
+            RedirectCode::Host => 1,
+            RedirectCode::TosNet => 2,
+            RedirectCode::TosHost => 3,
+            RedirectCode::Unknown(x) => x,
+        }
+    }
+}
+
+impl From<u8> for TimeExceededCode {
+    fn from(v: u8) -> Self {
+        match v {
+            0 => Self::Ttl,
+            1 => Self::FragmentReassembly,
+            x => Self::Unknown(x),
+        }
+    }
+}
+
+impl From<TimeExceededCode> for u8 {
+    fn from(v: TimeExceededCode) -> Self {
+        match v {
+            TimeExceededCode::Ttl => 0,
+            TimeExceededCode::FragmentReassembly => 1,
+            TimeExceededCode::Unknown(x) => x,
+        }
+    }
+}
+
 impl fmt::Display for IcmpMessage {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "IcmpMessage:")?;
@@ -101,6 +414,7 @@ impl fmt::Display for IcmpType {
         match self {
             EchoReply => write!(f, "Echo Reply(0)"),
             DestinationUnreachable => write!(f, "Destination Unreachable(3)"),
+            Redirect => write!(f, "Redirect(5)"),
             Echo => write!(f, "Echo(8)"),
             TimeExceeded => write!(f, "Time Exceeded(11)"),
             Unknown(x) => write!(f, "Unknown({})", x),
@@ -113,14 +427,68 @@ impl fmt::Display for IcmpData {
         use IcmpData::*;
         write!(f, "IcmpData::")?;
         match self {
-            None => writeln!(f, "None")?,
-            Echo { id, sequence } => {
+            Raw(data) => write!(f, "Raw: {} bytes", data.len())?,
+            Echo { id, sequence, data } => {
                 writeln!(f, "Echo:")?;
                 writeln!(f, "  id:  {}", id)?;
-                write!(f, "  seq: {}", sequence)?;
+                writeln!(f, "  seq: {}", sequence)?;
+                write!(f, "  dat: {} bytes", data.len())?;
+            }
+            Unreachable {
+                next_hop_mtu,
+                original,
+            } => {
+                writeln!(f, "Unreachable:")?;
+                writeln!(f, "  mtu: {}", next_hop_mtu)?;
+                write!(f, "  org: {} bytes", original.len())?;
+            }
+            Redirect { gateway, original } => {
+                writeln!(f, "Redirect:")?;
+                writeln!(f, "  gw:  {}", gateway)?;
+                write!(f, "  org: {} bytes", original.len())?;
+            }
+            TimeExceeded { original } => {
+                writeln!(f, "TimeExceeded:")?;
+                write!(f, "  org: {} bytes", original.len())?;
             }
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn echo_data_is_kept() {
+        let b = [0, 0, 0xab, 0xcd, 0, 7, 0, 9, 1, 2, 3];
+        let msg = IcmpMessage::read_from(&mut &b[..]).unwrap();
+        assert_eq!(
+            msg.data,
+            IcmpData::Echo {
+                id: 7,
+                sequence: 9,
+                data: vec![1, 2, 3],
+            }
+        );
+        assert_eq!(msg.encoded_len(), b.len());
+        let mut v = Vec::new();
+        msg.write_to(&mut v).unwrap();
+        assert_eq!(v, b);
+    }
+
+    #[test]
+    fn changed_messages_get_their_checksum_computed() {
+        let b = [8, 0, 0xab, 0xcd, 0, 7, 0, 9, 1, 2, 3];
+        let mut msg = IcmpMessage::read_from(&mut &b[..]).unwrap();
+        if let IcmpData::Echo { id, .. } = &mut msg.data {
+            *id = 8;
+        }
+        let mut v = Vec::new();
+        msg.write_to(&mut v).unwrap();
+        assert_eq!(v[4..6], [0, 8]);
+        assert!(checksum::verify(&v));
+    }
+}
@@ -1,6 +1,11 @@
-use std::io::{self, Read, Write};
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    os::unix::io::{AsRawFd, RawFd},
+    time::{Duration, Instant},
+};
 
-use link::ethernet::EthernetPayload;
+use link::ethernet::{EthernetFrame, EthernetPayload};
 
 use crate::protocol::{
     link::{self, address::MacAddress, arp},
@@ -8,63 +13,787 @@ use crate::protocol::{
 };
 
 use super::{
-    address::IPAddress,
-    ip::{IPDatagram, IPHeader, IPPayload, Protocol},
+    acd,
+    address::{IPAddress, Ipv4Cidr},
+    fragment,
+    icmp::{IcmpMessage, TimeExceededCode, UnreachableCode},
+    ip::{self, IPDatagram, IPHeader, IPPayload},
+    reassembly::Reassembler,
+    route::{Route, RoutingTable},
 };
 
+const DEFAULT_MTU: usize = 1500;
+const DEFAULT_PREFIX_LEN: u8 = 24;
+
+// the number of identification counters
+const IDENT_BUCKETS: usize = 2048;
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub arp: arp::ArpConfig,
+    /// Probe for the address before using it (RFC 5227).
+    pub conflict_detection: bool,
+    /// Send gratuitous ARP announcements after assigning the address.
+    pub announce: bool,
+    pub proxy_arp: arp::ProxyArp,
+    pub rarp_server: arp::RarpServer,
+    /// Sends datagrams without DF, so that routers fragment them instead of
+    /// reporting the path MTU (RFC 1191).
+    pub no_pmtu_discovery: bool,
+    /// The length of the prefix of the subnet, 24 if unspecified.
+    pub prefix_len: Option<u8>,
+}
+
+impl Config {
+    /// Reads the address to assign, taking the length of its subnet from
+    /// `addr/len`. A bare address keeps the default length, unlike a bare
+    /// `Ipv4Cidr`, which is a /32.
+    pub fn parse_addr(&mut self, s: &str) -> io::Result<IPAddress> {
+        let cidr = s.parse::<Ipv4Cidr>()?;
+        if s.contains('/') {
+            self.prefix_len = Some(cidr.len);
+        }
+        Ok(cidr.addr)
+    }
+}
+
+/// Counters of what was dropped on receipt.
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    pub bad_checksum: u64,
+    /// Frames that did not parse, including truncated datagrams.
+    pub malformed: u64,
+}
+
+#[derive(Debug)]
+pub enum Event {
+    /// Another host uses our address. `defended` tells whether we answered
+    /// with an announcement; a second conflict within `DEFEND_INTERVAL` is
+    /// not defended and the address should be given up.
+    AddressConflict {
+        ip_addr: IPAddress,
+        mac_addr: MacAddress,
+        defended: bool,
+    },
+}
+
 pub struct Interface {
     dev: link::Interface,
-    ip_addr: IPAddress,
+    // the primary address first
+    addrs: Vec<Ipv4Cidr>,
     arp_table: arp::ArpTable,
+    proxy_arp: arp::ProxyArp,
+    rarp_server: arp::RarpServer,
+    inbox: VecDeque<IPDatagram>,
+    events: VecDeque<Event>,
+    last_defended: Option<Instant>,
+    stats: Stats,
+    pmtu_discovery: bool,
+    idents: Vec<u16>,
+    mtu: usize,
+    reassembler: Reassembler,
+    // fragments are left to the stack, which only reassembles ours
+    forwarding: bool,
+    // none once the interface belongs to a stack, which routes what it
+    // sends from the outbox
+    routes: Option<RoutingTable>,
+    outbox: VecDeque<IPDatagram>,
 }
 
 impl Interface {
     pub fn new(dev: link::Interface, ip_addr: IPAddress) -> io::Result<Self> {
-        Ok(Self {
+        Self::with_config(dev, ip_addr, Config::default())
+    }
+
+    /// Assigns `ip_addr` to `dev` as its primary address. Fails with
+    /// `AddrInUse` if conflict detection is enabled and another host answers
+    /// our probes.
+    pub fn with_config(
+        dev: link::Interface,
+        ip_addr: IPAddress,
+        config: Config,
+    ) -> io::Result<Self> {
+        let prefix_len = config.prefix_len.unwrap_or(DEFAULT_PREFIX_LEN);
+        let mut iface = Self {
             dev,
-            ip_addr,
-            arp_table: arp::ArpTable::new(),
-        })
+            addrs: vec![Ipv4Cidr::new(ip_addr, prefix_len)?],
+            arp_table: arp::ArpTable::with_config(config.arp),
+            proxy_arp: config.proxy_arp,
+            rarp_server: config.rarp_server,
+            inbox: VecDeque::new(),
+            events: VecDeque::new(),
+            last_defended: None,
+            stats: Stats::default(),
+            pmtu_discovery: !config.no_pmtu_discovery,
+            idents: Vec::new(),
+            mtu: 0,
+            reassembler: Reassembler::new(),
+            forwarding: false,
+            routes: Some(RoutingTable::new()),
+            outbox: VecDeque::new(),
+        };
+        iface.add_route(Route::connected(iface.prefix(), iface.name()));
+        iface.mtu = iface.dev.mtu().unwrap_or(DEFAULT_MTU);
+        let mut jitter = acd::Jitter::new(iface.mac_addr());
+        iface.idents = (0..IDENT_BUCKETS)
+            .map(|_| jitter.next_u64() as u16)
+            .collect();
+        if config.conflict_detection {
+            iface.probe()?;
+        }
+        if config.announce {
+            iface.announce()?;
+        }
+        Ok(iface)
     }
 
     pub fn mac_addr(&self) -> &MacAddress {
         self.dev.mac_addr()
     }
 
+    /// The primary address.
     pub fn ip_addr(&self) -> &IPAddress {
-        &self.ip_addr
+        &self.addrs[0].addr
+    }
+
+    /// The subnet of the primary address, which is reached without a
+    /// gateway.
+    pub fn prefix(&self) -> Ipv4Cidr {
+        self.addrs[0].network()
+    }
+
+    /// The addresses with the length of their subnet, the primary one first.
+    pub fn addrs(&self) -> &[Ipv4Cidr] {
+        &self.addrs
+    }
+
+    pub fn has_addr(&self, addr: &IPAddress) -> bool {
+        self.addrs.iter().any(|p| p.addr == *addr)
+    }
+
+    /// Whether `addr` is the broadcast address of one of our subnets.
+    pub fn is_subnet_broadcast(&self, addr: &IPAddress) -> bool {
+        self.addrs
+            .iter()
+            .any(|p| p.len < 31 && p.broadcast() == *addr)
+    }
+
+    fn may_report(&self, datagram: &IPDatagram) -> bool {
+        may_report(datagram, |addr| self.is_subnet_broadcast(addr))
+    }
+
+    /// Adds a secondary address, and a route to its subnet.
+    pub fn add_addr(&mut self, addr: Ipv4Cidr) {
+        if self.has_addr(&addr.addr) {
+            return;
+        }
+        self.add_route(Route::connected(addr.clone(), self.name()));
+        self.addrs.push(addr);
+    }
+
+    /// Removes an address, and the route to its subnet unless another
+    /// address is in it. When the primary address goes, the next one takes
+    /// its place. The last address cannot be removed.
+    pub fn remove_addr(&mut self, addr: &IPAddress) -> io::Result<Ipv4Cidr> {
+        let i = match self.addrs.iter().position(|p| p.addr == *addr) {
+            Some(i) => i,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrNotAvailable,
+                    format!("{} is not assigned to {}", addr, self.name()),
+                ))
+            }
+        };
+        if self.addrs.len() == 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot remove the last address",
+            ));
+        }
+
+        let removed = self.addrs.remove(i);
+        let network = removed.network();
+        let name = self.name();
+        if let Some(routes) = &mut self.routes {
+            if self.addrs.iter().all(|p| p.network() != network) {
+                routes.retain(|r| r.prefix != network || r.gateway.is_some() || r.iface != name);
+            }
+        }
+        Ok(removed)
+    }
+
+    /// The address to send from: one in the subnet of `dst_addr`, else one in
+    /// the subnet of `next_hop`, else the primary address.
+    pub fn source_for(&self, dst_addr: &IPAddress, next_hop: &IPAddress) -> IPAddress {
+        self.addrs
+            .iter()
+            .find(|p| p.contains(dst_addr))
+            .or_else(|| self.addrs.iter().find(|p| p.contains(next_hop)))
+            .unwrap_or(&self.addrs[0])
+            .addr
+            .clone()
+    }
+
+    /// The address to send to `dst_addr` from, by the routes of the
+    /// interface.
+    pub fn select_source(&self, dst_addr: &IPAddress) -> IPAddress {
+        let route = self
+            .routes
+            .as_ref()
+            .and_then(|routes| routes.lookup(dst_addr));
+        let next_hop = match route {
+            Some(route) => route.next_hop(dst_addr),
+            None => dst_addr.clone(),
+        };
+        self.source_for(dst_addr, &next_hop)
     }
 
     pub fn link(&mut self) -> &mut link::Interface {
         &mut self.dev
     }
 
+    pub fn arp_table(&mut self) -> &mut arp::ArpTable {
+        &mut self.arp_table
+    }
+
+    pub fn proxy_arp(&mut self) -> &mut arp::ProxyArp {
+        &mut self.proxy_arp
+    }
+
+    pub fn rarp_server(&mut self) -> &mut arp::RarpServer {
+        &mut self.rarp_server
+    }
+
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    /// Overrides the MTU of the device.
+    pub fn set_mtu(&mut self, mtu: usize) {
+        self.mtu = mtu;
+    }
+
+    /// The routes, which start with the one to the subnet. `None` once the
+    /// interface belongs to a stack, whose routes it goes by.
+    pub fn routes(&mut self) -> Option<&mut RoutingTable> {
+        self.routes.as_mut()
+    }
+
+    /// Sends what is off the subnet through `gateway`. `None` removes the
+    /// default route.
+    pub fn set_gateway(&mut self, gateway: Option<IPAddress>) {
+        let name = self.name();
+        if let Some(routes) = &mut self.routes {
+            routes.set_default(gateway, name);
+        }
+    }
+
+    fn add_route(&mut self, route: Route) {
+        if let Some(routes) = &mut self.routes {
+            routes.add(route);
+        }
+    }
+
+    /// Hands the routes over to a stack, which routes what the interface
+    /// sends from then on.
+    pub(super) fn take_routes(&mut self) -> RoutingTable {
+        self.routes.take().unwrap_or_default()
+    }
+
+    /// The next datagram for the stack to route.
+    pub(super) fn next_outgoing(&mut self) -> Option<IPDatagram> {
+        self.outbox.pop_front()
+    }
+
+    /// Leaves fragments to the stack, which reassembles those for us with
+    /// `reassemble` and forwards the rest as they are.
+    pub(super) fn set_forwarding(&mut self, forwarding: bool) {
+        self.forwarding = forwarding;
+    }
+
+    /// Returns the datagram once all its fragments have arrived.
+    pub(super) fn reassemble(&mut self, datagram: IPDatagram) -> Option<IPDatagram> {
+        match self.reassembler.push(datagram) {
+            Ok(datagram) => datagram,
+            Err(_) => {
+                self.stats.malformed += 1;
+                None
+            }
+        }
+    }
+
+    /// Keeps a datagram for `recv` as if it had come in.
+    pub(super) fn deliver(&mut self, datagram: IPDatagram) {
+        self.inbox.push_back(datagram);
+    }
+
+    pub fn reassembler(&mut self) -> &mut Reassembler {
+        &mut self.reassembler
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub fn next_event(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    /// Checks that nobody else uses our address.
+    // https://tools.ietf.org/html/rfc5227#section-2.1
+    pub fn probe(&mut self) -> io::Result<()> {
+        let mut jitter = acd::Jitter::new(self.mac_addr());
+        let mut deadline = Instant::now() + jitter.between(Duration::from_secs(0), acd::PROBE_WAIT);
+
+        for i in 0..acd::PROBE_NUM {
+            self.wait_for_conflict(deadline)?;
+
+            let probe = arp::Arp::new(
+                arp::Opcode::Request,
+                self.mac_addr().clone(),
+                IPAddress::UNSPECIFIED,
+                MacAddress([0; 6]),
+                self.ip_addr().clone(),
+            );
+            self.dev
+                .send(MacAddress::broadcast(), EthernetPayload::Arp(probe))?;
+
+            deadline = Instant::now()
+                + if i + 1 == acd::PROBE_NUM {
+                    acd::ANNOUNCE_WAIT
+                } else {
+                    jitter.between(acd::PROBE_MIN, acd::PROBE_MAX)
+                };
+        }
+        self.wait_for_conflict(deadline)
+    }
+
+    fn wait_for_conflict(&mut self, deadline: Instant) -> io::Result<()> {
+        let ip_addr = self.ip_addr().clone();
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(());
+            }
+            let frame = match self.recv_frame(Some(deadline - now))? {
+                Some(frame) => frame,
+                None => continue,
+            };
+            let arp = match frame.payload {
+                EthernetPayload::Arp(arp) => arp,
+                _ => continue,
+            };
+            if arp.sender_hardware_addr == *self.mac_addr() {
+                continue;
+            }
+
+            // someone uses the address, or probes for it at the same time
+            let probing = arp.sender_protocol_addr == IPAddress::UNSPECIFIED
+                && arp.opcode == arp::Opcode::Request
+                && arp.target_protocol_addr == ip_addr;
+            if arp.sender_protocol_addr == ip_addr || probing {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is in use by {}", ip_addr, arp.sender_hardware_addr),
+                ));
+            }
+        }
+    }
+
+    /// Broadcasts gratuitous ARP requests for every address so that
+    /// neighbors update their caches.
+    // https://tools.ietf.org/html/rfc5227#section-2.3
+    pub fn announce(&mut self) -> io::Result<()> {
+        for i in 0..acd::ANNOUNCE_NUM {
+            if i > 0 {
+                self.process_until(Instant::now() + acd::ANNOUNCE_INTERVAL)?;
+            }
+            for addr in self.addrs.clone() {
+                self.send_announcement(addr.addr)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn send_announcement(&mut self, ip_addr: IPAddress) -> io::Result<()> {
+        let announcement = arp::Arp::new(
+            arp::Opcode::Request,
+            self.mac_addr().clone(),
+            ip_addr.clone(),
+            MacAddress([0; 6]),
+            ip_addr,
+        );
+        self.dev
+            .send(MacAddress::broadcast(), EthernetPayload::Arp(announcement))
+    }
+
+    /// Handles incoming frames until `deadline`, keeping datagrams for `recv`.
+    fn process_until(&mut self, deadline: Instant) -> io::Result<()> {
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(());
+            }
+            let frame = match self.recv_frame(Some(deadline - now))? {
+                Some(frame) => frame,
+                None => continue,
+            };
+            self.handle_frame(frame)?;
+        }
+    }
+
     pub fn recv(&mut self) -> io::Result<IPDatagram> {
         loop {
-            let frame = self.dev.recv()?;
-            if let EthernetPayload::IP(ip) = frame.payload {
-                return Ok(ip);
+            let timeout = self.arp_table.config().retrans_time;
+            if let Some(datagram) = self.recv_timeout(Some(timeout))? {
+                return Ok(datagram);
             }
         }
     }
 
+    /// Handles at most one frame, waiting up to `timeout` for it, and returns
+    /// a datagram if one is ready.
+    pub fn recv_timeout(&mut self, timeout: Option<Duration>) -> io::Result<Option<IPDatagram>> {
+        self.poll()?;
+        if let Some(datagram) = self.inbox.pop_front() {
+            return Ok(Some(datagram));
+        }
+
+        if let Some(frame) = self.recv_frame(timeout)? {
+            self.handle_frame(frame)?;
+        }
+        Ok(self.inbox.pop_front())
+    }
+
+    /// Sends a datagram from the address that suits `dst_addr`, or queues it
+    /// until the next hop is resolved.
     pub fn send(&mut self, dst_addr: IPAddress, payload: IPPayload) -> io::Result<()> {
-        let header = IPHeader {
+        let src_addr = self.select_source(&dst_addr);
+        let header = self.header(src_addr, dst_addr, &payload);
+        self.send_datagram(IPDatagram { header, payload })
+    }
+
+    /// Sends a datagram as it is to the next hop of its route, fragmenting it
+    /// to the MTU. One that has DF set and does not fit, or that has no
+    /// route, fails if it is ours, and is reported to its source with ICMP
+    /// otherwise. Once the interface belongs to a stack, the stack routes
+    /// and filters it instead when it next polls.
+    pub fn send_datagram(&mut self, datagram: IPDatagram) -> io::Result<()> {
+        let routes = match &self.routes {
+            Some(routes) => routes,
+            None => {
+                self.outbox.push_back(datagram);
+                return Ok(());
+            }
+        };
+        let ours = self.has_addr(&datagram.header.src_addr);
+        let dst_addr = datagram.header.dst_addr.clone();
+        let next_hop = match routes.lookup(&dst_addr) {
+            Some(route) if route.iface == self.name() => route.next_hop(&dst_addr),
+            _ if ours => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NetworkUnreachable,
+                    format!("no route to {}", dst_addr),
+                ))
+            }
+            _ => return self.send_unreachable(UnreachableCode::Net, datagram),
+        };
+
+        self.send_via(next_hop, datagram)
+    }
+
+    /// Sends a datagram to `next_hop` on the link regardless of the routes,
+    /// e.g. for a route chosen elsewhere.
+    pub fn send_via(&mut self, next_hop: IPAddress, datagram: IPDatagram) -> io::Result<()> {
+        let ours = self.has_addr(&datagram.header.src_addr);
+        if ours && datagram.header.dont_fragment() && fragment::is_oversized(&datagram, self.mtu)? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("datagram exceeds MTU {} and has DF set", self.mtu),
+            ));
+        }
+
+        // off the subnet, the gateway is resolved instead of the destination
+        match self.arp_table.lookup(&next_hop) {
+            Some(dst_mac_addr) => self.transmit(dst_mac_addr, datagram),
+            None => {
+                self.arp_table.enqueue(&next_hop, datagram);
+                self.poll()
+            }
+        }
+    }
+
+    /// Runs the timers of the interface. `recv` calls this by itself.
+    pub fn poll(&mut self) -> io::Result<()> {
+        for action in self.arp_table.tick() {
+            match action {
+                arp::ArpAction::Broadcast(addr) => {
+                    self.send_arp_request(MacAddress::broadcast(), addr)?
+                }
+                arp::ArpAction::Unicast(addr, mac_addr) => self.send_arp_request(mac_addr, addr)?,
+                arp::ArpAction::Failed(_, pending) => {
+                    for datagram in pending {
+                        self.send_unreachable(UnreachableCode::Host, datagram)?;
+                    }
+                }
+            }
+        }
+        self.arp_table.gc();
+
+        for first in self.reassembler.tick() {
+            if !self.may_report(&first) {
+                continue;
+            }
+            let src_addr = first.header.src_addr.clone();
+            let msg = IcmpMessage::time_exceeded(TimeExceededCode::FragmentReassembly, first)?;
+            self.send_error(src_addr, msg)?;
+        }
+        Ok(())
+    }
+
+    /// A header for a datagram of ours, which takes the next identification
+    /// for `dst_addr`.
+    pub fn header(
+        &mut self,
+        src_addr: IPAddress,
+        dst_addr: IPAddress,
+        payload: &IPPayload,
+    ) -> IPHeader {
+        // ICMP goes without DF like on Linux, so that errors about large
+        // datagrams still get through
+        // and what does not fit is fragmented here
+        let is_icmp = matches!(payload, IPPayload::Icmp(_));
+        let fits = 20 + payload.encoded_len() <= self.mtu;
+        let flags_offset = if self.pmtu_discovery && !is_icmp && fits {
+            ip::FLAG_DONT_FRAGMENT
+        } else {
+            0
+        };
+        IPHeader {
             version_ihl: (4 << 4) | 5,
-            tos: 5,
-            length: 112,
-            identification: 0,
-            flags_offset: 0,
+            tos: 0,
+            length: (20 + payload.encoded_len()) as u16,
+            identification: self.next_ident(&dst_addr),
+            flags_offset,
             ttl: 64,
             protocol: payload.protocol(),
             checksum: 0,
-            src_addr: self.ip_addr().clone(),
+            src_addr,
             dst_addr,
+            options: Vec::new(),
+            raw: Vec::new(),
+        }
+    }
+
+    // the counters are shared by the destinations in a bucket, which keeps
+    // them from growing with the number of destinations
+    fn next_ident(&mut self, dst_addr: &IPAddress) -> u16 {
+        let hash = u32::from_be_bytes(dst_addr.0).wrapping_mul(0x9e37_79b9);
+        let bucket = (hash >> 16) as usize % IDENT_BUCKETS;
+        let ident = self.idents[bucket];
+        self.idents[bucket] = ident.wrapping_add(1);
+        ident
+    }
+
+    // drops what does not parse, e.g. truncated datagrams
+    fn recv_frame(&mut self, timeout: Option<Duration>) -> io::Result<Option<EthernetFrame>> {
+        match self.dev.recv_timeout(timeout) {
+            Err(e)
+                if e.kind() == io::ErrorKind::InvalidData
+                    || e.kind() == io::ErrorKind::UnexpectedEof =>
+            {
+                self.stats.malformed += 1;
+                Ok(None)
+            }
+            result => result,
+        }
+    }
+
+    /// Keeps datagrams for `recv` and handles the rest.
+    fn handle_frame(&mut self, frame: EthernetFrame) -> io::Result<()> {
+        match frame.payload {
+            EthernetPayload::IP(ip) => {
+                if !ip.header.is_checksum_valid() {
+                    self.stats.bad_checksum += 1;
+                    return Ok(());
+                }
+                if self.forwarding {
+                    self.inbox.push_back(ip);
+                } else if let Some(ip) = self.reassemble(ip) {
+                    self.inbox.push_back(ip);
+                }
+            }
+            EthernetPayload::Arp(arp) => self.handle_arp(arp)?,
+            EthernetPayload::Rarp(rarp) => self.handle_rarp(rarp)?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // https://tools.ietf.org/html/rfc826 "Packet Reception"
+    fn handle_arp(&mut self, arp: arp::Arp) -> io::Result<()> {
+        let (mac_addr, sender_addr, target_addr) = match (
+            arp.sender_hardware_addr.as_mac(),
+            arp.sender_protocol_addr.as_ipv4(),
+            arp.target_protocol_addr.as_ipv4(),
+        ) {
+            (Some(mac_addr), Some(sender_addr), Some(target_addr)) => {
+                (mac_addr.clone(), sender_addr.clone(), target_addr.clone())
+            }
+            _ => return Ok(()),
         };
-        let datagram = IPDatagram { header, payload };
-        let dst_mac_addr = self.arp_table.find(dst_addr, &mut self).unwrap();
-        self.dev
-            .send(self.mac_addr().clone(), EthernetPayload::IP(datagram))
+        let for_us = self.has_addr(&target_addr);
+
+        if self.has_addr(&sender_addr) {
+            if mac_addr != *self.mac_addr() {
+                self.defend(sender_addr, mac_addr)?;
+            }
+            return Ok(());
+        }
+
+        // a sender of 0.0.0.0 is probing for an address and has no mapping
+        let merge = !sender_addr.is_unspecified()
+            && (for_us || self.arp_table.entry(&sender_addr).is_some());
+        if merge {
+            let solicited = for_us && arp.opcode == arp::Opcode::Reply;
+            let pending = self
+                .arp_table
+                .update(sender_addr.clone(), mac_addr.clone(), solicited);
+            for datagram in pending {
+                self.transmit(mac_addr.clone(), datagram)?;
+            }
+        }
+
+        let proxied = !for_us
+            && !sender_addr.is_unspecified()
+            && self.proxy_arp.answers(&sender_addr, &target_addr);
+        if (for_us || proxied) && arp.opcode == arp::Opcode::Request {
+            let reply = arp::Arp::new(
+                arp::Opcode::Reply,
+                self.mac_addr().clone(),
+                target_addr,
+                mac_addr.clone(),
+                sender_addr,
+            );
+            self.dev.send(mac_addr, EthernetPayload::Arp(reply))?;
+        }
+        Ok(())
+    }
+
+    fn handle_rarp(&mut self, rarp: arp::Arp) -> io::Result<()> {
+        let reply = match self
+            .rarp_server
+            .respond(&rarp, self.mac_addr(), &self.addrs[0].addr)
+        {
+            Some(reply) => reply,
+            None => return Ok(()),
+        };
+        let dst_addr = match reply.target_hardware_addr.as_mac() {
+            Some(addr) => addr.clone(),
+            None => return Ok(()),
+        };
+        self.dev.send(dst_addr, EthernetPayload::Rarp(reply))
+    }
+
+    // https://tools.ietf.org/html/rfc5227#section-2.4
+    fn defend(&mut self, ip_addr: IPAddress, mac_addr: MacAddress) -> io::Result<()> {
+        let now = Instant::now();
+        let defended = match self.last_defended {
+            Some(last) => now - last >= acd::DEFEND_INTERVAL,
+            None => true,
+        };
+        if defended {
+            self.last_defended = Some(now);
+            self.send_announcement(ip_addr.clone())?;
+        }
+
+        self.events.push_back(Event::AddressConflict {
+            ip_addr,
+            mac_addr,
+            defended,
+        });
+        Ok(())
+    }
+
+    fn send_arp_request(&mut self, dst_addr: MacAddress, target_addr: IPAddress) -> io::Result<()> {
+        let src_addr = self.source_for(&target_addr, &target_addr);
+        let request = arp::Arp::new(
+            arp::Opcode::Request,
+            self.mac_addr().clone(),
+            src_addr,
+            MacAddress::broadcast(),
+            target_addr,
+        );
+        self.dev.send(dst_addr, EthernetPayload::Arp(request))
+    }
+
+    fn transmit(&mut self, dst_addr: MacAddress, datagram: IPDatagram) -> io::Result<()> {
+        if datagram.header.dont_fragment() && fragment::is_oversized(&datagram, self.mtu)? {
+            if !self.may_report(&datagram) {
+                return Ok(());
+            }
+            let src_addr = datagram.header.src_addr.clone();
+            let msg = IcmpMessage::fragmentation_needed(self.mtu as u16, datagram)?;
+            return self.send_error(src_addr, msg);
+        }
+
+        for fragment in fragment::fragment(datagram, self.mtu)? {
+            self.dev
+                .send(dst_addr.clone(), EthernetPayload::IP(fragment))?;
+        }
+        Ok(())
+    }
+
+    fn send_unreachable(&mut self, code: UnreachableCode, original: IPDatagram) -> io::Result<()> {
+        if !self.may_report(&original) {
+            return Ok(());
+        }
+        let src_addr = original.header.src_addr.clone();
+        let msg = IcmpMessage::unreachable(code, original)?;
+        self.send_error(src_addr, msg)
+    }
+
+    fn send_error(&mut self, dst_addr: IPAddress, msg: IcmpMessage) -> io::Result<()> {
+        let payload = IPPayload::Icmp(msg);
+        if !self.has_addr(&dst_addr) {
+            // what cannot be routed back to the source is dropped
+            return match self.send(dst_addr, payload) {
+                Err(e) if e.kind() == io::ErrorKind::NetworkUnreachable => Ok(()),
+                result => result,
+            };
+        }
+
+        // the datagram was our own, so report the error to ourselves
+        let header = self.header(dst_addr.clone(), dst_addr, &payload);
+        self.inbox.push_back(IPDatagram { header, payload });
+        Ok(())
+    }
+}
+
+/// Whether an ICMP error may be sent about a datagram: none is about an
+/// error, a fragment but the first, or what goes to or comes from more than
+/// one host or none, `is_broadcast` telling the broadcasts of subnets.
+// https://tools.ietf.org/html/rfc1812#section-4.3.2.7
+pub(super) fn may_report<F: Fn(&IPAddress) -> bool>(
+    datagram: &IPDatagram,
+    is_broadcast: F,
+) -> bool {
+    let header = &datagram.header;
+    let to_many =
+        |addr: &IPAddress| addr.is_broadcast() || addr.is_multicast() || is_broadcast(addr);
+    !is_icmp_error(datagram)
+        && header.offset() == 0
+        && !to_many(&header.dst_addr)
+        && !to_many(&header.src_addr)
+        && !header.src_addr.is_unspecified()
+        && !header.src_addr.is_loopback()
+}
+
+// no errors are sent about errors
+fn is_icmp_error(datagram: &IPDatagram) -> bool {
+    match &datagram.payload {
+        IPPayload::Icmp(icmp) => icmp.is_error(),
+        _ => false,
     }
 }
 
@@ -76,6 +805,24 @@ impl Device for Interface {
     fn address(&self) -> io::Result<MacAddress> {
         self.dev.address()
     }
+
+    fn is_up(&self) -> io::Result<bool> {
+        self.dev.is_up()
+    }
+
+    fn mtu(&self) -> io::Result<usize> {
+        self.dev.mtu()
+    }
+
+    fn poll(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        self.dev.poll(timeout)
+    }
+}
+
+impl AsRawFd for Interface {
+    fn as_raw_fd(&self) -> RawFd {
+        self.dev.as_raw_fd()
+    }
 }
 
 impl Read for Interface {
@@ -93,3 +840,330 @@ impl Write for Interface {
         self.dev.flush()
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        io::Cursor,
+        os::unix::{io::RawFd, net::UnixDatagram},
+    };
+
+    use super::*;
+    use crate::protocol::internet::{
+        icmp::{IcmpData, IcmpType},
+        ip::Protocol,
+    };
+    use crate::protocol::link::{
+        arp::{ArpState, Opcode},
+        ethernet::{EtherType, EthernetHeader},
+    };
+
+    // one end of a link, whose other end the test holds
+    struct FakeDevice {
+        name: String,
+        sock: UnixDatagram,
+    }
+
+    impl Device for FakeDevice {
+        fn name(&self) -> String {
+            self.name.clone()
+        }
+
+        fn address(&self) -> io::Result<MacAddress> {
+            let n = self.name.as_bytes()[0];
+            Ok(MacAddress([2, 0, 0, 0, 0, n]))
+        }
+
+        fn is_up(&self) -> io::Result<bool> {
+            Ok(true)
+        }
+
+        fn mtu(&self) -> io::Result<usize> {
+            Ok(1500)
+        }
+    }
+
+    impl AsRawFd for FakeDevice {
+        fn as_raw_fd(&self) -> RawFd {
+            self.sock.as_raw_fd()
+        }
+    }
+
+    impl Read for FakeDevice {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.sock.recv(buf)
+        }
+    }
+
+    impl Write for FakeDevice {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sock.send(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn iface(name: &str, addr: &str) -> (Interface, UnixDatagram) {
+        let (sock, peer) = UnixDatagram::pair().unwrap();
+        peer.set_nonblocking(true).unwrap();
+        let dev = FakeDevice {
+            name: name.into(),
+            sock,
+        };
+        let dev = link::Interface::new(Box::new(dev)).unwrap();
+        let mut config = Config::default();
+        config.arp.retrans_time = Duration::from_millis(10);
+        config.arp.mcast_probes = 1;
+        let iface = Interface::with_config(dev, addr.parse().unwrap(), config).unwrap();
+        (iface, peer)
+    }
+
+    // the frames sent on a link so far
+    pub(crate) fn sent(peer: &UnixDatagram) -> Vec<EthernetFrame> {
+        let mut frames = Vec::new();
+        let mut buf = [0; 4096];
+        while let Ok(len) = peer.recv(&mut buf) {
+            frames.push(EthernetFrame::read_from(&mut Cursor::new(&buf[..len])).unwrap());
+        }
+        frames
+    }
+
+    pub(crate) fn echo() -> IPPayload {
+        IPPayload::Icmp(IcmpMessage {
+            typ: IcmpType::Echo,
+            code: 0,
+            checksum: 0,
+            data: IcmpData::Echo {
+                id: 1,
+                sequence: 1,
+                data: vec![1, 2, 3, 4],
+            },
+            raw: Vec::new(),
+        })
+    }
+
+    // an ARP packet from 10.0.0.9 arriving on a link
+    fn receive_arp(peer: &UnixDatagram, opcode: Opcode, sender_addr: &str, target_addr: &str) {
+        let arp = arp::Arp::new(
+            opcode,
+            MacAddress([2, 0, 0, 0, 0, 9]),
+            sender_addr.parse().unwrap(),
+            MacAddress([0; 6]),
+            target_addr.parse().unwrap(),
+        );
+        let frame = EthernetFrame {
+            header: EthernetHeader {
+                dst_addr: MacAddress::broadcast(),
+                src_addr: MacAddress([2, 0, 0, 0, 0, 9]),
+                typ: EtherType::Arp,
+            },
+            payload: EthernetPayload::Arp(arp),
+        };
+        let mut v = Vec::new();
+        frame.write_to(&mut v).unwrap();
+        peer.send(&v).unwrap();
+    }
+
+    fn process(iface: &mut Interface) {
+        let timeout = Some(Duration::from_millis(5));
+        assert!(iface.recv_timeout(timeout).unwrap().is_none());
+    }
+
+    fn state(iface: &mut Interface, addr: &str) -> Option<ArpState> {
+        let addr = addr.parse().unwrap();
+        iface.arp_table().entry(&addr).map(|entry| entry.state)
+    }
+
+    #[test]
+    fn parse_addr_keeps_the_default_subnet_for_a_bare_address() {
+        let mut config = Config::default();
+        assert_eq!(
+            config.parse_addr("10.0.0.4").unwrap(),
+            IPAddress([10, 0, 0, 4])
+        );
+        assert_eq!(config.prefix_len, None);
+        assert_eq!(
+            config.parse_addr("10.0.0.4/16").unwrap(),
+            IPAddress([10, 0, 0, 4])
+        );
+        assert_eq!(config.prefix_len, Some(16));
+        assert!(config.parse_addr("10.0.0.4/40").is_err());
+    }
+
+    #[test]
+    fn invalid_prefix_lengths_are_an_error() {
+        let (sock, _peer) = UnixDatagram::pair().unwrap();
+        let dev = FakeDevice {
+            name: "a".into(),
+            sock,
+        };
+        let dev = link::Interface::new(Box::new(dev)).unwrap();
+        let config = Config {
+            prefix_len: Some(33),
+            ..Config::default()
+        };
+        match Interface::with_config(dev, "10.0.0.1".parse().unwrap(), config) {
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::InvalidInput),
+            Ok(_) => panic!("a /33 was accepted"),
+        }
+    }
+
+    #[test]
+    fn headers_take_the_protocol_of_the_payload() {
+        let (mut iface, _peer) = iface("a", "10.0.0.1");
+        let (src, dst): (IPAddress, IPAddress) =
+            ("10.0.0.1".parse().unwrap(), "10.0.0.9".parse().unwrap());
+        let payload = IPPayload::Raw(Protocol::Tcp, vec![0; 20]);
+        let header = iface.header(src.clone(), dst.clone(), &payload);
+        assert_eq!(header.protocol, Protocol::Tcp);
+        let header = iface.header(src, dst, &echo());
+        assert_eq!(header.protocol, Protocol::Icmp);
+    }
+
+    #[test]
+    fn headers_count_identification_per_destination() {
+        let (mut iface, _peer) = iface("a", "10.0.0.1");
+        let header = |iface: &mut Interface, dst_addr: &str| {
+            iface.header(
+                "10.0.0.1".parse().unwrap(),
+                dst_addr.parse().unwrap(),
+                &echo(),
+            )
+        };
+        let first = header(&mut iface, "10.0.0.9");
+        assert_eq!(first.length, 20 + echo().encoded_len() as u16);
+        let second = header(&mut iface, "10.0.0.9");
+        assert_eq!(second.identification, first.identification.wrapping_add(1));
+    }
+
+    #[test]
+    fn dont_fragment_is_set_on_what_fits_but_icmp() {
+        let (mut iface, _peer) = iface("a", "10.0.0.1");
+        let dont_fragment = |iface: &mut Interface, payload: IPPayload| {
+            let header = iface.header(
+                "10.0.0.1".parse().unwrap(),
+                "10.0.0.9".parse().unwrap(),
+                &payload,
+            );
+            assert_eq!(header.length as usize, 20 + payload.encoded_len());
+            header.dont_fragment()
+        };
+        let udp = |len| IPPayload::Raw(Protocol::Udp, vec![0; len]);
+        assert!(dont_fragment(&mut iface, udp(1480)));
+        assert!(!dont_fragment(&mut iface, udp(1481)));
+        assert!(!dont_fragment(&mut iface, echo()));
+        iface.pmtu_discovery = false;
+        assert!(!dont_fragment(&mut iface, udp(8)));
+    }
+
+    #[test]
+    fn requests_for_our_address_are_answered_and_learned() {
+        let (mut iface, peer) = iface("a", "10.0.0.1");
+        receive_arp(&peer, Opcode::Request, "10.0.0.9", "10.0.0.1");
+        process(&mut iface);
+
+        match sent(&peer).as_slice() {
+            [EthernetFrame {
+                header,
+                payload: EthernetPayload::Arp(reply),
+            }] => {
+                assert_eq!(header.dst_addr, MacAddress([2, 0, 0, 0, 0, 9]));
+                assert_eq!(reply.opcode, Opcode::Reply);
+                assert_eq!(reply.sender_hardware_addr.as_mac(), Some(iface.mac_addr()));
+                assert_eq!(
+                    reply.sender_protocol_addr.as_ipv4(),
+                    Some(&"10.0.0.1".parse().unwrap())
+                );
+            }
+            frames => panic!("{:?}", frames),
+        }
+        assert_eq!(state(&mut iface, "10.0.0.9"), Some(ArpState::Stale));
+    }
+
+    #[test]
+    fn requests_for_other_addresses_are_neither_answered_nor_learned() {
+        let (mut iface, peer) = iface("a", "10.0.0.1");
+        receive_arp(&peer, Opcode::Request, "10.0.0.9", "10.0.0.7");
+        process(&mut iface);
+        assert!(sent(&peer).is_empty());
+        assert_eq!(state(&mut iface, "10.0.0.9"), None);
+    }
+
+    #[test]
+    fn known_senders_are_updated_by_any_packet() {
+        let (mut iface, peer) = iface("a", "10.0.0.1");
+        iface.arp_table().insert(
+            "10.0.0.9".parse().unwrap(),
+            MacAddress([2, 0, 0, 0, 0, 8]),
+            ArpState::Reachable,
+        );
+        receive_arp(&peer, Opcode::Request, "10.0.0.9", "10.0.0.7");
+        process(&mut iface);
+
+        let addr = "10.0.0.9".parse().unwrap();
+        assert_eq!(
+            iface.arp_table().get(&addr),
+            Some(MacAddress([2, 0, 0, 0, 0, 9]))
+        );
+        assert_eq!(state(&mut iface, "10.0.0.9"), Some(ArpState::Stale));
+    }
+
+    #[test]
+    fn replies_release_what_waited_for_them() {
+        let (mut iface, peer) = iface("a", "10.0.0.1");
+        let payload = echo();
+        let header = iface.header(
+            "10.0.0.1".parse().unwrap(),
+            "10.0.0.9".parse().unwrap(),
+            &payload,
+        );
+        iface.send_datagram(IPDatagram { header, payload }).unwrap();
+        assert!(matches!(
+            sent(&peer).as_slice(),
+            [EthernetFrame {
+                payload: EthernetPayload::Arp(_),
+                ..
+            }]
+        ));
+
+        receive_arp(&peer, Opcode::Reply, "10.0.0.9", "10.0.0.1");
+        process(&mut iface);
+        assert_eq!(state(&mut iface, "10.0.0.9"), Some(ArpState::Reachable));
+        match sent(&peer).as_slice() {
+            [EthernetFrame {
+                header,
+                payload: EthernetPayload::IP(_),
+            }] => assert_eq!(header.dst_addr, MacAddress([2, 0, 0, 0, 0, 9])),
+            frames => panic!("{:?}", frames),
+        }
+    }
+
+    #[test]
+    fn probes_are_answered_but_not_learned() {
+        let (mut iface, peer) = iface("a", "10.0.0.1");
+        receive_arp(&peer, Opcode::Request, "0.0.0.0", "10.0.0.1");
+        process(&mut iface);
+        assert_eq!(sent(&peer).len(), 1);
+        assert_eq!(state(&mut iface, "0.0.0.0"), None);
+    }
+
+    #[test]
+    fn rarp_requests_for_other_hardware_are_dropped() {
+        let (mut iface, peer) = iface("a", "10.0.0.1");
+        iface
+            .rarp_server()
+            .insert(MacAddress([2, 0, 0, 0, 0, 9]), "10.0.0.9".parse().unwrap());
+
+        // IEEE 802 hardware with 8 byte addresses
+        let mut v = MacAddress::broadcast().0.to_vec();
+        v.extend_from_slice(&[2, 0, 0, 0, 0, 9, 0x80, 0x35, 0, 6, 0x08, 0x00, 8, 4, 0, 3]);
+        for (x, len) in [(9, 8), (0, 4), (9, 8), (0, 4)] {
+            v.extend(std::iter::repeat_n(x, len));
+        }
+        peer.send(&v).unwrap();
+        process(&mut iface);
+        assert!(sent(&peer).is_empty());
+    }
+}
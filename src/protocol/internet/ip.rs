@@ -1,12 +1,17 @@
+pub mod option;
+pub use option::*;
+
 use std::{
     fmt,
-    io::{self, Read, Write},
-    u16,
+    io::{self, Cursor, Read, Write},
 };
 
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{BigEndian, ReadBytesExt};
+
+use super::{address::IPAddress, checksum, icmp::IcmpMessage};
 
-use super::{address::IPAddress, icmp::IcmpMessage};
+pub const FLAG_DONT_FRAGMENT: u16 = 0x4000;
+pub const FLAG_MORE_FRAGMENTS: u16 = 0x2000;
 
 // https://www.iana.org/assignments/protocol-numbers/protocol-numbers.xhtml
 #[derive(Debug)]
@@ -15,7 +20,7 @@ pub struct IPDatagram {
     pub payload: IPPayload,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct IPHeader {
     pub version_ihl: u8,
     pub tos: u8,
@@ -27,9 +32,13 @@ pub struct IPHeader {
     pub checksum: u16,
     pub src_addr: IPAddress,
     pub dst_addr: IPAddress,
+    pub options: Vec<IPOption>,
+    /// The header as it was read, over which the checksum is verified.
+    /// Empty for headers built here.
+    pub raw: Vec<u8>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum Protocol {
     Icmp,
     Tcp,
@@ -40,28 +49,69 @@ pub enum Protocol {
 #[derive(Debug)]
 pub enum IPPayload {
     Icmp(IcmpMessage),
-    Raw(Vec<u8>),
+    /// The bytes of a protocol that is not parsed here, or of a fragment.
+    Raw(Protocol, Vec<u8>),
 }
 
 impl IPDatagram {
+    /// Reads the datagram up to its total length, leaving anything after it
+    /// such as the padding of the frame.
     pub fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
         let header = IPHeader::read_from(r)?;
-        let payload = match header.protocol {
-            Protocol::Icmp => IPPayload::Icmp(IcmpMessage::read_from(r)?),
-            _ => IPPayload::Raw({
-                let mut v = Vec::new();
-                r.read_to_end(&mut v)?;
-                v
-            }),
+        let header_len = header.ihl() as usize * 4;
+        let len = header.length as usize;
+        if len < header_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "total length {} is shorter than the header: {}",
+                    len, header_len
+                ),
+            ));
+        }
+
+        let mut v = Vec::new();
+        r.take((len - header_len) as u64).read_to_end(&mut v)?;
+        if v.len() < len - header_len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "truncated datagram: {} of {} bytes",
+                    header_len + v.len(),
+                    len
+                ),
+            ));
+        }
+        // a fragment is only parsed once reassembled
+        let payload = if header.is_fragment() {
+            IPPayload::Raw(header.protocol, v)
+        } else {
+            IPPayload::read_from(header.protocol, v)?
         };
 
         Ok(Self { header, payload })
     }
 
-    pub fn write_to<W: Write>(self, w: &mut W) -> io::Result<()> {
+    /// Writes the datagram with its total length filled in, and the checksum
+    /// updated if the length changed.
+    pub fn write_to<W: Write>(mut self, w: &mut W) -> io::Result<()> {
+        let mut payload = Vec::new();
+        self.payload.write_to(&mut payload)?;
+
+        let len = self.header.header_len()? + payload.len();
+        if len > u16::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("datagram is too long: {} bytes", len),
+            ));
+        }
+        let len = len as u16;
+        if self.header.checksum != 0 {
+            self.header.checksum = checksum::update(self.header.checksum, self.header.length, len);
+        }
+        self.header.length = len;
         self.header.write_to(w)?;
-        self.payload.write_to(w)?;
-        Ok(())
+        w.write_all(&payload)
     }
 }
 
@@ -82,8 +132,28 @@ impl IPHeader {
         self.flags_offset & 0x1fff
     }
 
-    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
-        Ok(Self {
+    pub fn dont_fragment(&self) -> bool {
+        self.flags_offset & FLAG_DONT_FRAGMENT != 0
+    }
+
+    pub fn more_fragments(&self) -> bool {
+        self.flags_offset & FLAG_MORE_FRAGMENTS != 0
+    }
+
+    pub fn is_fragment(&self) -> bool {
+        self.more_fragments() || self.offset() != 0
+    }
+
+    /// The length of the header with its options.
+    pub fn header_len(&self) -> io::Result<usize> {
+        Ok(20 + self.options_bytes()?.len())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut fixed = [0; 20];
+        reader.read_exact(&mut fixed)?;
+        let r = &mut Cursor::new(&fixed[..]);
+        let mut header = Self {
             version_ihl: r.read_u8()?,
             tos: r.read_u8()?,
             length: r.read_u16::<BigEndian>()?,
@@ -94,36 +164,131 @@ impl IPHeader {
             checksum: r.read_u16::<BigEndian>()?,
             src_addr: IPAddress::read_from(r)?,
             dst_addr: IPAddress::read_from(r)?,
-        })
+            options: Vec::new(),
+            raw: Vec::new(),
+        };
+
+        let len = header.ihl() as usize * 4;
+        if len < 20 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid header length: {}", len),
+            ));
+        }
+        let mut options = vec![0; len - 20];
+        reader.read_exact(&mut options)?;
+        header.options = IPOption::read_all(&options)?;
+        header.raw = [&fixed[..], &options].concat();
+        Ok(header)
     }
 
-    pub fn write_to<W: Write>(self, w: &mut W) -> io::Result<()> {
-        w.write_u8(self.version_ihl)?;
-        w.write_u8(self.tos)?;
-        w.write_u16::<BigEndian>(self.length)?;
-        w.write_u16::<BigEndian>(self.identification)?;
-        w.write_u16::<BigEndian>(self.flags_offset)?;
-        w.write_u8(self.ttl)?;
-        w.write_u8(self.protocol.into())?;
-        w.write_u16::<BigEndian>(self.checksum)?;
-        w.write_all(&self.src_addr.0)?;
-        w.write_all(&self.dst_addr.0)?;
-        Ok(())
+    /// Writes the header with, from the options, its header length filled
+    /// in. A checksum of 0 is computed, as is one over options that are not
+    /// written back as they were read. Any other is kept, so that changes to
+    /// a forwarded header must update it, e.g. with `checksum::update`.
+    pub fn write_to<W: Write>(mut self, w: &mut W) -> io::Result<()> {
+        let options = self.options_bytes()?;
+        let len = 20 + options.len();
+        if len > 60 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("options are too long: {} bytes", len - 20),
+            ));
+        }
+        let version_ihl = (self.version_ihl & 0xf0) | (len / 4) as u8;
+        let rewritten = !self.raw.is_empty() && self.raw[20..] != options[..];
+        if self.checksum == 0 || rewritten || version_ihl != self.version_ihl {
+            self.version_ihl = version_ihl;
+            self.checksum = self.compute_checksum()?;
+        }
+        w.write_all(&self.to_bytes()?)
+    }
+
+    pub fn compute_checksum(&self) -> io::Result<u16> {
+        let mut b = self.to_bytes()?;
+        b[10..12].copy_from_slice(&[0, 0]);
+        Ok(checksum::checksum(&b))
+    }
+
+    /// Decrements the TTL, updating the checksum incrementally unless it is
+    /// yet to be computed. Returns the new TTL, which is 0 if the datagram
+    /// must not go further.
+    pub fn decrement_ttl(&mut self) -> u8 {
+        // the TTL shares its 16-bit word with the protocol
+        let old = u16::from_be_bytes([self.ttl, self.protocol.into()]);
+        self.ttl = self.ttl.saturating_sub(1);
+        let new = u16::from_be_bytes([self.ttl, self.protocol.into()]);
+        if self.checksum != 0 {
+            self.checksum = checksum::update(self.checksum, old, new);
+        }
+        self.ttl
+    }
+
+    /// Whether the checksum matches the header as it was read, whose options
+    /// may not be written back the same, e.g. with what follows End of
+    /// Options List. Headers built here are checked as they are written.
+    pub fn is_checksum_valid(&self) -> bool {
+        if !self.raw.is_empty() {
+            return checksum::verify(&self.raw);
+        }
+        self.to_bytes().is_ok_and(|b| checksum::verify(&b))
+    }
+
+    // padded with End of Options List to 32 bits
+    fn options_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut v = Vec::new();
+        for option in &self.options {
+            option.write_to(&mut v)?;
+        }
+        while v.len() % 4 != 0 {
+            v.push(0);
+        }
+        Ok(v)
+    }
+
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut v = Vec::with_capacity(20);
+        v.push(self.version_ihl);
+        v.push(self.tos);
+        v.extend_from_slice(&self.length.to_be_bytes());
+        v.extend_from_slice(&self.identification.to_be_bytes());
+        v.extend_from_slice(&self.flags_offset.to_be_bytes());
+        v.push(self.ttl);
+        v.push(self.protocol.into());
+        v.extend_from_slice(&self.checksum.to_be_bytes());
+        v.extend_from_slice(&self.src_addr.0);
+        v.extend_from_slice(&self.dst_addr.0);
+        v.extend_from_slice(&self.options_bytes()?);
+        Ok(v)
     }
 }
 
 impl IPPayload {
+    pub fn read_from(protocol: Protocol, v: Vec<u8>) -> io::Result<Self> {
+        Ok(match protocol {
+            Protocol::Icmp => Self::Icmp(IcmpMessage::read_from(&mut Cursor::new(v))?),
+            _ => Self::Raw(protocol, v),
+        })
+    }
+
     pub fn write_to<W: Write>(self, w: &mut W) -> io::Result<()> {
         match self {
             Self::Icmp(icmp) => icmp.write_to(w),
-            Self::Raw(v) => w.write_all(&v),
+            Self::Raw(_, v) => w.write_all(&v),
+        }
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            Self::Icmp(icmp) => icmp.encoded_len(),
+            Self::Raw(_, v) => v.len(),
         }
     }
 
     pub fn protocol(&self) -> Protocol {
         match self {
             Self::Icmp(_) => Protocol::Icmp,
-            _ => panic!(),
+            Self::Raw(protocol, _) => *protocol,
         }
     }
 }
@@ -165,6 +330,9 @@ impl fmt::Display for IPHeader {
         writeln!(f, "  chs: 0x{:04x}", self.checksum)?;
         writeln!(f, "  src: {}", self.src_addr)?;
         write!(f, "  dst: {}", self.dst_addr)?;
+        for option in &self.options {
+            write!(f, "\n  opt: {}", option)?;
+        }
         Ok(())
     }
 }
@@ -180,3 +348,116 @@ impl fmt::Display for Protocol {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a header with bytes after End of Options List, which are not written
+    // back
+    fn header_with_padding() -> Vec<u8> {
+        let mut b = vec![
+            0x46, 0, 0, 24, 0, 1, 0, 0, 64, 1, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2, 0, 0xaa, 0xbb, 0xcc,
+        ];
+        let sum = checksum::checksum(&b);
+        b[10..12].copy_from_slice(&sum.to_be_bytes());
+        b
+    }
+
+    #[test]
+    fn checksum_is_verified_as_read() {
+        let b = header_with_padding();
+        let header = IPHeader::read_from(&mut Cursor::new(&b)).unwrap();
+        assert_eq!(header.options, vec![IPOption::EndOfList]);
+        assert_ne!(header.to_bytes().unwrap(), b);
+        assert!(header.is_checksum_valid());
+    }
+
+    #[test]
+    fn corrupted_header_is_invalid() {
+        let mut b = header_with_padding();
+        b[22] ^= 1;
+        let header = IPHeader::read_from(&mut Cursor::new(&b)).unwrap();
+        assert!(!header.is_checksum_valid());
+    }
+
+    #[test]
+    fn written_header_is_valid() {
+        let mut header = IPHeader::read_from(&mut Cursor::new(header_with_padding())).unwrap();
+        header.decrement_ttl();
+        let mut v = Vec::new();
+        header.write_to(&mut v).unwrap();
+        let header = IPHeader::read_from(&mut Cursor::new(v)).unwrap();
+        assert_eq!(header.ttl, 63);
+        assert!(header.is_checksum_valid());
+    }
+
+    #[test]
+    fn forwarded_header_keeps_its_checksum() {
+        let mut b = header_with_padding();
+        b[20..].copy_from_slice(&[1, 1, 1, 0]);
+        b[10..12].copy_from_slice(&[0, 0]);
+        let sum = checksum::checksum(&b);
+        b[10..12].copy_from_slice(&sum.to_be_bytes());
+
+        let mut header = IPHeader::read_from(&mut Cursor::new(&b)).unwrap();
+        header.decrement_ttl();
+        let updated = header.checksum;
+        let mut v = Vec::new();
+        header.write_to(&mut v).unwrap();
+        assert_eq!(v[8], 63);
+        assert_eq!(u16::from_be_bytes([v[10], v[11]]), updated);
+        assert!(checksum::verify(&v));
+    }
+
+    #[test]
+    fn headers_with_more_than_40_bytes_of_options_are_not_written() {
+        let mut header = IPHeader::read_from(&mut Cursor::new(header_with_padding())).unwrap();
+        let option = IPOption::RecordRoute {
+            pointer: 4,
+            route: vec![IPAddress([10, 0, 0, 1]); 5],
+        };
+        header.options = vec![option.clone()];
+        assert_eq!(header.header_len().unwrap(), 44);
+        header.options = vec![option.clone(), option];
+        let err = header.write_to(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn total_length_is_filled_in_as_written() {
+        let header = IPHeader::read_from(&mut Cursor::new(header_with_padding())).unwrap();
+        let datagram = IPDatagram {
+            header,
+            payload: IPPayload::Raw(Protocol::Udp, vec![7; 8]),
+        };
+        let mut v = Vec::new();
+        datagram.write_to(&mut v).unwrap();
+        assert_eq!(v.len(), 32);
+        assert_eq!(u16::from_be_bytes([v[2], v[3]]), 32);
+        assert!(checksum::verify(&v[..24]));
+    }
+
+    #[test]
+    fn datagrams_are_read_up_to_their_total_length() {
+        let mut b = header_with_padding();
+        b[3] = 28;
+        b[9] = 17;
+        b[10..12].copy_from_slice(&[0, 0]);
+        let sum = checksum::checksum(&b);
+        b[10..12].copy_from_slice(&sum.to_be_bytes());
+        b.extend_from_slice(&[1, 2, 3, 4, 0, 0]);
+
+        let datagram = IPDatagram::read_from(&mut Cursor::new(&b)).unwrap();
+        match datagram.payload {
+            IPPayload::Raw(_, data) => assert_eq!(data, [1, 2, 3, 4]),
+            payload => panic!("{:?}", payload),
+        }
+
+        let err = IPDatagram::read_from(&mut Cursor::new(&b[..26])).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        b[3] = 20;
+        let err = IPDatagram::read_from(&mut Cursor::new(&b)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
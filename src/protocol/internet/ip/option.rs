@@ -0,0 +1,367 @@
+use std::{
+    fmt,
+    io::{self, Write},
+};
+
+use byteorder::{BigEndian, WriteBytesExt};
+
+use crate::protocol::internet::address::IPAddress;
+
+// https://www.iana.org/assignments/ip-parameters/ip-parameters.xhtml
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IPOption {
+    EndOfList,
+    NoOperation,
+    RecordRoute {
+        pointer: u8,
+        route: Vec<IPAddress>,
+    },
+    // https://tools.ietf.org/html/rfc791 "Internet Timestamp"
+    Timestamp {
+        pointer: u8,
+        overflow: u8,
+        flags: u8,
+        /// With flags 0 the addresses are absent.
+        entries: Vec<(Option<IPAddress>, u32)>,
+    },
+    LooseSourceRoute {
+        pointer: u8,
+        route: Vec<IPAddress>,
+    },
+    StrictSourceRoute {
+        pointer: u8,
+        route: Vec<IPAddress>,
+    },
+    // https://tools.ietf.org/html/rfc2113
+    RouterAlert(u16),
+    Unknown {
+        typ: u8,
+        data: Vec<u8>,
+    },
+}
+
+impl IPOption {
+    /// Parses the options area of a header, which is padded to 32 bits.
+    pub fn read_all(b: &[u8]) -> io::Result<Vec<Self>> {
+        let mut options = Vec::new();
+        let mut i = 0;
+        while i < b.len() {
+            match b[i] {
+                0 => {
+                    options.push(Self::EndOfList);
+                    break;
+                }
+                1 => {
+                    options.push(Self::NoOperation);
+                    i += 1;
+                }
+                typ => {
+                    let len = *b
+                        .get(i + 1)
+                        .ok_or_else(|| invalid_data("truncated option"))?;
+                    let len = len as usize;
+                    if len < 2 || i + len > b.len() {
+                        return Err(invalid_data(&format!(
+                            "invalid length of option {}: {}",
+                            typ, len
+                        )));
+                    }
+                    options.push(Self::parse(typ, &b[i + 2..i + len])?);
+                    i += len;
+                }
+            }
+        }
+        Ok(options)
+    }
+
+    fn parse(typ: u8, data: &[u8]) -> io::Result<Self> {
+        Ok(match typ {
+            7 | 131 | 137 => {
+                let (pointer, route) = match data.split_first() {
+                    Some((&pointer, route)) if route.len().is_multiple_of(4) => {
+                        (pointer, addresses(route))
+                    }
+                    _ => return Err(invalid_data("invalid route option")),
+                };
+                match typ {
+                    7 => Self::RecordRoute { pointer, route },
+                    131 => Self::LooseSourceRoute { pointer, route },
+                    _ => Self::StrictSourceRoute { pointer, route },
+                }
+            }
+            68 => {
+                if data.len() < 2 {
+                    return Err(invalid_data("invalid timestamp option"));
+                }
+                let flags = data[1] & 0x0f;
+                let entry_len = if flags == 0 { 4 } else { 8 };
+                if !(data.len() - 2).is_multiple_of(entry_len) {
+                    return Err(invalid_data("invalid timestamp option"));
+                }
+                let entries = data[2..]
+                    .chunks(entry_len)
+                    .map(|c| {
+                        let (addr, ts) = c.split_at(entry_len - 4);
+                        let addr = (!addr.is_empty()).then(|| addresses(addr).remove(0));
+                        (addr, u32::from_be_bytes([ts[0], ts[1], ts[2], ts[3]]))
+                    })
+                    .collect();
+                Self::Timestamp {
+                    pointer: data[0],
+                    overflow: data[1] >> 4,
+                    flags,
+                    entries,
+                }
+            }
+            148 if data.len() == 2 => Self::RouterAlert(u16::from_be_bytes([data[0], data[1]])),
+            typ => Self::Unknown {
+                typ,
+                data: data.to_vec(),
+            },
+        })
+    }
+
+    pub fn typ(&self) -> u8 {
+        match self {
+            Self::EndOfList => 0,
+            Self::NoOperation => 1,
+            Self::RecordRoute { .. } => 7,
+            Self::Timestamp { .. } => 68,
+            Self::LooseSourceRoute { .. } => 131,
+            Self::StrictSourceRoute { .. } => 137,
+            Self::RouterAlert(_) => 148,
+            Self::Unknown { typ, .. } => *typ,
+        }
+    }
+
+    /// Whether the option is copied into every fragment.
+    pub fn is_copied(&self) -> bool {
+        self.typ() & 0x80 != 0
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut data = Vec::new();
+        match self {
+            Self::EndOfList | Self::NoOperation => return w.write_u8(self.typ()),
+            Self::RecordRoute { pointer, route }
+            | Self::LooseSourceRoute { pointer, route }
+            | Self::StrictSourceRoute { pointer, route } => {
+                data.write_u8(*pointer)?;
+                for addr in route {
+                    data.write_all(&addr.0)?;
+                }
+            }
+            Self::Timestamp {
+                pointer,
+                overflow,
+                flags,
+                entries,
+            } => {
+                data.write_u8(*pointer)?;
+                data.write_u8((overflow << 4) | (flags & 0x0f))?;
+                for (addr, ts) in entries {
+                    if let Some(addr) = addr {
+                        data.write_all(&addr.0)?;
+                    }
+                    data.write_u32::<BigEndian>(*ts)?;
+                }
+            }
+            Self::RouterAlert(v) => data.write_u16::<BigEndian>(*v)?,
+            Self::Unknown { data: v, .. } => data.write_all(v)?,
+        }
+        if data.len() > 38 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "option {} is too long: {} bytes",
+                    self.typ(),
+                    data.len() + 2
+                ),
+            ));
+        }
+        w.write_u8(self.typ())?;
+        w.write_u8(data.len() as u8 + 2)?;
+        w.write_all(&data)
+    }
+}
+
+fn addresses(b: &[u8]) -> Vec<IPAddress> {
+    b.chunks(4)
+        .map(|c| IPAddress([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn route(f: &mut fmt::Formatter<'_>, pointer: u8, route: &[IPAddress]) -> fmt::Result {
+    write!(f, " ptr={}", pointer)?;
+    for addr in route {
+        write!(f, " {}", addr)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for IPOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use IPOption::*;
+        match self {
+            EndOfList => write!(f, "End of Options List(0)"),
+            NoOperation => write!(f, "No Operation(1)"),
+            RecordRoute { pointer, route: r } => {
+                write!(f, "Record Route(7)")?;
+                route(f, *pointer, r)
+            }
+            Timestamp {
+                pointer,
+                overflow,
+                flags,
+                entries,
+            } => {
+                write!(
+                    f,
+                    "Timestamp(68) ptr={} oflw={} flg={}",
+                    pointer, overflow, flags
+                )?;
+                for (addr, ts) in entries {
+                    match addr {
+                        Some(addr) => write!(f, " {}@{}", addr, ts)?,
+                        None => write!(f, " {}", ts)?,
+                    }
+                }
+                Ok(())
+            }
+            LooseSourceRoute { pointer, route: r } => {
+                write!(f, "Loose Source Route(131)")?;
+                route(f, *pointer, r)
+            }
+            StrictSourceRoute { pointer, route: r } => {
+                write!(f, "Strict Source Route(137)")?;
+                route(f, *pointer, r)
+            }
+            RouterAlert(v) => write!(f, "Router Alert(148) {}", v),
+            Unknown { typ, data } => write!(f, "Unknown({}) {} bytes", typ, data.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_bytes(options: &[IPOption]) -> Vec<u8> {
+        let mut v = Vec::new();
+        for option in options {
+            option.write_to(&mut v).unwrap();
+        }
+        v
+    }
+
+    fn round_trip(b: &[u8]) -> Vec<IPOption> {
+        let options = IPOption::read_all(b).unwrap();
+        assert_eq!(to_bytes(&options), b);
+        options
+    }
+
+    #[test]
+    fn route_options_round_trip() {
+        let addrs = [10, 0, 0, 1, 10, 0, 0, 2];
+        for (typ, copied) in [(7, false), (131, true), (137, true)] {
+            let mut b = vec![typ, 11, 8];
+            b.extend_from_slice(&addrs);
+            let options = round_trip(&b);
+            assert_eq!(options.len(), 1);
+            assert_eq!(options[0].typ(), typ);
+            assert_eq!(options[0].is_copied(), copied);
+        }
+        assert_eq!(
+            IPOption::read_all(&[7, 11, 8, 10, 0, 0, 1, 10, 0, 0, 2]).unwrap(),
+            [IPOption::RecordRoute {
+                pointer: 8,
+                route: vec![IPAddress([10, 0, 0, 1]), IPAddress([10, 0, 0, 2])],
+            }]
+        );
+    }
+
+    #[test]
+    fn timestamps_round_trip_with_and_without_addresses() {
+        let options = round_trip(&[68, 12, 13, 0x10, 0, 0, 0, 1, 0, 0, 0, 2]);
+        assert_eq!(
+            options,
+            [IPOption::Timestamp {
+                pointer: 13,
+                overflow: 1,
+                flags: 0,
+                entries: vec![(None, 1), (None, 2)],
+            }]
+        );
+
+        for flags in [1, 3] {
+            let options = round_trip(&[68, 12, 5, flags, 10, 0, 0, 1, 0, 0, 0, 9]);
+            assert_eq!(
+                options,
+                [IPOption::Timestamp {
+                    pointer: 5,
+                    overflow: 0,
+                    flags,
+                    entries: vec![(Some(IPAddress([10, 0, 0, 1])), 9)],
+                }]
+            );
+        }
+    }
+
+    #[test]
+    fn no_operations_pad_and_end_of_list_ends() {
+        let options = IPOption::read_all(&[1, 148, 4, 0, 0, 0, 0xaa, 0xbb]).unwrap();
+        assert_eq!(
+            options,
+            [
+                IPOption::NoOperation,
+                IPOption::RouterAlert(0),
+                IPOption::EndOfList,
+            ]
+        );
+        assert_eq!(to_bytes(&options), [1, 148, 4, 0, 0, 0]);
+        assert!(options[1].is_copied());
+    }
+
+    #[test]
+    fn unknown_options_are_kept() {
+        let options = round_trip(&[30, 4, 1, 2]);
+        assert_eq!(
+            options,
+            [IPOption::Unknown {
+                typ: 30,
+                data: vec![1, 2],
+            }]
+        );
+    }
+
+    #[test]
+    fn bad_lengths_are_rejected() {
+        for b in [
+            &[7][..],
+            &[7, 0, 0, 0],
+            &[7, 1, 0, 0],
+            &[7, 12, 4, 10, 0, 0, 1],
+            &[7, 6, 4, 10, 0, 0],
+            &[68, 3, 5],
+            &[68, 8, 5, 1, 0, 0, 0, 1],
+        ] {
+            let err = IPOption::read_all(b).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{:?}", b);
+        }
+    }
+
+    #[test]
+    fn options_longer_than_the_header_allows_are_not_written() {
+        let option = |n| IPOption::RecordRoute {
+            pointer: 4,
+            route: vec![IPAddress([10, 0, 0, 1]); n],
+        };
+        assert_eq!(to_bytes(&[option(9)]).len(), 39);
+        let err = option(10).write_to(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -1,5 +1,15 @@
+pub mod acd;
 pub mod address;
+pub mod checksum;
+pub mod conntrack;
+pub mod firewall;
+pub mod fragment;
 pub mod icmp;
 pub mod interface;
 pub use interface::*;
 pub mod ip;
+pub mod nat;
+pub mod reassembly;
+pub mod route;
+pub mod stack;
+pub use stack::Stack;
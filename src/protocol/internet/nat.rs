@@ -0,0 +1,441 @@
+use std::{fmt, io, ops::RangeInclusive};
+
+use super::{
+    address::{IPAddress, Ipv4Cidr},
+    checksum,
+    conntrack::{Conntrack, Direction, Tuple},
+    icmp::IcmpData,
+    ip::{IPDatagram, IPHeader, IPPayload, Protocol},
+};
+
+// where ports are taken from when the original one is in use
+const PORTS: RangeInclusive<u16> = 32768..=60999;
+
+/// Rewrites the source of the connections that leave through `out_iface`.
+#[derive(Debug, Clone)]
+pub struct SnatRule {
+    pub out_iface: String,
+    /// Only connections from here are translated, or all if unspecified.
+    pub source: Option<Ipv4Cidr>,
+    /// The address to translate to. If unspecified, the address the
+    /// interface would send from is used, which is masquerading.
+    pub to_addr: Option<IPAddress>,
+}
+
+/// Source NAT for the connections tracked by `Conntrack`.
+#[derive(Debug, Default)]
+pub struct Nat {
+    rules: Vec<SnatRule>,
+    next_port: u16,
+}
+
+impl SnatRule {
+    pub fn masquerade(out_iface: String) -> Self {
+        Self {
+            out_iface,
+            source: None,
+            to_addr: None,
+        }
+    }
+
+    pub fn snat(out_iface: String, to_addr: IPAddress) -> Self {
+        Self {
+            out_iface,
+            source: None,
+            to_addr: Some(to_addr),
+        }
+    }
+
+    pub fn from(mut self, source: Ipv4Cidr) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    fn matches(&self, original: &Tuple, out_iface: &str) -> bool {
+        self.out_iface == out_iface
+            && self
+                .source
+                .as_ref()
+                .is_none_or(|p| p.contains(&original.src_addr))
+    }
+}
+
+impl Nat {
+    pub fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+            next_port: *PORTS.start(),
+        }
+    }
+
+    pub fn add_rule(&mut self, rule: SnatRule) {
+        self.rules.push(rule);
+    }
+
+    /// Removes the rules for `out_iface`. Translated connections stay so.
+    pub fn remove_rules(&mut self, out_iface: &str) {
+        self.rules.retain(|rule| rule.out_iface != out_iface);
+    }
+
+    pub fn rules(&self) -> &[SnatRule] {
+        &self.rules
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.rules.is_empty()
+    }
+
+    /// Translates a datagram leaving through `out_iface`, whose own address
+    /// for it is `iface_addr`. A new connection that matches a rule is bound
+    /// to an address and port first, keeping its port if it is free. Returns
+    /// false if the datagram must be dropped because no port was left.
+    pub fn outbound(
+        &mut self,
+        conntrack: &mut Conntrack,
+        datagram: &mut IPDatagram,
+        out_iface: &str,
+        iface_addr: &IPAddress,
+    ) -> io::Result<bool> {
+        let tuple = match Tuple::of(datagram) {
+            Some(tuple) => tuple,
+            None => {
+                // later fragments go like the first one, which had the ports
+                if let Some((conn, Direction::Original)) = conntrack.fragment(&datagram.header) {
+                    if conn.is_translated() {
+                        let addr = conn.reply.dst_addr.clone();
+                        set_addr(&mut datagram.header, true, &addr);
+                    }
+                // an error from inside about a reply, quoting its inner side
+                } else if let Some((conn, Direction::Original)) = conntrack.related(datagram) {
+                    if conn.is_translated() {
+                        let (addr, port) = (conn.reply.dst_addr.clone(), conn.reply.dst_port);
+                        rewrite_error(datagram, Direction::Original, &addr, port)?;
+                    }
+                }
+                return Ok(true);
+            }
+        };
+
+        let conn = match conntrack.get(&tuple) {
+            Some(conn) => conn,
+            // replies are translated on the way in
+            None => return Ok(true),
+        };
+        if !conn.is_translated()
+            && !conn.replied
+            && !self.bind(conntrack, &tuple, out_iface, iface_addr)
+        {
+            return Ok(false);
+        }
+
+        let conn = conntrack.get(&tuple).unwrap();
+        if conn.is_translated() {
+            let (addr, port) = (conn.reply.dst_addr.clone(), conn.reply.dst_port);
+            rewrite(datagram, Direction::Original, &addr, port);
+        }
+        Ok(true)
+    }
+
+    /// Restores the destination of replies and of ICMP errors about
+    /// translated connections, as they arrive.
+    pub fn inbound(&self, conntrack: &Conntrack, datagram: &mut IPDatagram) -> io::Result<()> {
+        match Tuple::of(datagram) {
+            Some(tuple) => {
+                if let Some((conn, Direction::Reply)) = conntrack.find(&tuple) {
+                    if conn.is_translated() {
+                        let (addr, port) = (conn.original.src_addr.clone(), conn.original.src_port);
+                        rewrite(datagram, Direction::Reply, &addr, port);
+                    }
+                }
+            }
+            None => {
+                if let Some((conn, Direction::Reply)) = conntrack.fragment(&datagram.header) {
+                    if conn.is_translated() {
+                        let addr = conn.original.src_addr.clone();
+                        set_addr(&mut datagram.header, false, &addr);
+                    }
+                } else if let Some((conn, Direction::Reply)) = conntrack.related(datagram) {
+                    if conn.is_translated() {
+                        let (addr, port) = (conn.original.src_addr.clone(), conn.original.src_port);
+                        rewrite_error(datagram, Direction::Reply, &addr, port)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn bind(
+        &mut self,
+        conntrack: &mut Conntrack,
+        original: &Tuple,
+        out_iface: &str,
+        iface_addr: &IPAddress,
+    ) -> bool {
+        let rule = match self.rules.iter().find(|r| r.matches(original, out_iface)) {
+            Some(rule) => rule,
+            None => return true,
+        };
+        let addr = rule.to_addr.clone().unwrap_or_else(|| iface_addr.clone());
+        let mut reply = original.reverse();
+        reply.dst_addr = addr;
+        if conntrack.set_reply(original, reply.clone()) {
+            return true;
+        }
+
+        // ICMP identifiers are all alike, but low ports are kept low
+        let range = match original.protocol {
+            Protocol::Tcp | Protocol::Udp if original.src_port < 1024 => 1..=1023,
+            Protocol::Tcp | Protocol::Udp => PORTS,
+            Protocol::Icmp => 1..=u16::MAX,
+            // without ports only the address tells connections apart
+            _ => return false,
+        };
+        let len = (*range.end() - *range.start()) as u32 + 1;
+        for _ in 0..len {
+            let port = if range.contains(&self.next_port) {
+                self.next_port
+            } else {
+                *range.start()
+            };
+            self.next_port = if port == *range.end() {
+                *range.start()
+            } else {
+                port + 1
+            };
+            reply.dst_port = port;
+            if original.protocol == Protocol::Icmp {
+                reply.src_port = port;
+            }
+            if conntrack.set_reply(original, reply.clone()) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+// the source of what goes the original way, and the destination of what
+// comes back
+fn rewrite(datagram: &mut IPDatagram, direction: Direction, addr: &IPAddress, port: u16) {
+    let src = direction == Direction::Original;
+    let header = &mut datagram.header;
+    let old_addr = if src {
+        header.src_addr.clone()
+    } else {
+        header.dst_addr.clone()
+    };
+    match &mut datagram.payload {
+        IPPayload::Icmp(icmp) => {
+            // the checksum of ICMP is computed as it is written
+            if let IcmpData::Echo { id, .. } = &mut icmp.data {
+                *id = port;
+            }
+        }
+        IPPayload::Raw(protocol, data) => {
+            rewrite_transport(*protocol, data, src, &old_addr, addr, port);
+        }
+    }
+    set_addr(header, src, addr);
+}
+
+// errors quote the datagram they are about, which went the other way
+fn rewrite_error(
+    datagram: &mut IPDatagram,
+    direction: Direction,
+    addr: &IPAddress,
+    port: u16,
+) -> io::Result<()> {
+    let src = direction == Direction::Original;
+    let quote = match &mut datagram.payload {
+        IPPayload::Icmp(icmp) => match icmp.original_mut() {
+            Some(quote) => quote,
+            None => return Ok(()),
+        },
+        _ => return Ok(()),
+    };
+
+    let mut r = io::Cursor::new(&quote[..]);
+    let mut header = IPHeader::read_from(&mut r)?;
+    let mut data = quote[r.position() as usize..].to_vec();
+    let old_addr = if src {
+        header.dst_addr.clone()
+    } else {
+        header.src_addr.clone()
+    };
+    rewrite_transport(header.protocol, &mut data, !src, &old_addr, addr, port);
+    set_addr(&mut header, !src, addr);
+
+    let mut v = Vec::new();
+    header.write_to(&mut v)?;
+    v.extend_from_slice(&data);
+    *quote = v;
+
+    set_addr(&mut datagram.header, src, addr);
+    Ok(())
+}
+
+fn set_addr(header: &mut IPHeader, src: bool, addr: &IPAddress) {
+    let old = if src {
+        &mut header.src_addr
+    } else {
+        &mut header.dst_addr
+    };
+    // one yet to be computed is left so
+    if header.checksum != 0 {
+        header.checksum = update_addr(header.checksum, old, addr);
+    }
+    *old = addr.clone();
+}
+
+// the ports, or the identifier of ICMP echo, and the checksum, which for TCP
+// and UDP covers the addresses through the pseudo header
+fn rewrite_transport(
+    protocol: Protocol,
+    data: &mut [u8],
+    src: bool,
+    old_addr: &IPAddress,
+    addr: &IPAddress,
+    port: u16,
+) {
+    let port_at = match protocol {
+        Protocol::Tcp | Protocol::Udp if src => 0,
+        Protocol::Tcp | Protocol::Udp => 2,
+        Protocol::Icmp => 4,
+        _ => return,
+    };
+    let checksum_at = match protocol {
+        Protocol::Tcp => 16,
+        Protocol::Udp => 6,
+        _ => 2,
+    };
+    if data.len() < port_at + 2 {
+        return;
+    }
+    let old_port = u16::from_be_bytes([data[port_at], data[port_at + 1]]);
+    data[port_at..port_at + 2].copy_from_slice(&port.to_be_bytes());
+
+    // quotes may end before the checksum
+    if data.len() < checksum_at + 2 {
+        return;
+    }
+    let mut sum = u16::from_be_bytes([data[checksum_at], data[checksum_at + 1]]);
+    // UDP may go without a checksum
+    if protocol == Protocol::Udp && sum == 0 {
+        return;
+    }
+    sum = checksum::update(sum, old_port, port);
+    if protocol != Protocol::Icmp {
+        sum = update_addr(sum, old_addr, addr);
+    }
+    if protocol == Protocol::Udp && sum == 0 {
+        sum = 0xffff;
+    }
+    data[checksum_at..checksum_at + 2].copy_from_slice(&sum.to_be_bytes());
+}
+
+fn update_addr(sum: u16, old: &IPAddress, new: &IPAddress) -> u16 {
+    let sum = checksum::update(
+        sum,
+        u16::from_be_bytes([old.0[0], old.0[1]]),
+        u16::from_be_bytes([new.0[0], new.0[1]]),
+    );
+    checksum::update(
+        sum,
+        u16::from_be_bytes([old.0[2], old.0[3]]),
+        u16::from_be_bytes([new.0[2], new.0[3]]),
+    )
+}
+
+impl fmt::Display for SnatRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(source) = &self.source {
+            write!(f, "from {} ", source)?;
+        }
+        write!(f, "out {} ", self.out_iface)?;
+        match &self.to_addr {
+            Some(addr) => write!(f, "snat to {}", addr),
+            None => write!(f, "masquerade"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::internet::ip::FLAG_MORE_FRAGMENTS;
+
+    // a fragment of a UDP datagram of 24 bytes, which has its ports in the
+    // first one
+    fn fragment(src_addr: [u8; 4], dst_addr: [u8; 4], first: bool) -> IPDatagram {
+        let data = if first {
+            vec![0x13, 0x88, 0, 53, 0, 24, 0x12, 0x34, 1, 2, 3, 4, 5, 6, 7, 8]
+        } else {
+            vec![9, 10, 11, 12, 13, 14, 15, 16]
+        };
+        IPDatagram {
+            header: IPHeader {
+                version_ihl: 0x45,
+                tos: 0,
+                length: 20 + data.len() as u16,
+                identification: 7,
+                flags_offset: if first { FLAG_MORE_FRAGMENTS } else { 2 },
+                ttl: 64,
+                protocol: Protocol::Udp,
+                checksum: 0,
+                src_addr: IPAddress(src_addr),
+                dst_addr: IPAddress(dst_addr),
+                options: Vec::new(),
+                raw: Vec::new(),
+            },
+            payload: IPPayload::Raw(Protocol::Udp, data),
+        }
+    }
+
+    fn data(datagram: &IPDatagram) -> &[u8] {
+        match &datagram.payload {
+            IPPayload::Raw(_, data) => data,
+            payload => panic!("{:?}", payload),
+        }
+    }
+
+    #[test]
+    fn later_fragments_are_translated_like_the_first() {
+        let mut conntrack = Conntrack::new();
+        let mut nat = Nat::new();
+        let public = IPAddress([192, 0, 2, 1]);
+        nat.add_rule(SnatRule::snat("wan".into(), public.clone()));
+        let (client, server) = ([10, 0, 0, 9], [198, 51, 100, 1]);
+
+        let mut sent = Vec::new();
+        for first in [true, false] {
+            let mut datagram = fragment(client, server, first);
+            conntrack.track(&datagram);
+            let iface_addr = IPAddress([0; 4]);
+            assert!(nat
+                .outbound(&mut conntrack, &mut datagram, "wan", &iface_addr)
+                .unwrap());
+            sent.push(datagram);
+        }
+        assert_eq!(conntrack.connections().count(), 1);
+        for datagram in &sent {
+            assert_eq!(datagram.header.src_addr, public);
+        }
+        // the checksum in the first fragment covers the new address
+        assert_ne!(data(&sent[0])[6..8], [0x12, 0x34]);
+        assert_eq!(data(&sent[1]), data(&fragment(client, server, false)));
+
+        // and the reply goes back to the client
+        for first in [true, false] {
+            let mut datagram = fragment(server, public.0, first);
+            if let IPPayload::Raw(_, data) = &mut datagram.payload {
+                if first {
+                    data[0..4].copy_from_slice(&[0, 53, 0x13, 0x88]);
+                }
+            }
+            conntrack.track(&datagram);
+            nat.inbound(&conntrack, &mut datagram).unwrap();
+            assert_eq!(datagram.header.dst_addr, IPAddress(client));
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use std::{
+    collections::HashMap,
+    io,
+    time::{Duration, Instant},
+};
+
+use super::{
+    address::IPAddress,
+    ip::{IPDatagram, IPHeader, IPPayload, Protocol, FLAG_DONT_FRAGMENT},
+};
+
+// the payload of a datagram cannot reach beyond this
+const MAX_PAYLOAD: usize = 65535 - 20;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    src_addr: IPAddress,
+    dst_addr: IPAddress,
+    protocol: Protocol,
+    identification: u16,
+}
+
+#[derive(Debug)]
+struct Buffer {
+    // of the fragment at offset 0, once it has arrived
+    header: Option<IPHeader>,
+    first_len: usize,
+    data: Vec<u8>,
+    // received byte ranges, sorted and merged
+    ranges: Vec<(usize, usize)>,
+    total: Option<usize>,
+    created: Instant,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReassemblyStats {
+    pub reassembled: u64,
+    pub timeouts: u64,
+    /// Datagrams given up because fragments overlapped or disagreed.
+    pub invalid: u64,
+    /// Datagrams given up to stay within `max_memory`.
+    pub evicted: u64,
+}
+
+/// Puts fragmented datagrams back together.
+// https://tools.ietf.org/html/rfc815
+#[derive(Debug)]
+pub struct Reassembler {
+    buffers: HashMap<Key, Buffer>,
+    memory: usize,
+    stats: ReassemblyStats,
+    pub timeout: Duration,
+    /// The bytes all incomplete datagrams may take together.
+    pub max_memory: usize,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Buffer {
+    fn new(now: Instant) -> Self {
+        Self {
+            header: None,
+            first_len: 0,
+            data: Vec::new(),
+            ranges: Vec::new(),
+            total: None,
+            created: now,
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.total.is_some_and(|total| self.ranges == [(0, total)])
+    }
+
+    /// Adds the data at `start`. Fails if it conflicts with what arrived.
+    fn insert(&mut self, start: usize, data: &[u8], last: bool) -> Result<(), ()> {
+        let end = start + data.len();
+        if end > MAX_PAYLOAD || (!last && !data.len().is_multiple_of(8)) {
+            return Err(());
+        }
+        match self.total {
+            Some(total) if end > total || (last && end != total) => return Err(()),
+            None if last && self.ranges.last().is_some_and(|r| r.1 > end) => return Err(()),
+            _ => {}
+        }
+
+        for &(s, e) in &self.ranges {
+            if start < e && s < end {
+                // an exact duplicate is harmless, any other overlap is not
+                if start >= s && end <= e && self.data[start..end] == *data {
+                    return Ok(());
+                }
+                return Err(());
+            }
+        }
+
+        if self.data.len() < end {
+            self.data.resize(end, 0);
+        }
+        self.data[start..end].copy_from_slice(data);
+        if last {
+            self.total = Some(end);
+        }
+
+        self.ranges.push((start, end));
+        self.ranges.sort_unstable();
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(self.ranges.len());
+        for &(s, e) in &self.ranges {
+            match merged.last_mut() {
+                Some(last) if last.1 == s => last.1 = e,
+                _ => merged.push((s, e)),
+            }
+        }
+        self.ranges = merged;
+        Ok(())
+    }
+
+    // the first fragment as it arrived, for quoting in an ICMP error
+    fn first_fragment(&self) -> Option<IPDatagram> {
+        let header = self.header.clone()?;
+        let payload = IPPayload::Raw(header.protocol, self.data[..self.first_len].to_vec());
+        Some(IPDatagram { header, payload })
+    }
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self {
+            buffers: HashMap::new(),
+            memory: 0,
+            stats: ReassemblyStats::default(),
+            timeout: Duration::from_secs(60),
+            max_memory: 4 * 1024 * 1024,
+        }
+    }
+
+    pub fn stats(&self) -> &ReassemblyStats {
+        &self.stats
+    }
+
+    /// The bytes held by incomplete datagrams.
+    pub fn memory(&self) -> usize {
+        self.memory
+    }
+
+    /// Takes a datagram, and returns it whole once all its fragments have
+    /// arrived. A datagram that is not a fragment is returned as it is.
+    pub fn push(&mut self, datagram: IPDatagram) -> io::Result<Option<IPDatagram>> {
+        if !datagram.header.is_fragment() {
+            return Ok(Some(datagram));
+        }
+
+        let IPDatagram { header, payload } = datagram;
+        let mut data = Vec::new();
+        payload.write_to(&mut data)?;
+        let key = Key {
+            src_addr: header.src_addr.clone(),
+            dst_addr: header.dst_addr.clone(),
+            protocol: header.protocol,
+            identification: header.identification,
+        };
+
+        let now = Instant::now();
+        if !self.buffers.contains_key(&key) && !self.reserve(data.len()) {
+            self.stats.evicted += 1;
+            return Ok(None);
+        }
+        let buffer = self
+            .buffers
+            .entry(key.clone())
+            .or_insert_with(|| Buffer::new(now));
+
+        let start = header.offset() as usize * 8;
+        let last = !header.more_fragments();
+        let before = buffer.data.len();
+        if buffer.insert(start, &data, last).is_err() {
+            self.remove(&key);
+            self.stats.invalid += 1;
+            return Ok(None);
+        }
+        if start == 0 {
+            buffer.first_len = data.len();
+            buffer.header = Some(header);
+        }
+        let grown = buffer.data.len() - before;
+        self.memory += grown;
+
+        // the options of the first fragment leave less room for the payload
+        let room = match &buffer.header {
+            Some(header) => 65535 - header.header_len()?,
+            None => MAX_PAYLOAD,
+        };
+        if buffer.data.len() > room {
+            self.remove(&key);
+            self.stats.invalid += 1;
+            return Ok(None);
+        }
+
+        if !buffer.is_complete() {
+            if self.memory > self.max_memory {
+                // the datagram has grown beyond what is left
+                self.remove(&key);
+                self.stats.evicted += 1;
+            }
+            return Ok(None);
+        }
+
+        let buffer = self.remove(&key).unwrap();
+        self.stats.reassembled += 1;
+        let mut header = buffer.header.unwrap();
+        header.flags_offset &= FLAG_DONT_FRAGMENT;
+        header.length = (header.header_len()? + buffer.data.len()) as u16;
+        header.checksum = header.compute_checksum()?;
+        header.raw.clear();
+        let payload = IPPayload::read_from(header.protocol, buffer.data)?;
+        Ok(Some(IPDatagram { header, payload }))
+    }
+
+    /// Drops the datagrams that took too long, and returns the first
+    /// fragments of those that had one, to be reported with ICMP time
+    /// exceeded.
+    pub fn tick(&mut self) -> Vec<IPDatagram> {
+        let now = Instant::now();
+        let expired = self
+            .buffers
+            .iter()
+            .filter(|(_, buffer)| now - buffer.created >= self.timeout)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        let mut firsts = Vec::new();
+        for key in expired {
+            let buffer = self.remove(&key).unwrap();
+            self.stats.timeouts += 1;
+            firsts.extend(buffer.first_fragment());
+        }
+        firsts
+    }
+
+    // makes room for a new datagram by dropping the oldest ones
+    fn reserve(&mut self, len: usize) -> bool {
+        if len > self.max_memory {
+            return false;
+        }
+        while self.memory + len > self.max_memory {
+            let oldest = match self.buffers.iter().min_by_key(|(_, buffer)| buffer.created) {
+                Some((key, _)) => key.clone(),
+                None => break,
+            };
+            self.remove(&oldest);
+            self.stats.evicted += 1;
+        }
+        true
+    }
+
+    fn remove(&mut self, key: &Key) -> Option<Buffer> {
+        let buffer = self.buffers.remove(key)?;
+        self.memory -= buffer.data.len();
+        Some(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::internet::ip::{IPOption, FLAG_MORE_FRAGMENTS};
+
+    fn fragment(options: usize, offset: usize, len: usize, more: bool) -> IPDatagram {
+        let flags = if more { FLAG_MORE_FRAGMENTS } else { 0 };
+        IPDatagram {
+            header: IPHeader {
+                version_ihl: 0x45 + options as u8 / 4,
+                tos: 0,
+                length: 0,
+                identification: 1,
+                flags_offset: flags | (offset / 8) as u16,
+                ttl: 64,
+                protocol: Protocol::Udp,
+                checksum: 0,
+                src_addr: IPAddress([10, 0, 0, 1]),
+                dst_addr: IPAddress([10, 0, 0, 2]),
+                options: vec![IPOption::NoOperation; options],
+                raw: Vec::new(),
+            },
+            payload: IPPayload::Raw(Protocol::Udp, vec![0; len]),
+        }
+    }
+
+    #[test]
+    fn reassembles_the_longest_datagram() {
+        let mut reassembler = Reassembler::new();
+        assert!(reassembler
+            .push(fragment(0, 0, 65504, true))
+            .unwrap()
+            .is_none());
+        let datagram = reassembler.push(fragment(0, 65504, 11, false)).unwrap();
+        assert_eq!(datagram.unwrap().header.length, 65535);
+    }
+
+    #[test]
+    fn drops_a_datagram_too_long_for_its_options() {
+        let mut reassembler = Reassembler::new();
+        assert!(reassembler
+            .push(fragment(40, 0, 65464, true))
+            .unwrap()
+            .is_none());
+        assert!(reassembler
+            .push(fragment(0, 65464, 12, false))
+            .unwrap()
+            .is_none());
+        assert_eq!(reassembler.stats().invalid, 1);
+        assert_eq!(reassembler.memory(), 0);
+    }
+}
@@ -0,0 +1,231 @@
+use std::fmt;
+
+use super::address::{IPAddress, Ipv4Cidr};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub prefix: Ipv4Cidr,
+    /// The router to go through, or none if the prefix is on the link.
+    pub gateway: Option<IPAddress>,
+    /// The name of the interface to send through.
+    pub iface: String,
+    /// Among routes of the same length, the lowest metric wins.
+    pub metric: u32,
+}
+
+/// Chooses where datagrams go by the longest matching prefix.
+#[derive(Debug, Clone, Default)]
+pub struct RoutingTable {
+    routes: Vec<Route>,
+}
+
+impl Route {
+    /// A route to the hosts directly on the link of `iface`.
+    pub fn connected(prefix: Ipv4Cidr, iface: String) -> Self {
+        Self {
+            prefix: prefix.network(),
+            gateway: None,
+            iface,
+            metric: 0,
+        }
+    }
+
+    pub fn via(prefix: Ipv4Cidr, gateway: IPAddress, iface: String) -> Self {
+        Self {
+            prefix: prefix.network(),
+            gateway: Some(gateway),
+            iface,
+            metric: 0,
+        }
+    }
+
+    pub fn with_metric(mut self, metric: u32) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.prefix.len == 0
+    }
+
+    /// The address to resolve on the link to reach `dst_addr`.
+    pub fn next_hop(&self, dst_addr: &IPAddress) -> IPAddress {
+        self.gateway.clone().unwrap_or_else(|| dst_addr.clone())
+    }
+}
+
+impl RoutingTable {
+    pub fn new() -> Self {
+        Self { routes: Vec::new() }
+    }
+
+    /// Adds a route, replacing one with the same prefix, gateway and
+    /// interface.
+    pub fn add(&mut self, route: Route) {
+        self.routes.retain(|r| {
+            r.prefix != route.prefix || r.gateway != route.gateway || r.iface != route.iface
+        });
+        self.routes.push(route);
+    }
+
+    /// Removes the routes to `prefix`, and returns how many there were.
+    pub fn remove(&mut self, prefix: &Ipv4Cidr) -> usize {
+        let prefix = prefix.network();
+        let before = self.routes.len();
+        self.routes.retain(|r| r.prefix != prefix);
+        before - self.routes.len()
+    }
+
+    /// Keeps only the routes for which `f` returns true.
+    pub fn retain<F: FnMut(&Route) -> bool>(&mut self, f: F) {
+        self.routes.retain(f);
+    }
+
+    /// Removes the routes through `iface`, e.g. when it goes away.
+    pub fn remove_iface(&mut self, iface: &str) {
+        self.routes.retain(|r| r.iface != iface);
+    }
+
+    /// Replaces the default route. `None` removes it.
+    pub fn set_default(&mut self, gateway: Option<IPAddress>, iface: String) {
+        let default = Ipv4Cidr {
+            addr: IPAddress::UNSPECIFIED,
+            len: 0,
+        };
+        self.remove(&default);
+        if let Some(gateway) = gateway {
+            self.add(Route::via(default, gateway, iface));
+        }
+    }
+
+    pub fn default_route(&self) -> Option<&Route> {
+        self.routes
+            .iter()
+            .filter(|r| r.is_default())
+            .min_by_key(|r| r.metric)
+    }
+
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    /// The most specific route to `dst_addr`, the one with the lowest metric
+    /// among equally specific ones.
+    pub fn lookup(&self, dst_addr: &IPAddress) -> Option<&Route> {
+        self.routes
+            .iter()
+            .filter(|r| r.prefix.contains(dst_addr))
+            .min_by_key(|r| (u8::MAX - r.prefix.len, r.metric))
+    }
+}
+
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_default() {
+            write!(f, "default")?;
+        } else {
+            write!(f, "{}", self.prefix)?;
+        }
+        if let Some(gateway) = &self.gateway {
+            write!(f, " via {}", gateway)?;
+        }
+        write!(f, " dev {} metric {}", self.iface, self.metric)
+    }
+}
+
+impl fmt::Display for RoutingTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RoutingTable:")?;
+        for route in &self.routes {
+            write!(f, "\n  {}", route)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Ipv4Cidr {
+        s.parse().unwrap()
+    }
+
+    fn addr(s: &str) -> IPAddress {
+        s.parse().unwrap()
+    }
+
+    // the interface of the route to `dst_addr`
+    fn iface(table: &RoutingTable, dst_addr: &str) -> Option<String> {
+        table.lookup(&addr(dst_addr)).map(|r| r.iface.clone())
+    }
+
+    #[test]
+    fn the_longest_prefix_wins() {
+        let mut table = RoutingTable::new();
+        table.set_default(Some(addr("192.168.0.1")), "wan".into());
+        table.add(Route::via(cidr("10.0.0.0/8"), addr("10.1.0.1"), "a".into()));
+        table.add(Route::connected(cidr("10.1.2.3/24"), "b".into()));
+        table.add(Route::connected(cidr("10.1.2.128/25"), "c".into()));
+
+        assert_eq!(iface(&table, "10.1.2.200").as_deref(), Some("c"));
+        assert_eq!(iface(&table, "10.1.2.100").as_deref(), Some("b"));
+        assert_eq!(iface(&table, "10.9.9.9").as_deref(), Some("a"));
+        assert_eq!(iface(&table, "8.8.8.8").as_deref(), Some("wan"));
+
+        let route = table.lookup(&addr("10.9.9.9")).unwrap();
+        assert_eq!(route.next_hop(&addr("10.9.9.9")), addr("10.1.0.1"));
+        let route = table.lookup(&addr("10.1.2.100")).unwrap();
+        assert_eq!(route.prefix, cidr("10.1.2.0/24"));
+        assert_eq!(route.next_hop(&addr("10.1.2.100")), addr("10.1.2.100"));
+    }
+
+    #[test]
+    fn the_lowest_metric_wins_among_equal_prefixes() {
+        let mut table = RoutingTable::new();
+        table.add(Route::via(cidr("10.0.0.0/8"), addr("10.0.0.1"), "a".into()).with_metric(20));
+        table.add(Route::via(cidr("10.0.0.0/8"), addr("10.0.0.2"), "b".into()).with_metric(10));
+        table.add(Route::via(cidr("10.0.0.0/16"), addr("10.0.0.3"), "c".into()).with_metric(99));
+        assert_eq!(iface(&table, "10.1.0.1").as_deref(), Some("b"));
+        assert_eq!(iface(&table, "10.0.1.1").as_deref(), Some("c"));
+
+        // the same route again replaces the first one
+        table.add(Route::via(cidr("10.0.0.0/8"), addr("10.0.0.1"), "a".into()).with_metric(5));
+        assert_eq!(table.routes().len(), 3);
+        assert_eq!(iface(&table, "10.1.0.1").as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn removed_routes_are_no_longer_looked_up() {
+        let mut table = RoutingTable::new();
+        table.add(Route::connected(cidr("10.0.0.0/24"), "a".into()));
+        table.add(Route::via(
+            cidr("10.0.0.0/24"),
+            addr("10.1.0.1"),
+            "b".into(),
+        ));
+        table.add(Route::connected(cidr("10.1.0.0/24"), "b".into()));
+        table.set_default(Some(addr("10.1.0.1")), "b".into());
+
+        // any address of the prefix names it
+        assert_eq!(table.remove(&cidr("10.0.0.9/24")), 2);
+        assert_eq!(iface(&table, "10.0.0.9").as_deref(), Some("b"));
+        assert!(table.lookup(&addr("10.0.0.9")).unwrap().is_default());
+
+        table.set_default(None, "b".into());
+        assert!(table.default_route().is_none());
+        assert_eq!(iface(&table, "10.0.0.9"), None);
+
+        table.remove_iface("b");
+        assert!(table.routes().is_empty());
+    }
+
+    #[test]
+    fn nothing_is_found_without_a_route() {
+        let mut table = RoutingTable::new();
+        assert!(table.lookup(&addr("10.0.0.1")).is_none());
+        table.add(Route::connected(cidr("10.0.0.0/24"), "a".into()));
+        assert!(table.lookup(&addr("10.0.1.1")).is_none());
+        assert!(table.default_route().is_none());
+    }
+}
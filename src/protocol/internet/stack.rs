@@ -0,0 +1,840 @@
+use std::{io, time::Duration};
+
+use crate::protocol::physical::{self, Device};
+
+use super::{
+    address::{IPAddress, Ipv4Cidr},
+    conntrack::{Conntrack, State},
+    firewall::{self, Action, Chain, Firewall, Packet},
+    icmp::{IcmpMessage, RedirectCode, TimeExceededCode, UnreachableCode},
+    interface::{self, Interface},
+    ip::{IPDatagram, IPOption, IPPayload, Protocol},
+    nat::Nat,
+    route::{Route, RoutingTable},
+};
+
+/// Counters of forwarding.
+#[derive(Debug, Clone, Default)]
+pub struct ForwardStats {
+    pub forwarded: u64,
+    pub ttl_exceeded: u64,
+    pub no_route: u64,
+    pub redirects: u64,
+    /// Datagrams dropped because a device failed to send them.
+    pub tx_errors: u64,
+}
+
+/// Several interfaces sharing one routing table, for hosts with more than
+/// one link such as routers.
+#[derive(Default)]
+pub struct Stack {
+    ifaces: Vec<Interface>,
+    routes: RoutingTable,
+    forwarding: bool,
+    stats: ForwardStats,
+    conntrack: Conntrack,
+    nat: Nat,
+    firewall: Firewall,
+}
+
+impl Stack {
+    pub fn new() -> Self {
+        Self {
+            ifaces: Vec::new(),
+            routes: RoutingTable::new(),
+            forwarding: false,
+            stats: ForwardStats::default(),
+            conntrack: Conntrack::new(),
+            nat: Nat::new(),
+            firewall: Firewall::new(),
+        }
+    }
+
+    /// Adds an interface with the routes it has, which are kept in the
+    /// routing table of the stack from then on, and returns its index.
+    pub fn add_interface(&mut self, mut iface: Interface) -> usize {
+        for route in iface.take_routes().routes() {
+            self.routes.add(route.clone());
+        }
+        iface.set_forwarding(self.forwarding);
+        self.ifaces.push(iface);
+        self.ifaces.len() - 1
+    }
+
+    pub fn interfaces(&self) -> &[Interface] {
+        &self.ifaces
+    }
+
+    pub fn interface(&mut self, index: usize) -> &mut Interface {
+        &mut self.ifaces[index]
+    }
+
+    /// The index of the interface named `name`.
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.ifaces.iter().position(|iface| iface.name() == name)
+    }
+
+    pub fn routes(&mut self) -> &mut RoutingTable {
+        &mut self.routes
+    }
+
+    pub fn forwarding(&self) -> bool {
+        self.forwarding
+    }
+
+    /// Routes the datagrams that are not for us out of an interface, instead
+    /// of returning them from `recv`. Their fragments go on as they are.
+    pub fn set_forwarding(&mut self, forwarding: bool) {
+        self.forwarding = forwarding;
+        for iface in &mut self.ifaces {
+            iface.set_forwarding(forwarding);
+        }
+    }
+
+    pub fn stats(&self) -> &ForwardStats {
+        &self.stats
+    }
+
+    pub fn conntrack(&mut self) -> &mut Conntrack {
+        &mut self.conntrack
+    }
+
+    /// The source NAT of forwarded connections, which are tracked once it
+    /// has rules.
+    pub fn nat(&mut self) -> &mut Nat {
+        &mut self.nat
+    }
+
+    /// The filter of what comes in, goes through and goes out. Connections
+    /// are tracked once it filters anything.
+    pub fn firewall(&mut self) -> &mut Firewall {
+        &mut self.firewall
+    }
+
+    /// Adds a secondary address to an interface, and a route to its subnet.
+    pub fn add_addr(&mut self, index: usize, addr: Ipv4Cidr) {
+        let iface = &mut self.ifaces[index];
+        if iface.has_addr(&addr.addr) {
+            return;
+        }
+        self.routes
+            .add(Route::connected(addr.clone(), iface.name()));
+        iface.add_addr(addr);
+    }
+
+    /// Removes an address from an interface, and the route to its subnet
+    /// unless another address of the interface is in it.
+    pub fn remove_addr(&mut self, index: usize, addr: &IPAddress) -> io::Result<Ipv4Cidr> {
+        let iface = &mut self.ifaces[index];
+        let removed = iface.remove_addr(addr)?;
+        let network = removed.network();
+        if iface.addrs().iter().all(|p| p.network() != network) {
+            let name = iface.name();
+            self.routes
+                .retain(|r| r.prefix != network || r.gateway.is_some() || r.iface != name);
+        }
+        Ok(removed)
+    }
+
+    /// Sends what is off every subnet through `gateway` on an interface.
+    /// `None` removes the default route.
+    pub fn set_gateway(&mut self, gateway: Option<IPAddress>, index: usize) {
+        let name = self.ifaces[index].name();
+        self.routes.set_default(gateway, name);
+    }
+
+    /// Whether any interface has the address.
+    pub fn has_addr(&self, addr: &IPAddress) -> bool {
+        self.ifaces.iter().any(|iface| iface.has_addr(addr))
+    }
+
+    /// Whether datagrams to `addr` are for us, counting broadcasts and
+    /// multicasts.
+    pub fn is_local(&self, addr: &IPAddress) -> bool {
+        self.has_addr(addr)
+            || addr.is_broadcast()
+            || addr.is_multicast()
+            || self.is_subnet_broadcast(addr)
+    }
+
+    fn is_subnet_broadcast(&self, addr: &IPAddress) -> bool {
+        self.ifaces
+            .iter()
+            .any(|iface| iface.is_subnet_broadcast(addr))
+    }
+
+    /// The address to send to `dst_addr` from, which is on the interface of
+    /// its route. `None` if there is no route.
+    pub fn select_source(&self, dst_addr: &IPAddress) -> Option<IPAddress> {
+        let (index, next_hop) = self.route(dst_addr)?;
+        Some(self.ifaces[index].source_for(dst_addr, &next_hop))
+    }
+
+    /// Sends a datagram through the interface of the route to `dst_addr`,
+    /// from the address that suits it.
+    pub fn send(&mut self, dst_addr: IPAddress, payload: IPPayload) -> io::Result<()> {
+        let (index, next_hop) = self.route(&dst_addr).ok_or_else(|| no_route(&dst_addr))?;
+        let iface = &mut self.ifaces[index];
+        let src_addr = iface.source_for(&dst_addr, &next_hop);
+        let header = iface.header(src_addr, dst_addr, &payload);
+        let datagram = IPDatagram { header, payload };
+        self.filter_output(index, &datagram)?;
+        self.ifaces[index].send_via(next_hop, datagram)?;
+        self.flush();
+        Ok(())
+    }
+
+    /// Sends a datagram as it is through the interface of its route.
+    pub fn send_datagram(&mut self, datagram: IPDatagram) -> io::Result<()> {
+        self.output(datagram)?;
+        self.flush();
+        Ok(())
+    }
+
+    /// Runs the timers of every interface. `recv` calls this by itself.
+    pub fn poll(&mut self) -> io::Result<()> {
+        for iface in &mut self.ifaces {
+            iface.poll()?;
+        }
+        self.flush();
+        self.conntrack.tick();
+        Ok(())
+    }
+
+    fn output(&mut self, datagram: IPDatagram) -> io::Result<()> {
+        let dst_addr = &datagram.header.dst_addr;
+        let (index, next_hop) = self.route(dst_addr).ok_or_else(|| no_route(dst_addr))?;
+        self.filter_output(index, &datagram)?;
+        self.ifaces[index].send_via(next_hop, datagram)
+    }
+
+    // routes what the interfaces sent by themselves, such as the errors
+    // they report, which may queue more
+    fn flush(&mut self) {
+        loop {
+            let mut flushed = true;
+            for index in 0..self.ifaces.len() {
+                while let Some(datagram) = self.ifaces[index].next_outgoing() {
+                    flushed = false;
+                    let dst_addr = &datagram.header.dst_addr;
+                    match self
+                        .ifaces
+                        .iter()
+                        .position(|iface| iface.has_addr(dst_addr))
+                    {
+                        Some(owner) => self.ifaces[owner].deliver(datagram),
+                        None => {
+                            let result = self.output(datagram);
+                            self.drop_unsent(result);
+                        }
+                    }
+                }
+            }
+            if flushed {
+                return;
+            }
+        }
+    }
+
+    /// Returns the next datagram and the index of the interface it came in
+    /// through. With forwarding on, only those for us are returned.
+    pub fn recv(&mut self) -> io::Result<(usize, IPDatagram)> {
+        if self.ifaces.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the stack has no interfaces",
+            ));
+        }
+
+        loop {
+            self.conntrack.tick();
+            for index in 0..self.ifaces.len() {
+                let received = self.ifaces[index].recv_timeout(Some(Duration::from_secs(0)))?;
+                self.flush();
+                let mut datagram = match received {
+                    Some(datagram) => datagram,
+                    None => continue,
+                };
+                // https://tools.ietf.org/html/rfc1812#section-5.2.6
+                if self.forwarding && self.is_local(&datagram.header.dst_addr) {
+                    datagram = match self.ifaces[index].reassemble(datagram) {
+                        Some(datagram) => datagram,
+                        None => continue,
+                    };
+                }
+                let state = self.track(&datagram);
+                if self.nat.is_enabled() {
+                    self.nat.inbound(&self.conntrack, &mut datagram)?;
+                }
+                if !self.forwarding || self.is_local(&datagram.header.dst_addr) {
+                    let name = self.ifaces[index].name();
+                    let packet = Packet {
+                        datagram: &datagram,
+                        in_iface: Some(&name),
+                        out_iface: None,
+                        state,
+                    };
+                    match self.firewall.filter(Chain::Input, &packet) {
+                        Action::Accept => return Ok((index, datagram)),
+                        Action::Drop => {}
+                        Action::Reject => {
+                            let result = self.reject(datagram);
+                            self.drop_unsent(result);
+                        }
+                    }
+                    continue;
+                }
+                let result = self.forward(index, datagram, state);
+                self.drop_unsent(result);
+                self.flush();
+            }
+
+            // wake up for the ARP timers too
+            let timeout = self
+                .ifaces
+                .iter_mut()
+                .map(|iface| iface.arp_table().config().retrans_time)
+                .min();
+            let devs = self
+                .ifaces
+                .iter()
+                .map(|iface| iface as &dyn Device)
+                .collect::<Vec<_>>();
+            physical::select(&devs, timeout)?;
+        }
+    }
+
+    // https://tools.ietf.org/html/rfc1812#section-5.2
+    fn forward(
+        &mut self,
+        in_index: usize,
+        mut datagram: IPDatagram,
+        state: Option<State>,
+    ) -> io::Result<()> {
+        let src_addr = datagram.header.src_addr.clone();
+        let dst_addr = datagram.header.dst_addr.clone();
+        // what comes from no one in particular is not routed
+        if src_addr.is_unspecified() || self.is_local(&src_addr) {
+            return Ok(());
+        }
+
+        let (out_index, next_hop) = match self.route(&dst_addr) {
+            Some(route) => route,
+            None => {
+                self.stats.no_route += 1;
+                if !self.may_report(&datagram) {
+                    return Ok(());
+                }
+                let msg = IcmpMessage::unreachable(UnreachableCode::Net, datagram)?;
+                self.send_error(src_addr, msg);
+                return Ok(());
+            }
+        };
+        if datagram.header.ttl <= 1 {
+            self.stats.ttl_exceeded += 1;
+            if !self.may_report(&datagram) {
+                return Ok(());
+            }
+            let msg = IcmpMessage::time_exceeded(TimeExceededCode::Ttl, datagram)?;
+            self.send_error(src_addr, msg);
+            return Ok(());
+        }
+
+        let (in_name, out_name) = (self.ifaces[in_index].name(), self.ifaces[out_index].name());
+        let packet = Packet {
+            datagram: &datagram,
+            in_iface: Some(&in_name),
+            out_iface: Some(&out_name),
+            state,
+        };
+        match self.firewall.filter(Chain::Forward, &packet) {
+            Action::Accept => {}
+            Action::Drop => return Ok(()),
+            Action::Reject => return self.reject(datagram),
+        }
+
+        // the sender could have reached the next hop by itself
+        let source_routed = datagram.header.options.iter().any(|option| {
+            matches!(
+                option,
+                IPOption::LooseSourceRoute { .. } | IPOption::StrictSourceRoute { .. }
+            )
+        });
+        let on_link = self.ifaces[in_index]
+            .addrs()
+            .iter()
+            .any(|p| p.contains(&src_addr));
+        let redirect = out_index == in_index && on_link && !source_routed && next_hop != src_addr;
+        if redirect && self.may_report(&datagram) {
+            self.stats.redirects += 1;
+            let IPDatagram { header, payload } = datagram;
+            let protocol = payload.protocol();
+            let mut data = Vec::new();
+            payload.write_to(&mut data)?;
+            let quoted = IPDatagram {
+                header: header.clone(),
+                payload: IPPayload::Raw(protocol, data.clone()),
+            };
+            datagram = IPDatagram {
+                header,
+                payload: IPPayload::Raw(protocol, data),
+            };
+            let msg = IcmpMessage::redirect(RedirectCode::Host, next_hop.clone(), quoted)?;
+            self.send_error(src_addr, msg);
+        }
+
+        datagram.header.decrement_ttl();
+        if self.nat.is_enabled() {
+            let out = &self.ifaces[out_index];
+            let (name, iface_addr) = (out.name(), out.source_for(&dst_addr, &next_hop));
+            let translated =
+                self.nat
+                    .outbound(&mut self.conntrack, &mut datagram, &name, &iface_addr)?;
+            if !translated {
+                return Ok(());
+            }
+        }
+        self.ifaces[out_index].send_via(next_hop, datagram)?;
+        self.stats.forwarded += 1;
+        Ok(())
+    }
+
+    // the state of the connection a datagram belongs to, if connections are
+    // tracked
+    fn track(&mut self, datagram: &IPDatagram) -> Option<State> {
+        if !self.nat.is_enabled() && !self.firewall.is_enabled() {
+            return None;
+        }
+        match self.conntrack.track(datagram) {
+            Some((_, _, state)) => Some(state),
+            None => self.conntrack.related(datagram).map(|_| State::Related),
+        }
+    }
+
+    // what we send out of an interface, which the sender is told about if
+    // it is not accepted
+    fn filter_output(&mut self, index: usize, datagram: &IPDatagram) -> io::Result<()> {
+        let state = self.track(datagram);
+        let name = self.ifaces[index].name();
+        let packet = Packet {
+            datagram,
+            in_iface: None,
+            out_iface: Some(&name),
+            state,
+        };
+        match self.firewall.filter(Chain::Output, &packet) {
+            Action::Accept => Ok(()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "the firewall does not let out {}",
+                    packet.datagram.header.dst_addr
+                ),
+            )),
+        }
+    }
+
+    // TCP is answered with RST, and the rest with port unreachable
+    fn reject(&mut self, datagram: IPDatagram) -> io::Result<()> {
+        if !self.may_report(&datagram) {
+            return Ok(());
+        }
+        let src_addr = datagram.header.src_addr.clone();
+        if datagram.header.protocol != Protocol::Tcp {
+            let msg = IcmpMessage::unreachable(UnreachableCode::Port, datagram)?;
+            self.send_error(src_addr, msg);
+            return Ok(());
+        }
+        match firewall::tcp_reset(&datagram) {
+            Some(rst) => ignore_unsent(self.send_datagram(rst)),
+            None => Ok(()),
+        }
+    }
+
+    fn may_report(&self, datagram: &IPDatagram) -> bool {
+        interface::may_report(datagram, |addr| self.is_subnet_broadcast(addr))
+    }
+
+    fn send_error(&mut self, dst_addr: IPAddress, msg: IcmpMessage) {
+        let result = self.send(dst_addr, IPPayload::Icmp(msg));
+        self.drop_unsent(result);
+    }
+
+    // what fails to go out of one interface is dropped, so that the others
+    // keep working
+    fn drop_unsent(&mut self, result: io::Result<()>) {
+        if ignore_unsent(result).is_err() {
+            self.stats.tx_errors += 1;
+        }
+    }
+
+    // the interface and the address to resolve on its link
+    fn route(&self, dst_addr: &IPAddress) -> Option<(usize, IPAddress)> {
+        let route = self.routes.lookup(dst_addr)?;
+        let index = self.find(&route.iface)?;
+        Some((index, route.next_hop(dst_addr)))
+    }
+}
+
+// what we send about others' datagrams is dropped if it cannot be routed
+// back or the firewall keeps it in
+fn ignore_unsent(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(e)
+            if e.kind() == io::ErrorKind::NetworkUnreachable
+                || e.kind() == io::ErrorKind::PermissionDenied =>
+        {
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+fn no_route(dst_addr: &IPAddress) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NetworkUnreachable,
+        format!("no route to {}", dst_addr),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::net::UnixDatagram, time::Instant};
+
+    use super::*;
+    use crate::protocol::internet::{
+        checksum,
+        icmp::IcmpType,
+        interface::tests::{echo, iface, sent},
+        ip::{IPHeader, FLAG_MORE_FRAGMENTS},
+    };
+    use crate::protocol::link::{
+        address::MacAddress,
+        arp::Opcode,
+        ethernet::{EtherType, EthernetFrame, EthernetHeader, EthernetPayload},
+    };
+
+    fn datagram(src_addr: &str, dst_addr: &str, payload: IPPayload) -> IPDatagram {
+        let protocol = payload.protocol();
+        let header = IPHeader {
+            version_ihl: 0x45,
+            tos: 0,
+            length: (20 + payload.encoded_len()) as u16,
+            identification: 1,
+            flags_offset: 0,
+            ttl: 64,
+            protocol,
+            checksum: 0,
+            src_addr: src_addr.parse().unwrap(),
+            dst_addr: dst_addr.parse().unwrap(),
+            options: Vec::new(),
+            raw: Vec::new(),
+        };
+        IPDatagram { header, payload }
+    }
+
+    // a datagram arriving on the link of interface a
+    fn receive(peer: &UnixDatagram, datagram: IPDatagram) {
+        receive_on(peer, b'a', datagram)
+    }
+
+    fn receive_on(peer: &UnixDatagram, iface: u8, datagram: IPDatagram) {
+        let frame = EthernetFrame {
+            header: EthernetHeader {
+                dst_addr: MacAddress([2, 0, 0, 0, 0, iface]),
+                src_addr: MacAddress([2, 0, 0, 0, 0, 9]),
+                typ: EtherType::IPv4,
+            },
+            payload: EthernetPayload::IP(datagram),
+        };
+        let mut v = Vec::new();
+        frame.write_to(&mut v).unwrap();
+        peer.send(&v).unwrap();
+    }
+
+    fn stack() -> (Stack, UnixDatagram, UnixDatagram) {
+        let mut stack = Stack::new();
+        let (a, a_peer) = iface("a", "10.0.0.1");
+        let (b, b_peer) = iface("b", "10.1.0.1");
+        stack.add_interface(a);
+        stack.add_interface(b);
+        (stack, a_peer, b_peer)
+    }
+
+    #[test]
+    fn routes_are_kept_by_the_stack() {
+        let (mut stack, _a, _b) = stack();
+        assert!(stack.interface(0).routes().is_none());
+        assert_eq!(stack.routes().routes().len(), 2);
+    }
+
+    #[test]
+    fn set_gateway_replaces_the_default() {
+        let (mut stack, _a, _b) = stack();
+        stack.set_gateway(Some("10.0.0.254".parse().unwrap()), 0);
+        stack.set_gateway(Some("10.1.0.254".parse().unwrap()), 1);
+        let defaults = stack.routes().routes().iter().filter(|r| r.is_default());
+        let defaults = defaults.collect::<Vec<_>>();
+        assert_eq!(defaults.len(), 1);
+        assert_eq!(defaults[0].iface, "b");
+    }
+
+    #[test]
+    fn add_addr_ignores_a_duplicate() {
+        let (mut stack, _a, _b) = stack();
+        stack.add_addr(0, "10.0.0.1/16".parse().unwrap());
+        assert_eq!(stack.routes().routes().len(), 2);
+        assert_eq!(stack.interfaces()[0].addrs().len(), 1);
+    }
+
+    #[test]
+    fn interfaces_send_by_the_routes_of_the_stack() {
+        let (mut stack, a_peer, b_peer) = stack();
+        let prefix = "10.9.0.0/16".parse().unwrap();
+        let gateway: IPAddress = "10.1.0.254".parse().unwrap();
+        stack
+            .routes()
+            .add(Route::via(prefix, gateway.clone(), "b".into()));
+
+        stack
+            .interface(0)
+            .send("10.9.0.1".parse().unwrap(), echo())
+            .unwrap();
+        stack.poll().unwrap();
+        assert!(sent(&a_peer).is_empty());
+        let frames = sent(&b_peer);
+        assert_eq!(frames.len(), 1);
+        match &frames[0].payload {
+            EthernetPayload::Arp(arp) => {
+                assert_eq!(arp.opcode, Opcode::Request);
+                assert_eq!(arp.target_protocol_addr.as_ipv4(), Some(&gateway));
+            }
+            payload => panic!("not an ARP request: {:?}", payload),
+        }
+    }
+
+    #[test]
+    fn unresolvable_hosts_are_reported() {
+        let (mut stack, a_peer, _b_peer) = stack();
+        stack.set_forwarding(true);
+        let sender: IPAddress = "10.0.0.9".parse().unwrap();
+        let sender_mac = MacAddress([2, 0, 0, 0, 0, 9]);
+        stack
+            .interface(0)
+            .arp_table()
+            .insert_static(sender.clone(), sender_mac);
+
+        // the ping to the router returns once the other is forwarded
+        receive(&a_peer, datagram("10.0.0.9", "10.1.0.77", echo()));
+        receive(&a_peer, datagram("10.0.0.9", "10.0.0.1", echo()));
+        let (_, datagram) = stack.recv().unwrap();
+        assert_eq!(datagram.header.dst_addr.to_string(), "10.0.0.1");
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let error = loop {
+            assert!(Instant::now() < deadline, "no host unreachable");
+            std::thread::sleep(Duration::from_millis(10));
+            stack.poll().unwrap();
+            let error = sent(&a_peer)
+                .into_iter()
+                .find_map(|frame| match frame.payload {
+                    EthernetPayload::IP(ip) => Some(ip),
+                    _ => None,
+                });
+            if let Some(error) = error {
+                break error;
+            }
+        };
+        assert_eq!(error.header.dst_addr, sender);
+        match error.payload {
+            IPPayload::Icmp(icmp) => {
+                assert_eq!(icmp.typ, IcmpType::DestinationUnreachable);
+                assert_eq!(icmp.code, 1);
+            }
+            payload => panic!("not ICMP: {:?}", payload),
+        }
+    }
+
+    #[test]
+    fn icmp_is_forwarded_as_it_came() {
+        let (mut stack, a_peer, b_peer) = stack();
+        stack.set_forwarding(true);
+        stack
+            .interface(1)
+            .arp_table()
+            .insert_static("10.1.0.77".parse().unwrap(), MacAddress([2, 0, 0, 0, 0, 7]));
+
+        // an echo with data, and a type not parsed here with a checksum that
+        // is not ours to fix
+        let mut ping = vec![8, 0, 0, 0, 0, 1, 0, 1];
+        ping.extend(0..32);
+        let sum = checksum::checksum(&ping);
+        ping[2..4].copy_from_slice(&sum.to_be_bytes());
+        let other = vec![42, 1, 0x12, 0x34, 5, 6, 7];
+        for msg in [&ping, &other] {
+            let icmp = IcmpMessage::read_from(&mut &msg[..]).unwrap();
+            let payload = IPPayload::Icmp(icmp);
+            receive(&a_peer, datagram("10.0.0.9", "10.1.0.77", payload));
+        }
+        receive(&a_peer, datagram("10.0.0.9", "10.0.0.1", echo()));
+        stack.recv().unwrap();
+
+        let mut forwarded = Vec::new();
+        let mut buf = [0; 4096];
+        while let Ok(len) = b_peer.recv(&mut buf) {
+            forwarded.push(buf[14 + 20..len].to_vec());
+        }
+        assert_eq!(forwarded, [ping, other]);
+    }
+
+    #[test]
+    fn what_a_device_fails_to_send_is_dropped_and_counted() {
+        let (mut stack, a_peer, b_peer) = stack();
+        stack.set_forwarding(true);
+        stack
+            .interface(1)
+            .arp_table()
+            .insert_static("10.1.0.77".parse().unwrap(), MacAddress([2, 0, 0, 0, 0, 7]));
+        drop(b_peer);
+
+        receive(&a_peer, datagram("10.0.0.9", "10.1.0.77", echo()));
+        receive(&a_peer, datagram("10.0.0.9", "10.0.0.1", echo()));
+        let (index, _) = stack.recv().unwrap();
+        assert_eq!(index, 0);
+        assert_eq!(stack.stats().forwarded, 0);
+        assert_eq!(stack.stats().tx_errors, 1);
+    }
+
+    #[test]
+    fn only_fragments_for_us_are_reassembled() {
+        let (mut stack, a_peer, b_peer) = stack();
+        stack.set_forwarding(true);
+        let next_hop: IPAddress = "10.1.0.77".parse().unwrap();
+        stack
+            .interface(1)
+            .arp_table()
+            .insert_static(next_hop, MacAddress([2, 0, 0, 0, 0, 7]));
+
+        let fragment = |dst_addr, flags_offset| {
+            let mut fragment = datagram(
+                "10.0.0.9",
+                dst_addr,
+                IPPayload::Raw(Protocol::Udp, vec![7; 8]),
+            );
+            fragment.header.flags_offset = flags_offset;
+            fragment
+        };
+        receive(&a_peer, fragment("10.1.0.77", FLAG_MORE_FRAGMENTS));
+        receive(&a_peer, fragment("10.0.0.1", FLAG_MORE_FRAGMENTS));
+        receive(&a_peer, fragment("10.0.0.1", 1));
+
+        let (_, datagram) = stack.recv().unwrap();
+        assert!(!datagram.header.is_fragment());
+        assert_eq!(datagram.header.length, 36);
+        let forwarded = sent(&b_peer)
+            .into_iter()
+            .find_map(|frame| match frame.payload {
+                EthernetPayload::IP(ip) => Some(ip),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(forwarded.header.flags_offset, FLAG_MORE_FRAGMENTS);
+        assert_eq!(forwarded.header.ttl, 63);
+    }
+
+    #[test]
+    fn fragments_of_replies_are_established() {
+        let (mut stack, a_peer, b_peer) = stack();
+        stack.set_forwarding(true);
+        let (client, server) = ("10.0.0.9", "10.1.0.77");
+        stack
+            .interface(0)
+            .arp_table()
+            .insert_static(client.parse().unwrap(), MacAddress([2, 0, 0, 0, 0, 9]));
+        stack
+            .interface(1)
+            .arp_table()
+            .insert_static(server.parse().unwrap(), MacAddress([2, 0, 0, 0, 0, 7]));
+        for rule in [
+            "forward accept in a",
+            "forward accept state established",
+            "forward drop",
+        ] {
+            stack
+                .firewall()
+                .add_rule(firewall::Rule::parse(rule).unwrap());
+        }
+
+        let udp = |src_addr, dst_addr, ports: [u8; 4], flags_offset| {
+            let mut data = ports.to_vec();
+            data.extend_from_slice(&[0, 16, 0, 0]);
+            let payload = IPPayload::Raw(Protocol::Udp, data);
+            let mut datagram = datagram(src_addr, dst_addr, payload);
+            datagram.header.flags_offset = flags_offset;
+            datagram
+        };
+        receive(&a_peer, udp(client, server, [4, 0, 0, 53], 0));
+        // the reply, whose later fragment starts with what is not a port
+        let first = udp(server, client, [0, 53, 4, 0], FLAG_MORE_FRAGMENTS);
+        receive_on(&b_peer, b'b', first);
+        receive_on(&b_peer, b'b', udp(server, client, [9, 9, 9, 9], 1));
+        receive_on(&b_peer, b'b', datagram(server, "10.1.0.1", echo()));
+        stack.recv().unwrap();
+
+        let forwarded = sent(&a_peer)
+            .into_iter()
+            .filter(|frame| matches!(frame.payload, EthernetPayload::IP(_)))
+            .count();
+        assert_eq!(forwarded, 2);
+        assert_eq!(stack.stats().forwarded, 3);
+    }
+
+    #[test]
+    fn rejected_broadcasts_are_not_reported() {
+        let (mut stack, a_peer, _b_peer) = stack();
+        let sender: IPAddress = "10.0.0.9".parse().unwrap();
+        stack
+            .interface(0)
+            .arp_table()
+            .insert_static(sender.clone(), MacAddress([2, 0, 0, 0, 0, 9]));
+        let rule = firewall::Rule::parse("input reject proto udp").unwrap();
+        stack.firewall().add_rule(rule);
+
+        let udp = || IPPayload::Raw(Protocol::Udp, vec![0; 8]);
+        receive(&a_peer, datagram("10.0.0.9", "10.0.0.255", udp()));
+        receive(&a_peer, datagram("10.0.0.9", "255.255.255.255", udp()));
+        receive(&a_peer, datagram("10.0.0.9", "10.0.0.1", udp()));
+        receive(&a_peer, datagram("10.0.0.9", "10.0.0.1", echo()));
+        stack.recv().unwrap();
+
+        let errors = sent(&a_peer)
+            .into_iter()
+            .filter_map(|frame| match frame.payload {
+                EthernetPayload::IP(ip) => Some(ip),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].header.dst_addr, sender);
+    }
+
+    #[test]
+    fn what_interfaces_send_is_filtered() {
+        let (mut stack, _a_peer, b_peer) = stack();
+        let rule = firewall::Rule::parse("output drop to 10.1.0.77").unwrap();
+        stack.firewall().add_rule(rule);
+        stack
+            .interface(0)
+            .send("10.1.0.77".parse().unwrap(), echo())
+            .unwrap();
+        stack.poll().unwrap();
+        assert!(sent(&b_peer).is_empty());
+
+        stack
+            .interface(0)
+            .send("10.1.0.78".parse().unwrap(), echo())
+            .unwrap();
+        stack.poll().unwrap();
+        assert_eq!(sent(&b_peer).len(), 1);
+    }
+}
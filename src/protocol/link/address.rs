@@ -1,11 +1,10 @@
 use std::{
     fmt,
     io::{self, Read},
+    str::FromStr,
 };
 
-use byteorder::ReadBytesExt;
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct MacAddress(pub [u8; 6]);
 
 impl MacAddress {
@@ -15,9 +14,7 @@ impl MacAddress {
 
     pub fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
         let mut b = [0; 6];
-        for i in 0..6 {
-            b[i] = r.read_u8()?;
-        }
+        r.read_exact(&mut b)?;
         Ok(Self(b))
     }
 }
@@ -35,3 +32,30 @@ impl fmt::Display for MacAddress {
         )
     }
 }
+
+impl FromStr for MacAddress {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid MAC address: {}", s),
+            )
+        };
+
+        let mut b = [0; 6];
+        let mut octets = s.split([':', '-']);
+        for x in b.iter_mut() {
+            let octet = octets.next().ok_or_else(invalid)?;
+            if octet.is_empty() || octet.len() > 2 {
+                return Err(invalid());
+            }
+            *x = u8::from_str_radix(octet, 16).map_err(|_| invalid())?;
+        }
+        if octets.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Self(b))
+    }
+}
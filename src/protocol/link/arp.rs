@@ -1,5 +1,12 @@
+mod persist;
+pub mod proxy;
+pub mod rarp;
 pub mod table;
+pub mod watch;
+pub use proxy::*;
+pub use rarp::*;
 pub use table::*;
+pub use watch::*;
 
 use std::{
     fmt,
@@ -8,10 +15,7 @@ use std::{
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
-use crate::protocol::{
-    internet::{address::IPAddress, ip},
-    link::ethernet::EtherType,
-};
+use crate::protocol::{internet::address::IPAddress, link::ethernet::EtherType};
 
 use super::address::MacAddress;
 
@@ -23,13 +27,13 @@ pub struct Arp {
     pub hardware_len: u8,
     pub protocol_len: u8,
     pub opcode: Opcode,
-    pub sender_hardware_addr: MacAddress,
-    pub sender_protocol_addr: IPAddress,
-    pub target_hardware_addr: MacAddress,
-    pub target_protocol_addr: IPAddress,
+    pub sender_hardware_addr: HardwareAddress,
+    pub sender_protocol_addr: ProtocolAddress,
+    pub target_hardware_addr: HardwareAddress,
+    pub target_protocol_addr: ProtocolAddress,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum HardwareType {
     Ethernet,
     Unknown(u16),
@@ -39,9 +43,26 @@ pub enum HardwareType {
 pub enum Opcode {
     Request,
     Reply,
+    // https://tools.ietf.org/html/rfc903
+    ReverseRequest,
+    ReverseReply,
     Unknown(u16),
 }
 
+/// A hardware address of `hardware_len` bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HardwareAddress {
+    Ethernet(MacAddress),
+    Unknown(Vec<u8>),
+}
+
+/// A protocol address of `protocol_len` bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolAddress {
+    IPv4(IPAddress),
+    Unknown(Vec<u8>),
+}
+
 impl Arp {
     pub fn new(
         opcode: Opcode,
@@ -56,39 +77,195 @@ impl Arp {
             hardware_len: 6,
             protocol_len: 4,
             opcode,
-            sender_hardware_addr,
-            sender_protocol_addr,
-            target_hardware_addr,
-            target_protocol_addr,
+            sender_hardware_addr: sender_hardware_addr.into(),
+            sender_protocol_addr: sender_protocol_addr.into(),
+            target_hardware_addr: target_hardware_addr.into(),
+            target_protocol_addr: target_protocol_addr.into(),
         }
     }
 
+    /// Asks for the protocol address of `mac_addr`.
+    pub fn reverse_request(sender_hardware_addr: MacAddress, mac_addr: MacAddress) -> Self {
+        Self::new(
+            Opcode::ReverseRequest,
+            sender_hardware_addr,
+            IPAddress::UNSPECIFIED,
+            mac_addr,
+            IPAddress::UNSPECIFIED,
+        )
+    }
+
     pub fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        let hardware_type = r.read_u16::<BigEndian>()?.into();
+        let protocol_type = r.read_u16::<BigEndian>()?.into();
+        let hardware_len = r.read_u8()?;
+        let protocol_len = r.read_u8()?;
+        Self::check_lengths(&hardware_type, &protocol_type, hardware_len, protocol_len)?;
+
+        let opcode = r.read_u16::<BigEndian>()?.into();
+        let sender_hardware_addr = HardwareAddress::read_from(r, &hardware_type, hardware_len)?;
+        let sender_protocol_addr = ProtocolAddress::read_from(r, &protocol_type, protocol_len)?;
+        let target_hardware_addr = HardwareAddress::read_from(r, &hardware_type, hardware_len)?;
+        let target_protocol_addr = ProtocolAddress::read_from(r, &protocol_type, protocol_len)?;
+
         Ok(Self {
-            hardware_type: r.read_u16::<BigEndian>()?.into(),
-            protocol_type: r.read_u16::<BigEndian>()?.into(),
-            hardware_len: r.read_u8()?,
-            protocol_len: r.read_u8()?,
-            opcode: r.read_u16::<BigEndian>()?.into(),
-            sender_hardware_addr: MacAddress::read_from(r)?,
-            sender_protocol_addr: IPAddress::read_from(r)?,
-            target_hardware_addr: MacAddress::read_from(r)?,
-            target_protocol_addr: IPAddress::read_from(r)?,
+            hardware_type,
+            protocol_type,
+            hardware_len,
+            protocol_len,
+            opcode,
+            sender_hardware_addr,
+            sender_protocol_addr,
+            target_hardware_addr,
+            target_protocol_addr,
         })
     }
 
     pub fn write_to<W: Write>(self, w: &mut W) -> io::Result<()> {
+        Self::check_lengths(
+            &self.hardware_type,
+            &self.protocol_type,
+            self.hardware_len,
+            self.protocol_len,
+        )?;
+        let hardware_addrs = [&self.sender_hardware_addr, &self.target_hardware_addr];
+        let protocol_addrs = [&self.sender_protocol_addr, &self.target_protocol_addr];
+        if hardware_addrs
+            .iter()
+            .any(|addr| addr.len() != self.hardware_len as usize)
+            || protocol_addrs
+                .iter()
+                .any(|addr| addr.len() != self.protocol_len as usize)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "address lengths do not match the header",
+            ));
+        }
+
         w.write_u16::<BigEndian>(self.hardware_type.into())?;
         w.write_u16::<BigEndian>(self.protocol_type.into())?;
-        w.write_u8(self.hardware_len.into())?;
-        w.write_u8(self.protocol_len.into())?;
+        w.write_u8(self.hardware_len)?;
+        w.write_u8(self.protocol_len)?;
         w.write_u16::<BigEndian>(self.opcode.into())?;
-        w.write_all(&self.sender_hardware_addr.0)?;
-        w.write_all(&self.sender_protocol_addr.0)?;
-        w.write_all(&self.target_hardware_addr.0)?;
-        w.write_all(&self.target_protocol_addr.0)?;
+        w.write_all(self.sender_hardware_addr.as_bytes())?;
+        w.write_all(self.sender_protocol_addr.as_bytes())?;
+        w.write_all(self.target_hardware_addr.as_bytes())?;
+        w.write_all(self.target_protocol_addr.as_bytes())?;
         Ok(())
     }
+
+    fn check_lengths(
+        hardware_type: &HardwareType,
+        protocol_type: &EtherType,
+        hardware_len: u8,
+        protocol_len: u8,
+    ) -> io::Result<()> {
+        let valid = hardware_len > 0
+            && protocol_len > 0
+            && (*hardware_type != HardwareType::Ethernet || hardware_len == 6)
+            && (*protocol_type != EtherType::IPv4 || protocol_len == 4);
+        if valid {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "invalid address lengths for {} and {}: hln={} pln={}",
+                hardware_type, protocol_type, hardware_len, protocol_len
+            ),
+        ))
+    }
+}
+
+impl HardwareAddress {
+    fn read_from<R: Read>(r: &mut R, typ: &HardwareType, len: u8) -> io::Result<Self> {
+        if *typ == HardwareType::Ethernet {
+            return Ok(Self::Ethernet(MacAddress::read_from(r)?));
+        }
+        let mut v = vec![0; len as usize];
+        r.read_exact(&mut v)?;
+        Ok(Self::Unknown(v))
+    }
+
+    pub fn as_mac(&self) -> Option<&MacAddress> {
+        match self {
+            Self::Ethernet(addr) => Some(addr),
+            Self::Unknown(_) => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Ethernet(addr) => &addr.0,
+            Self::Unknown(v) => v,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.as_bytes().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl ProtocolAddress {
+    fn read_from<R: Read>(r: &mut R, typ: &EtherType, len: u8) -> io::Result<Self> {
+        if *typ == EtherType::IPv4 {
+            return Ok(Self::IPv4(IPAddress::read_from(r)?));
+        }
+        let mut v = vec![0; len as usize];
+        r.read_exact(&mut v)?;
+        Ok(Self::Unknown(v))
+    }
+
+    pub fn as_ipv4(&self) -> Option<&IPAddress> {
+        match self {
+            Self::IPv4(addr) => Some(addr),
+            Self::Unknown(_) => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::IPv4(addr) => &addr.0,
+            Self::Unknown(v) => v,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.as_bytes().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl From<MacAddress> for HardwareAddress {
+    fn from(v: MacAddress) -> Self {
+        Self::Ethernet(v)
+    }
+}
+
+impl From<IPAddress> for ProtocolAddress {
+    fn from(v: IPAddress) -> Self {
+        Self::IPv4(v)
+    }
+}
+
+impl PartialEq<MacAddress> for HardwareAddress {
+    fn eq(&self, other: &MacAddress) -> bool {
+        self.as_mac() == Some(other)
+    }
+}
+
+impl PartialEq<IPAddress> for ProtocolAddress {
+    fn eq(&self, other: &IPAddress) -> bool {
+        self.as_ipv4() == Some(other)
+    }
 }
 
 impl From<u16> for HardwareType {
@@ -114,6 +291,8 @@ impl From<u16> for Opcode {
         match v {
             1 => Self::Request,
             2 => Self::Reply,
+            3 => Self::ReverseRequest,
+            4 => Self::ReverseReply,
             x => Self::Unknown(x),
         }
     }
@@ -124,6 +303,8 @@ impl From<Opcode> for u16 {
         match v {
             Opcode::Request => 1,
             Opcode::Reply => 2,
+            Opcode::ReverseRequest => 3,
+            Opcode::ReverseReply => 4,
             Opcode::Unknown(x) => x,
         }
     }
@@ -161,7 +342,129 @@ impl fmt::Display for Opcode {
         match self {
             Request => write!(f, "Request(0x1)"),
             Reply => write!(f, "Reply(0x2)"),
+            ReverseRequest => write!(f, "Reverse Request(0x3)"),
+            ReverseReply => write!(f, "Reverse Reply(0x4)"),
             Unknown(x) => write!(f, "Unknown(0x{:x})", x),
         }
     }
 }
+
+impl fmt::Display for HardwareAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ethernet(addr) => write!(f, "{}", addr),
+            Self::Unknown(v) => write!(f, "{}", hex(v)),
+        }
+    }
+}
+
+impl fmt::Display for ProtocolAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IPv4(addr) => write!(f, "{}", addr),
+            Self::Unknown(v) => write!(f, "{}", hex(v)),
+        }
+    }
+}
+
+fn hex(v: &[u8]) -> String {
+    v.iter()
+        .map(|i| format!("{:02x}", i))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn request() -> Arp {
+        Arp::new(
+            Opcode::Request,
+            MacAddress([2, 0, 0, 0, 0, 1]),
+            IPAddress([10, 0, 0, 1]),
+            MacAddress([0; 6]),
+            IPAddress([10, 0, 0, 2]),
+        )
+    }
+
+    fn read(b: &[u8]) -> io::Result<Arp> {
+        Arp::read_from(&mut Cursor::new(b))
+    }
+
+    fn to_bytes(arp: Arp) -> Vec<u8> {
+        let mut v = Vec::new();
+        arp.write_to(&mut v).unwrap();
+        v
+    }
+
+    // a header with the given types and lengths, and addresses of 1, 2, 3, 4
+    fn packet(htype: u16, ptype: u16, hlen: u8, plen: u8) -> Vec<u8> {
+        let mut v = htype.to_be_bytes().to_vec();
+        v.extend_from_slice(&ptype.to_be_bytes());
+        v.extend_from_slice(&[hlen, plen, 0, 1]);
+        for (x, len) in [(1, hlen), (2, plen), (3, hlen), (4, plen)] {
+            v.extend(std::iter::repeat_n(x, len as usize));
+        }
+        v
+    }
+
+    #[test]
+    fn ethernet_and_ipv4_round_trip() {
+        let b = to_bytes(request());
+        assert_eq!(b.len(), 28);
+        let arp = read(&b).unwrap();
+        assert_eq!(arp.opcode, Opcode::Request);
+        assert_eq!(arp.sender_hardware_addr, MacAddress([2, 0, 0, 0, 0, 1]));
+        assert_eq!(arp.target_protocol_addr, IPAddress([10, 0, 0, 2]));
+        assert_eq!(to_bytes(arp), b);
+    }
+
+    #[test]
+    fn other_address_lengths_are_read_by_the_header() {
+        // IEEE 802 hardware with 8 byte addresses, and IPv6
+        let b = packet(6, 0x86dd, 8, 16);
+        let arp = read(&b).unwrap();
+        assert_eq!(arp.hardware_type, HardwareType::Unknown(6));
+        assert_eq!(
+            arp.sender_hardware_addr,
+            HardwareAddress::Unknown(vec![1; 8])
+        );
+        assert_eq!(
+            arp.sender_protocol_addr,
+            ProtocolAddress::Unknown(vec![2; 16])
+        );
+        assert_eq!(arp.target_hardware_addr.as_mac(), None);
+        assert_eq!(arp.target_protocol_addr.as_ipv4(), None);
+        assert_eq!(to_bytes(arp), b);
+    }
+
+    #[test]
+    fn lengths_that_do_not_fit_the_types_are_rejected() {
+        for b in [
+            packet(1, 0x0800, 8, 4),
+            packet(1, 0x0800, 6, 16),
+            packet(6, 0x86dd, 0, 16),
+            packet(6, 0x86dd, 8, 0),
+        ] {
+            assert_eq!(read(&b).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn truncated_packets_are_rejected() {
+        let b = packet(6, 0x86dd, 8, 16);
+        let err = read(&b[..b.len() - 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn addresses_must_match_the_lengths_to_be_written() {
+        let mut arp = request();
+        arp.target_protocol_addr = ProtocolAddress::Unknown(vec![0; 16]);
+        let err = arp.write_to(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,373 @@
+use std::io::{self, BufRead, Read, Write};
+
+use crate::protocol::{internet::address::IPAddress, link::address::MacAddress};
+
+use super::{ArpState, ArpTable};
+
+// include/uapi/linux/if_arp.h
+const ATF_COM: u32 = 0x02;
+const ATF_PERM: u32 = 0x04;
+
+const PROC_HEADER: &str =
+    "IP address       HW type     Flags       HW address            Mask     Device";
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn parse_ip_addr(s: &str) -> io::Result<IPAddress> {
+    s.parse()
+        .map_err(|e: io::Error| invalid_data(e.to_string()))
+}
+
+fn parse_state(s: &str) -> io::Result<ArpState> {
+    match s {
+        "REACHABLE" => Ok(ArpState::Reachable),
+        "STALE" | "DELAY" | "PROBE" => Ok(ArpState::Stale),
+        "PERMANENT" => Ok(ArpState::Permanent),
+        _ => Err(invalid_data(format!("invalid state: {}", s))),
+    }
+}
+
+impl ArpTable {
+    /// Loads the entries of `device` (or of every device) in the format of
+    /// `/proc/net/arp`. Permanent entries stay permanent, and the others are
+    /// STALE so that they get verified before being relied on.
+    pub fn read_proc<R: BufRead>(&mut self, r: R, device: Option<&str>) -> io::Result<()> {
+        for line in r.lines() {
+            let line = line?;
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            // the header may have been left out
+            if fields.is_empty() || line.starts_with("IP address") {
+                continue;
+            }
+            if fields.len() != 6 {
+                return Err(invalid_data(format!("invalid line: {}", line)));
+            }
+            if device.is_some_and(|device| device != fields[5]) {
+                continue;
+            }
+
+            let flags = u32::from_str_radix(fields[2].trim_start_matches("0x"), 16)
+                .map_err(|_| invalid_data(format!("invalid flags: {}", fields[2])))?;
+            if flags & ATF_COM == 0 {
+                continue;
+            }
+
+            let ip_addr = parse_ip_addr(fields[0])?;
+            let mac_addr = fields[3]
+                .parse()
+                .map_err(|e: io::Error| invalid_data(e.to_string()))?;
+            let state = if flags & ATF_PERM != 0 {
+                ArpState::Permanent
+            } else {
+                ArpState::Stale
+            };
+            self.insert(ip_addr, mac_addr, state);
+        }
+        Ok(())
+    }
+
+    pub fn write_proc<W: Write>(&self, w: &mut W, device: &str) -> io::Result<()> {
+        writeln!(w, "{}", PROC_HEADER)?;
+        for (ip_addr, entry) in self.entries() {
+            let flags = match (&entry.mac_addr, entry.state) {
+                (_, ArpState::Permanent) => ATF_COM | ATF_PERM,
+                (Some(_), _) => ATF_COM,
+                (None, _) => 0,
+            };
+            let mac_addr = entry.mac_addr.clone().unwrap_or(MacAddress([0; 6]));
+            writeln!(
+                w,
+                "{:<16} 0x{:<10x}0x{:<10x}{:<17}     {:<8} {}",
+                ip_addr.to_string(),
+                1,
+                flags,
+                mac_addr.to_string(),
+                "*",
+                device
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Loads an array of `{"ip": .., "mac": .., "state": ..}` objects as
+    /// written by `write_json`. `state` is optional and defaults to STALE.
+    pub fn read_json<R: Read>(&mut self, r: &mut R) -> io::Result<()> {
+        let mut s = String::new();
+        r.read_to_string(&mut s)?;
+
+        for object in json::parse(&s)? {
+            let field = |key: &str| {
+                object
+                    .iter()
+                    .find(|(k, _)| k == key)
+                    .map(|(_, v)| v.as_str())
+            };
+
+            let ip_addr =
+                parse_ip_addr(field("ip").ok_or_else(|| invalid_data("missing ip".into()))?)?;
+            let mac_addr = field("mac")
+                .ok_or_else(|| invalid_data("missing mac".into()))?
+                .parse()
+                .map_err(|e: io::Error| invalid_data(e.to_string()))?;
+            let state = match field("state") {
+                Some(state) => parse_state(state)?,
+                None => ArpState::Stale,
+            };
+            self.insert(ip_addr, mac_addr, state);
+        }
+        Ok(())
+    }
+
+    /// Writes the resolved entries as a JSON array.
+    pub fn write_json<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let entries = self
+            .entries()
+            .filter_map(|(ip_addr, entry)| {
+                entry.mac_addr.as_ref().map(|mac_addr| {
+                    format!(
+                        "  {{\"ip\": \"{}\", \"mac\": \"{}\", \"state\": \"{}\"}}",
+                        ip_addr, mac_addr, entry.state
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+
+        if entries.is_empty() {
+            return writeln!(w, "[]");
+        }
+        writeln!(w, "[\n{}\n]", entries.join(",\n"))
+    }
+}
+
+// Just enough JSON for an array of objects with string values.
+mod json {
+    use std::{io, iter::Peekable, str::Chars};
+
+    use super::invalid_data;
+
+    pub type Object = Vec<(String, String)>;
+
+    pub fn parse(s: &str) -> io::Result<Vec<Object>> {
+        let mut chars = s.chars().peekable();
+        let mut objects = Vec::new();
+
+        expect(&mut chars, '[')?;
+        if !consume(&mut chars, ']') {
+            loop {
+                objects.push(parse_object(&mut chars)?);
+                if consume(&mut chars, ']') {
+                    break;
+                }
+                expect(&mut chars, ',')?;
+            }
+        }
+
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            Some(c) => Err(invalid_data(format!("unexpected '{}'", c))),
+            None => Ok(objects),
+        }
+    }
+
+    fn parse_object(chars: &mut Peekable<Chars>) -> io::Result<Object> {
+        let mut object = Vec::new();
+
+        expect(chars, '{')?;
+        if consume(chars, '}') {
+            return Ok(object);
+        }
+        loop {
+            let key = parse_string(chars)?;
+            expect(chars, ':')?;
+            let value = parse_string(chars)?;
+            object.push((key, value));
+
+            if consume(chars, '}') {
+                return Ok(object);
+            }
+            expect(chars, ',')?;
+        }
+    }
+
+    fn parse_string(chars: &mut Peekable<Chars>) -> io::Result<String> {
+        let mut s = String::new();
+
+        expect(chars, '"')?;
+        loop {
+            match chars.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => match chars.next() {
+                    Some(c @ '"') | Some(c @ '\\') | Some(c @ '/') => s.push(c),
+                    Some(c) => return Err(invalid_data(format!("unsupported escape '\\{}'", c))),
+                    None => break,
+                },
+                Some(c) => s.push(c),
+                None => break,
+            }
+        }
+        Err(invalid_data("unterminated string".into()))
+    }
+
+    fn skip_whitespace(chars: &mut Peekable<Chars>) {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+    }
+
+    fn consume(chars: &mut Peekable<Chars>, expected: char) -> bool {
+        skip_whitespace(chars);
+        if chars.peek() == Some(&expected) {
+            chars.next();
+            return true;
+        }
+        false
+    }
+
+    fn expect(chars: &mut Peekable<Chars>, expected: char) -> io::Result<()> {
+        if consume(chars, expected) {
+            return Ok(());
+        }
+        match chars.peek() {
+            Some(c) => Err(invalid_data(format!(
+                "expected '{}' but found '{}'",
+                expected, c
+            ))),
+            None => Err(invalid_data(format!(
+                "expected '{}' but reached the end",
+                expected
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn addr(x: u8) -> IPAddress {
+        IPAddress([10, 0, 0, x])
+    }
+
+    fn mac(x: u8) -> MacAddress {
+        MacAddress([2, 0, 0, 0, 0, x])
+    }
+
+    fn state(table: &ArpTable, x: u8) -> Option<ArpState> {
+        table.entry(&addr(x)).map(|entry| entry.state)
+    }
+
+    fn table() -> ArpTable {
+        let mut table = ArpTable::new();
+        table.insert(addr(1), mac(1), ArpState::Reachable);
+        table.insert_static(addr(2), mac(2));
+        // being resolved, so there is nothing to save
+        table.lookup(&addr(3));
+        table
+    }
+
+    fn read_json(s: &str) -> io::Result<ArpTable> {
+        let mut table = ArpTable::new();
+        table.read_json(&mut s.as_bytes())?;
+        Ok(table)
+    }
+
+    #[test]
+    fn json_round_trips() {
+        let mut v = Vec::new();
+        table().write_json(&mut v).unwrap();
+        let table = read_json(std::str::from_utf8(&v).unwrap()).unwrap();
+
+        assert_eq!(table.entries().count(), 2);
+        assert_eq!(table.get(&addr(1)), Some(mac(1)));
+        assert_eq!(state(&table, 1), Some(ArpState::Reachable));
+        assert_eq!(state(&table, 2), Some(ArpState::Permanent));
+    }
+
+    #[test]
+    fn json_states_default_to_stale() {
+        let table = read_json(r#"[{"ip": "10.0.0.1", "mac": "02:00:00:00:00:01"}]"#).unwrap();
+        assert_eq!(state(&table, 1), Some(ArpState::Stale));
+        assert_eq!(read_json(" [ ] ").unwrap().entries().count(), 0);
+    }
+
+    #[test]
+    fn malformed_json_is_rejected() {
+        for s in [
+            r#"[{"ip": "10.0.0.1", "mac": "02:00:00:00:00:01", "state": "GONE"}]"#,
+            r#"[{"ip": "10.0.0.1", "mac": "02:00:00:00:00:01""#,
+            r#"[{"ip": "10.0.0.1", "mac": "02:00:00:00:00"}]"#,
+            r#"[{"ip": "10.0.0.256", "mac": "02:00:00:00:00:01"}]"#,
+            r#"[{"mac": "02:00:00:00:00:01"}]"#,
+            r#"[{"ip": "10.0.0.1", "mac": "02:00:00:00:00:01"}] x"#,
+            r#"[{"ip": "10.0.0.1\q"}]"#,
+            "",
+        ] {
+            let err = read_json(s)
+                .err()
+                .unwrap_or_else(|| panic!("accepted {}", s));
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", s);
+        }
+    }
+
+    #[test]
+    fn proc_round_trips() {
+        let mut v = Vec::new();
+        table().write_proc(&mut v, "eth0").unwrap();
+        let s = String::from_utf8(v).unwrap();
+        assert_eq!(s.lines().next(), Some(PROC_HEADER));
+        assert_eq!(s.lines().count(), 4);
+
+        let mut table = ArpTable::new();
+        table.read_proc(Cursor::new(s), Some("eth0")).unwrap();
+        assert_eq!(table.entries().count(), 2);
+        assert_eq!(state(&table, 1), Some(ArpState::Stale));
+        assert_eq!(state(&table, 2), Some(ArpState::Permanent));
+        assert_eq!(table.get(&addr(2)), Some(mac(2)));
+    }
+
+    #[test]
+    fn proc_is_read_as_the_kernel_writes_it() {
+        let s = "IP address       HW type     Flags       HW address            Mask     Device
+10.0.0.1         0x1         0x2         02:00:00:00:00:01     *        eth0
+10.0.0.2         0x1         0x6         02:00:00:00:00:02     *        eth1
+10.0.0.3         0x1         0x0         00:00:00:00:00:00     *        eth0
+
+";
+        let mut table = ArpTable::new();
+        table.read_proc(Cursor::new(s), Some("eth0")).unwrap();
+        assert_eq!(table.entries().count(), 1);
+        assert_eq!(state(&table, 1), Some(ArpState::Stale));
+
+        let mut table = ArpTable::new();
+        table.read_proc(Cursor::new(s), None).unwrap();
+        assert_eq!(state(&table, 2), Some(ArpState::Permanent));
+        assert_eq!(state(&table, 3), None);
+    }
+
+    #[test]
+    fn proc_without_the_header_keeps_the_first_entry() {
+        let s = "10.0.0.1 0x1 0x2 02:00:00:00:00:01 * eth0\n";
+        let mut table = ArpTable::new();
+        table.read_proc(Cursor::new(s), None).unwrap();
+        assert_eq!(table.get(&addr(1)), Some(mac(1)));
+    }
+
+    #[test]
+    fn malformed_proc_lines_are_rejected() {
+        for line in [
+            "10.0.0.1 0x1 0x2 02:00:00:00:00:01 * eth0 extra",
+            "10.0.0.1 0x1 0xz 02:00:00:00:00:01 * eth0",
+            "10.0.0.1 0x1 0x2 02:00:00:00:00:0g * eth0",
+            "10.0.0 0x1 0x2 02:00:00:00:00:01 * eth0",
+        ] {
+            let s = format!("{}\n{}\n", PROC_HEADER, line);
+            let mut table = ArpTable::new();
+            let err = table.read_proc(Cursor::new(s), None).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", line);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use crate::protocol::internet::address::{IPAddress, Ipv4Cidr};
+
+/// Answers ARP requests on behalf of the hosts in `prefix`. If `sources` is
+/// not empty, only requests from those prefixes are answered.
+#[derive(Debug, Clone)]
+pub struct ProxyEntry {
+    pub prefix: Ipv4Cidr,
+    pub sources: Vec<Ipv4Cidr>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ProxyArp(Vec<ProxyEntry>);
+
+impl ProxyEntry {
+    pub fn new(prefix: Ipv4Cidr) -> Self {
+        Self {
+            prefix,
+            sources: Vec::new(),
+        }
+    }
+
+    pub fn allow(mut self, source: Ipv4Cidr) -> Self {
+        self.sources.push(source);
+        self
+    }
+
+    fn matches(&self, sender_addr: &IPAddress, target_addr: &IPAddress) -> bool {
+        self.prefix.contains(target_addr)
+            && (self.sources.is_empty() || self.sources.iter().any(|p| p.contains(sender_addr)))
+    }
+}
+
+impl ProxyArp {
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    pub fn add(&mut self, entry: ProxyEntry) {
+        self.0.push(entry);
+    }
+
+    pub fn remove(&mut self, prefix: &Ipv4Cidr) {
+        self.0.retain(|entry| &entry.prefix != prefix);
+    }
+
+    pub fn entries(&self) -> &[ProxyEntry] {
+        &self.0
+    }
+
+    pub fn answers(&self, sender_addr: &IPAddress, target_addr: &IPAddress) -> bool {
+        // a host never needs a proxy to reach itself
+        sender_addr != target_addr
+            && self
+                .0
+                .iter()
+                .any(|entry| entry.matches(sender_addr, target_addr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Ipv4Cidr {
+        s.parse().unwrap()
+    }
+
+    fn addr(s: &str) -> IPAddress {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn answers_for_the_hosts_of_the_prefix() {
+        let mut proxy = ProxyArp::new();
+        proxy.add(ProxyEntry::new(cidr("10.1.0.0/16")));
+        assert!(proxy.answers(&addr("10.0.0.9"), &addr("10.1.2.3")));
+        assert!(proxy.answers(&addr("10.0.0.9"), &addr("10.1.255.255")));
+        assert!(!proxy.answers(&addr("10.0.0.9"), &addr("10.2.0.1")));
+    }
+
+    #[test]
+    fn answers_only_the_allowed_sources() {
+        let mut proxy = ProxyArp::new();
+        proxy.add(
+            ProxyEntry::new(cidr("10.1.0.0/16"))
+                .allow(cidr("10.0.0.0/24"))
+                .allow(cidr("10.9.0.1/32")),
+        );
+        assert!(proxy.answers(&addr("10.0.0.9"), &addr("10.1.0.1")));
+        assert!(proxy.answers(&addr("10.9.0.1"), &addr("10.1.0.1")));
+        assert!(!proxy.answers(&addr("10.9.0.2"), &addr("10.1.0.1")));
+    }
+
+    #[test]
+    fn does_not_answer_a_host_for_itself() {
+        let mut proxy = ProxyArp::new();
+        proxy.add(ProxyEntry::new(cidr("10.1.0.0/16")));
+        assert!(!proxy.answers(&addr("10.1.0.1"), &addr("10.1.0.1")));
+        assert!(proxy.answers(&addr("10.1.0.2"), &addr("10.1.0.1")));
+    }
+
+    #[test]
+    fn removed_prefixes_are_not_answered() {
+        let mut proxy = ProxyArp::new();
+        proxy.add(ProxyEntry::new(cidr("10.1.0.0/16")));
+        proxy.add(ProxyEntry::new(cidr("10.2.0.0/16")));
+        // any address of the prefix names it
+        proxy.remove(&cidr("10.1.2.3/16"));
+        assert!(!proxy.answers(&addr("10.0.0.9"), &addr("10.1.0.1")));
+        assert!(proxy.answers(&addr("10.0.0.9"), &addr("10.2.0.1")));
+        assert_eq!(proxy.entries().len(), 1);
+    }
+}
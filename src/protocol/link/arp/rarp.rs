@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+
+use crate::protocol::{internet::address::IPAddress, link::address::MacAddress};
+
+use super::{Arp, Opcode};
+
+/// Answers RARP requests from a static table, like rarpd does with
+/// `/etc/ethers`.
+// https://tools.ietf.org/html/rfc903
+#[derive(Debug, Clone, Default)]
+pub struct RarpServer(HashMap<MacAddress, IPAddress>);
+
+impl RarpServer {
+    pub fn new() -> Self {
+        Self(HashMap::new())
+    }
+
+    pub fn insert(&mut self, mac_addr: MacAddress, ip_addr: IPAddress) {
+        self.0.insert(mac_addr, ip_addr);
+    }
+
+    pub fn remove(&mut self, mac_addr: &MacAddress) -> Option<IPAddress> {
+        self.0.remove(mac_addr)
+    }
+
+    pub fn get(&self, mac_addr: &MacAddress) -> Option<&IPAddress> {
+        self.0.get(mac_addr)
+    }
+
+    /// Builds the reply to `request` from the server at `mac_addr` and
+    /// `ip_addr`. The reply is to be sent to its target hardware address.
+    pub fn respond(
+        &self,
+        request: &Arp,
+        mac_addr: &MacAddress,
+        ip_addr: &IPAddress,
+    ) -> Option<Arp> {
+        if request.opcode != Opcode::ReverseRequest {
+            return None;
+        }
+
+        let target_addr = request.target_hardware_addr.as_mac()?;
+        let assigned_addr = self.get(target_addr)?;
+        Some(Arp::new(
+            Opcode::ReverseReply,
+            mac_addr.clone(),
+            ip_addr.clone(),
+            target_addr.clone(),
+            assigned_addr.clone(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::link::arp::HardwareAddress;
+
+    fn mac(x: u8) -> MacAddress {
+        MacAddress([2, 0, 0, 0, 0, x])
+    }
+
+    fn server() -> RarpServer {
+        let mut server = RarpServer::new();
+        server.insert(mac(2), IPAddress([10, 0, 0, 2]));
+        server
+    }
+
+    #[test]
+    fn known_hosts_are_given_their_address() {
+        let request = Arp::reverse_request(mac(2), mac(2));
+        let reply = server()
+            .respond(&request, &mac(1), &IPAddress([10, 0, 0, 1]))
+            .unwrap();
+        assert_eq!(reply.opcode, Opcode::ReverseReply);
+        assert_eq!(reply.sender_protocol_addr, IPAddress([10, 0, 0, 1]));
+        assert_eq!(reply.target_hardware_addr, mac(2));
+        assert_eq!(reply.target_protocol_addr, IPAddress([10, 0, 0, 2]));
+    }
+
+    #[test]
+    fn unknown_hosts_and_other_hardware_are_not_answered() {
+        let server = server();
+        let request = Arp::reverse_request(mac(3), mac(3));
+        assert!(server
+            .respond(&request, &mac(1), &IPAddress([10, 0, 0, 1]))
+            .is_none());
+
+        let mut request = Arp::reverse_request(mac(2), mac(2));
+        request.target_hardware_addr = HardwareAddress::Unknown(vec![2, 0, 0, 0, 0, 2, 0, 0]);
+        assert!(server
+            .respond(&request, &mac(1), &IPAddress([10, 0, 0, 1]))
+            .is_none());
+
+        let request = Arp::new(
+            Opcode::Request,
+            mac(2),
+            IPAddress([10, 0, 0, 2]),
+            mac(2),
+            IPAddress([10, 0, 0, 2]),
+        );
+        assert!(server
+            .respond(&request, &mac(1), &IPAddress([10, 0, 0, 1]))
+            .is_none());
+    }
+}
@@ -1,56 +1,537 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt, mem,
+    time::{Duration, Instant},
+};
 
 use crate::protocol::{
-    internet::{self, address::IPAddress},
-    link::{
-        self,
-        address::MacAddress,
-        arp,
-        ethernet::{EtherType, EthernetPayload},
-    },
+    internet::{address::IPAddress, ip::IPDatagram},
+    link::address::MacAddress,
 };
 
-use super::{Arp, HardwareType};
+// https://tools.ietf.org/html/rfc4861#section-7.3.2
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArpState {
+    Incomplete,
+    Reachable,
+    Stale,
+    Delay,
+    Probe,
+    Failed,
+    /// A static entry which never expires and is not overridden from the wire.
+    Permanent,
+}
+
+#[derive(Debug, Clone)]
+pub struct ArpConfig {
+    pub reachable_time: Duration,
+    pub delay_first_probe_time: Duration,
+    pub retrans_time: Duration,
+    pub ucast_probes: u32,
+    pub mcast_probes: u32,
+    pub gc_stale_time: Duration,
+    pub max_entries: usize,
+    pub unres_qlen: usize,
+}
 
 #[derive(Debug)]
-pub struct ArpTable(HashMap<IPAddress, MacAddress>);
+pub struct ArpEntry {
+    pub mac_addr: Option<MacAddress>,
+    pub state: ArpState,
+    pub updated: Instant,
+    pub used: Instant,
+    pub probes: u32,
+    pending: VecDeque<IPDatagram>,
+}
+
+#[derive(Debug)]
+pub enum ArpAction {
+    Broadcast(IPAddress),
+    Unicast(IPAddress, MacAddress),
+    /// Resolution failed; the datagrams that were waiting for it are returned.
+    Failed(IPAddress, Vec<IPDatagram>),
+}
+
+#[derive(Debug)]
+pub struct ArpTable {
+    entries: HashMap<IPAddress, ArpEntry>,
+    config: ArpConfig,
+}
+
+impl Default for ArpConfig {
+    // the defaults of net.ipv4.neigh.default.*
+    fn default() -> Self {
+        Self {
+            reachable_time: Duration::from_secs(30),
+            delay_first_probe_time: Duration::from_secs(5),
+            retrans_time: Duration::from_secs(1),
+            ucast_probes: 3,
+            mcast_probes: 3,
+            gc_stale_time: Duration::from_secs(60),
+            max_entries: 1024,
+            unres_qlen: 3,
+        }
+    }
+}
+
+impl ArpEntry {
+    fn new(mac_addr: Option<MacAddress>, state: ArpState) -> Self {
+        let now = Instant::now();
+        Self {
+            mac_addr,
+            state,
+            updated: now,
+            used: now,
+            probes: 0,
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn set_state(&mut self, state: ArpState) {
+        self.state = state;
+        self.updated = Instant::now();
+        self.probes = 0;
+    }
+}
+
+impl Default for ArpTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl ArpTable {
     pub fn new() -> Self {
-        Self(HashMap::new())
+        Self::with_config(ArpConfig::default())
     }
 
+    pub fn with_config(config: ArpConfig) -> Self {
+        Self {
+            entries: HashMap::new(),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &ArpConfig {
+        &self.config
+    }
+
+    pub fn entry(&self, addr: &IPAddress) -> Option<&ArpEntry> {
+        self.entries.get(addr)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&IPAddress, &ArpEntry)> {
+        self.entries.iter()
+    }
+
+    /// Returns the cached address without touching the state of the entry.
     pub fn get(&self, addr: &IPAddress) -> Option<MacAddress> {
-        self.0.get(addr).cloned()
+        match self.entries.get(addr) {
+            Some(entry) if entry.state != ArpState::Failed => entry.mac_addr.clone(),
+            _ => None,
+        }
     }
 
-    pub fn insert(&mut self, ip_addr: IPAddress, mac_addr: MacAddress) {
-        self.0.insert(ip_addr, mac_addr);
+    /// Looks up an address for sending a packet to it. An unknown or failed
+    /// address becomes INCOMPLETE and has to be solicited by the caller, and a
+    /// STALE entry is moved to DELAY so that it gets verified soon.
+    pub fn lookup(&mut self, addr: &IPAddress) -> Option<MacAddress> {
+        if !self.entries.contains_key(addr) && !self.reserve() {
+            // every entry is still being resolved or static
+            return None;
+        }
+        let now = Instant::now();
+        let reachable_time = self.config.reachable_time;
+
+        let entry = self
+            .entries
+            .entry(addr.clone())
+            .or_insert_with(|| ArpEntry::new(None, ArpState::Failed));
+        entry.used = now;
+
+        match entry.state {
+            ArpState::Failed => {
+                entry.mac_addr = None;
+                entry.set_state(ArpState::Incomplete);
+            }
+            ArpState::Reachable if now - entry.updated >= reachable_time => {
+                entry.set_state(ArpState::Delay);
+            }
+            ArpState::Stale => entry.set_state(ArpState::Delay),
+            _ => {}
+        }
+        entry.mac_addr.clone()
     }
 
-    pub fn find(&mut self, addr: IPAddress, iface: &mut internet::Interface) -> Option<MacAddress> {
-        if self.0.contains_key(&addr) {
-            return self.get(&addr);
+    /// Holds a datagram until `addr`, which must have been looked up before,
+    /// is resolved. The oldest datagram is dropped when the queue is full.
+    pub fn enqueue(&mut self, addr: &IPAddress, datagram: IPDatagram) {
+        let unres_qlen = self.config.unres_qlen;
+        if let Some(entry) = self.entries.get_mut(addr) {
+            if entry.pending.len() >= unres_qlen {
+                entry.pending.pop_front();
+            }
+            entry.pending.push_back(datagram);
         }
+    }
+
+    /// Records a mapping learned from the wire. `solicited` tells whether it
+    /// is the answer to our own request, which proves the neighbor reachable.
+    /// Returns the datagrams which were waiting for the address.
+    pub fn update(
+        &mut self,
+        ip_addr: IPAddress,
+        mac_addr: MacAddress,
+        solicited: bool,
+    ) -> Vec<IPDatagram> {
+        let state = if solicited {
+            ArpState::Reachable
+        } else {
+            ArpState::Stale
+        };
 
-        let payload = EthernetPayload::Arp(Arp::new(
-            arp::Opcode::Request,
-            iface.mac_addr().clone(),
-            iface.ip_addr().clone(),
-            MacAddress::broadcast(),
-            addr.clone(),
-        ));
+        if let Some(entry) = self.entries.get_mut(&ip_addr) {
+            let changed = entry.mac_addr.as_ref() != Some(&mac_addr);
+            let learned = entry.state != ArpState::Permanent;
+            if learned && (solicited || changed || entry.state == ArpState::Incomplete) {
+                entry.mac_addr = Some(mac_addr);
+                entry.set_state(state);
+            }
+            return entry.pending.drain(..).collect();
+        }
 
-        iface.link().send(MacAddress::broadcast(), payload).unwrap();
+        if self.reserve() {
+            self.insert(ip_addr, mac_addr, state);
+        }
+        Vec::new()
+    }
 
-        loop {
-            let frame = iface.link().recv().unwrap();
-            if let EthernetPayload::Arp(arp) = frame.payload {
-                if arp.opcode == arp::Opcode::Reply {
-                    self.insert(arp.sender_protocol_addr, arp.sender_hardware_addr);
-                    return self.get(&addr);
+    pub fn insert(&mut self, ip_addr: IPAddress, mac_addr: MacAddress, state: ArpState) {
+        if !self.entries.contains_key(&ip_addr) {
+            self.reserve();
+        }
+        self.entries
+            .insert(ip_addr, ArpEntry::new(Some(mac_addr), state));
+    }
+
+    pub fn insert_static(&mut self, ip_addr: IPAddress, mac_addr: MacAddress) {
+        self.insert(ip_addr, mac_addr, ArpState::Permanent);
+    }
+
+    /// Forward progress hint from an upper layer, e.g. a TCP ACK.
+    pub fn confirm(&mut self, addr: &IPAddress) {
+        if let Some(entry) = self.entries.get_mut(addr) {
+            if entry.mac_addr.is_some() && entry.state != ArpState::Permanent {
+                entry.set_state(ArpState::Reachable);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, addr: &IPAddress) -> Option<ArpEntry> {
+        self.entries.remove(addr)
+    }
+
+    /// Runs the timers of all entries and returns the solicitations to be
+    /// sent and the resolutions that failed. Should be called at least every
+    /// `retrans_time`.
+    pub fn tick(&mut self) -> Vec<ArpAction> {
+        let now = Instant::now();
+        let config = &self.config;
+        let mut actions = Vec::new();
+
+        for (addr, entry) in self.entries.iter_mut() {
+            let elapsed = now - entry.updated;
+            match entry.state {
+                ArpState::Reachable if elapsed >= config.reachable_time => {
+                    entry.set_state(ArpState::Stale);
                 }
+                ArpState::Delay if elapsed >= config.delay_first_probe_time => {
+                    entry.set_state(ArpState::Probe);
+                }
+                _ => {}
+            }
+
+            let max_probes = match entry.state {
+                ArpState::Incomplete => config.mcast_probes,
+                ArpState::Probe => config.ucast_probes,
+                _ => continue,
+            };
+
+            if entry.probes > 0 && now - entry.updated < config.retrans_time * entry.probes {
+                continue;
             }
+            if entry.probes >= max_probes {
+                entry.mac_addr = None;
+                entry.set_state(ArpState::Failed);
+                let pending = mem::take(&mut entry.pending);
+                actions.push(ArpAction::Failed(addr.clone(), pending.into()));
+                continue;
+            }
+
+            entry.probes += 1;
+            actions.push(match &entry.mac_addr {
+                Some(mac_addr) if entry.state == ArpState::Probe => {
+                    ArpAction::Unicast(addr.clone(), mac_addr.clone())
+                }
+                _ => ArpAction::Broadcast(addr.clone()),
+            });
+        }
+
+        actions
+    }
+
+    /// Removes FAILED entries and STALE entries which were not used for
+    /// `gc_stale_time`.
+    pub fn gc(&mut self) {
+        let now = Instant::now();
+        let gc_stale_time = self.config.gc_stale_time;
+        self.entries.retain(|_, entry| match entry.state {
+            ArpState::Failed => false,
+            ArpState::Stale => now - entry.used < gc_stale_time,
+            _ => true,
+        });
+    }
+
+    // makes room for a new entry, and tells whether there is some
+    fn reserve(&mut self) -> bool {
+        if self.entries.len() >= self.config.max_entries {
+            self.gc();
+            if self.entries.len() >= self.config.max_entries {
+                self.evict();
+            }
+        }
+        self.entries.len() < self.config.max_entries
+    }
+
+    fn evict(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| {
+                entry.state != ArpState::Incomplete && entry.state != ArpState::Permanent
+            })
+            .min_by_key(|(_, entry)| entry.used)
+            .map(|(addr, _)| addr.clone());
+        if let Some(addr) = oldest {
+            self.entries.remove(&addr);
+        }
+    }
+}
+
+impl fmt::Display for ArpState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ArpState::*;
+        match self {
+            Incomplete => write!(f, "INCOMPLETE"),
+            Reachable => write!(f, "REACHABLE"),
+            Stale => write!(f, "STALE"),
+            Delay => write!(f, "DELAY"),
+            Probe => write!(f, "PROBE"),
+            Failed => write!(f, "FAILED"),
+            Permanent => write!(f, "PERMANENT"),
+        }
+    }
+}
+
+impl fmt::Display for ArpTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ArpTable:")?;
+        for (addr, entry) in &self.entries {
+            match &entry.mac_addr {
+                Some(mac_addr) => write!(f, "\n  {} {} {}", addr, mac_addr, entry.state)?,
+                None => write!(
+                    f,
+                    "\n  {} - {} ({} pending)",
+                    addr,
+                    entry.state,
+                    entry.pending.len()
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::internet::ip::{IPHeader, IPPayload, Protocol};
+
+    fn addr(x: u8) -> IPAddress {
+        IPAddress([10, 0, 0, x])
+    }
+
+    fn mac(x: u8) -> MacAddress {
+        MacAddress([2, 0, 0, 0, 0, x])
+    }
+
+    fn datagram() -> IPDatagram {
+        IPDatagram {
+            header: IPHeader {
+                version_ihl: 0x45,
+                tos: 0,
+                length: 0,
+                identification: 0,
+                flags_offset: 0,
+                ttl: 64,
+                protocol: Protocol::Udp,
+                checksum: 0,
+                src_addr: addr(1),
+                dst_addr: addr(2),
+                options: Vec::new(),
+                raw: Vec::new(),
+            },
+            payload: IPPayload::Raw(Protocol::Udp, Vec::new()),
+        }
+    }
+
+    // every timer runs out at the first tick
+    fn table(max_entries: usize) -> ArpTable {
+        ArpTable::with_config(ArpConfig {
+            reachable_time: Duration::ZERO,
+            delay_first_probe_time: Duration::ZERO,
+            retrans_time: Duration::ZERO,
+            ucast_probes: 1,
+            mcast_probes: 1,
+            max_entries,
+            ..ArpConfig::default()
+        })
+    }
+
+    fn state(table: &ArpTable, x: u8) -> Option<ArpState> {
+        table.entry(&addr(x)).map(|entry| entry.state)
+    }
+
+    #[test]
+    fn reachable_entries_go_stale_and_are_probed_until_they_fail() {
+        let mut table = table(8);
+        table.update(addr(2), mac(2), true);
+        assert_eq!(state(&table, 2), Some(ArpState::Reachable));
+
+        assert!(table.tick().is_empty());
+        assert_eq!(state(&table, 2), Some(ArpState::Stale));
+
+        assert_eq!(table.lookup(&addr(2)), Some(mac(2)));
+        assert_eq!(state(&table, 2), Some(ArpState::Delay));
+
+        match table.tick().as_slice() {
+            [ArpAction::Unicast(ip_addr, mac_addr)] => {
+                assert_eq!((ip_addr, mac_addr), (&addr(2), &mac(2)))
+            }
+            actions => panic!("{:?}", actions),
         }
+        assert_eq!(state(&table, 2), Some(ArpState::Probe));
+
+        match table.tick().as_slice() {
+            [ArpAction::Failed(ip_addr, pending)] => {
+                assert_eq!(ip_addr, &addr(2));
+                assert!(pending.is_empty());
+            }
+            actions => panic!("{:?}", actions),
+        }
+        assert_eq!(state(&table, 2), Some(ArpState::Failed));
+        assert_eq!(table.get(&addr(2)), None);
+
+        table.gc();
+        assert_eq!(state(&table, 2), None);
+    }
+
+    #[test]
+    fn probing_reachable_entries_are_confirmed() {
+        let mut table = table(8);
+        table.update(addr(2), mac(2), false);
+        assert_eq!(state(&table, 2), Some(ArpState::Stale));
+        table.lookup(&addr(2));
+        table.tick();
+        assert_eq!(state(&table, 2), Some(ArpState::Probe));
+
+        table.update(addr(2), mac(2), true);
+        assert_eq!(state(&table, 2), Some(ArpState::Reachable));
+    }
+
+    #[test]
+    fn pending_datagrams_are_released_by_the_reply() {
+        let mut table = table(8);
+        assert_eq!(table.lookup(&addr(2)), None);
+        assert_eq!(state(&table, 2), Some(ArpState::Incomplete));
+        table.enqueue(&addr(2), datagram());
+
+        let pending = table.update(addr(2), mac(2), true);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(table.get(&addr(2)), Some(mac(2)));
+    }
+
+    #[test]
+    fn pending_datagrams_are_returned_when_resolution_fails() {
+        let mut table = table(8);
+        table.lookup(&addr(2));
+        table.enqueue(&addr(2), datagram());
+        assert!(matches!(table.tick().as_slice(), [ArpAction::Broadcast(_)]));
+        match table.tick().as_slice() {
+            [ArpAction::Failed(_, pending)] => assert_eq!(pending.len(), 1),
+            actions => panic!("{:?}", actions),
+        }
+    }
+
+    #[test]
+    fn lookups_do_not_grow_the_table_beyond_its_size() {
+        let mut table = table(2);
+        for x in 2..10 {
+            assert_eq!(table.lookup(&addr(x)), None);
+        }
+        assert_eq!(table.entries().count(), 2);
+        assert_eq!(state(&table, 2), Some(ArpState::Incomplete));
+        assert_eq!(state(&table, 9), None);
+    }
+
+    #[test]
+    fn the_least_recently_used_entry_is_evicted() {
+        let mut table = table(2);
+        table.update(addr(2), mac(2), false);
+        table.update(addr(3), mac(3), false);
+        table.entries.get_mut(&addr(3)).unwrap().used -= Duration::from_secs(1);
+
+        table.update(addr(4), mac(4), false);
+        assert_eq!(table.entries().count(), 2);
+        assert_eq!(state(&table, 3), None);
+        assert_eq!(state(&table, 4), Some(ArpState::Stale));
+    }
+
+    #[test]
+    fn failed_entries_are_collected_before_evicting() {
+        let mut table = table(2);
+        table.lookup(&addr(2));
+        table.tick();
+        table.tick();
+        assert_eq!(state(&table, 2), Some(ArpState::Failed));
+        table.update(addr(3), mac(3), false);
+
+        table.update(addr(4), mac(4), false);
+        assert_eq!(state(&table, 2), None);
+        assert_eq!(state(&table, 3), Some(ArpState::Stale));
+        assert_eq!(state(&table, 4), Some(ArpState::Stale));
+    }
+
+    #[test]
+    fn static_entries_are_kept() {
+        let mut table = table(1);
+        table.insert_static(addr(2), mac(2));
+
+        table.update(addr(2), mac(9), true);
+        table.confirm(&addr(2));
+        table.tick();
+        table.gc();
+        assert_eq!(state(&table, 2), Some(ArpState::Permanent));
+        assert_eq!(table.lookup(&addr(2)), Some(mac(2)));
+
+        // neither evicted for learned nor for looked up entries
+        table.update(addr(3), mac(3), false);
+        assert_eq!(table.lookup(&addr(4)), None);
+        assert_eq!(table.entries().count(), 1);
+        assert_eq!(state(&table, 2), Some(ArpState::Permanent));
     }
 }
@@ -0,0 +1,214 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::protocol::{internet::address::IPAddress, link::address::MacAddress};
+
+use super::{Arp, Opcode};
+
+#[derive(Debug, Clone)]
+pub struct Binding {
+    pub mac_addr: MacAddress,
+    pub previous: Option<MacAddress>,
+    pub first_seen: Instant,
+    pub last_seen: Instant,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArpEvent {
+    NewStation {
+        ip_addr: IPAddress,
+        mac_addr: MacAddress,
+    },
+    Changed {
+        ip_addr: IPAddress,
+        old: MacAddress,
+        new: MacAddress,
+    },
+    /// The address went back to the one it had before the last change.
+    FlipFlop {
+        ip_addr: IPAddress,
+        old: MacAddress,
+        new: MacAddress,
+    },
+    /// Both addresses were active within `duplicate_window`.
+    DuplicateIp {
+        ip_addr: IPAddress,
+        old: MacAddress,
+        new: MacAddress,
+    },
+    /// A reply which does not answer any request we saw.
+    UnsolicitedReply {
+        ip_addr: IPAddress,
+        mac_addr: MacAddress,
+        target_addr: IPAddress,
+    },
+}
+
+/// Tracks the IP to MAC bindings seen on the wire, like arpwatch does.
+#[derive(Debug)]
+pub struct ArpWatch {
+    bindings: HashMap<IPAddress, Binding>,
+    requests: HashMap<(IPAddress, IPAddress), Instant>,
+    pub duplicate_window: Duration,
+    pub request_timeout: Duration,
+}
+
+impl Default for ArpWatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ArpWatch {
+    pub fn new() -> Self {
+        Self {
+            bindings: HashMap::new(),
+            requests: HashMap::new(),
+            duplicate_window: Duration::from_secs(10),
+            request_timeout: Duration::from_secs(5),
+        }
+    }
+
+    pub fn bindings(&self) -> impl Iterator<Item = (&IPAddress, &Binding)> {
+        self.bindings.iter()
+    }
+
+    pub fn process(&mut self, arp: &Arp) -> Vec<ArpEvent> {
+        let now = Instant::now();
+        let mut events = Vec::new();
+
+        let request_timeout = self.request_timeout;
+        self.requests
+            .retain(|_, seen| now - *seen < request_timeout);
+
+        let (mac_addr, sender_addr, target_addr) = match (
+            arp.sender_hardware_addr.as_mac(),
+            arp.sender_protocol_addr.as_ipv4(),
+            arp.target_protocol_addr.as_ipv4(),
+        ) {
+            (Some(mac_addr), Some(sender_addr), Some(target_addr)) => {
+                (mac_addr, sender_addr, target_addr)
+            }
+            _ => return events,
+        };
+        match arp.opcode {
+            Opcode::Request => {
+                self.requests
+                    .insert((sender_addr.clone(), target_addr.clone()), now);
+            }
+            Opcode::Reply => {
+                let key = (target_addr.clone(), sender_addr.clone());
+                if self.requests.remove(&key).is_none() {
+                    events.push(ArpEvent::UnsolicitedReply {
+                        ip_addr: sender_addr.clone(),
+                        mac_addr: mac_addr.clone(),
+                        target_addr: target_addr.clone(),
+                    });
+                }
+            }
+            _ => return events,
+        }
+
+        // probes do not bind any address
+        if sender_addr.is_unspecified() {
+            return events;
+        }
+        events.extend(self.observe(sender_addr, mac_addr, now));
+        events
+    }
+
+    fn observe(
+        &mut self,
+        ip_addr: &IPAddress,
+        mac_addr: &MacAddress,
+        now: Instant,
+    ) -> Option<ArpEvent> {
+        let binding = match self.bindings.get_mut(ip_addr) {
+            Some(binding) => binding,
+            None => {
+                self.bindings.insert(
+                    ip_addr.clone(),
+                    Binding {
+                        mac_addr: mac_addr.clone(),
+                        previous: None,
+                        first_seen: now,
+                        last_seen: now,
+                    },
+                );
+                return Some(ArpEvent::NewStation {
+                    ip_addr: ip_addr.clone(),
+                    mac_addr: mac_addr.clone(),
+                });
+            }
+        };
+
+        if &binding.mac_addr == mac_addr {
+            binding.last_seen = now;
+            return None;
+        }
+
+        let old = binding.mac_addr.clone();
+        let new = mac_addr.clone();
+        let event = if now - binding.last_seen < self.duplicate_window {
+            ArpEvent::DuplicateIp {
+                ip_addr: ip_addr.clone(),
+                old: old.clone(),
+                new,
+            }
+        } else if binding.previous.as_ref() == Some(mac_addr) {
+            ArpEvent::FlipFlop {
+                ip_addr: ip_addr.clone(),
+                old: old.clone(),
+                new,
+            }
+        } else {
+            ArpEvent::Changed {
+                ip_addr: ip_addr.clone(),
+                old: old.clone(),
+                new,
+            }
+        };
+
+        binding.previous = Some(old);
+        binding.mac_addr = mac_addr.clone();
+        binding.first_seen = now;
+        binding.last_seen = now;
+        Some(event)
+    }
+}
+
+impl fmt::Display for ArpEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ArpEvent::*;
+        match self {
+            NewStation { ip_addr, mac_addr } => {
+                write!(f, "event=new-station ip={} mac={}", ip_addr, mac_addr)
+            }
+            Changed { ip_addr, old, new } => {
+                write!(f, "event=changed ip={} old={} new={}", ip_addr, old, new)
+            }
+            FlipFlop { ip_addr, old, new } => {
+                write!(f, "event=flip-flop ip={} old={} new={}", ip_addr, old, new)
+            }
+            DuplicateIp { ip_addr, old, new } => {
+                write!(
+                    f,
+                    "event=duplicate-ip ip={} old={} new={}",
+                    ip_addr, old, new
+                )
+            }
+            UnsolicitedReply {
+                ip_addr,
+                mac_addr,
+                target_addr,
+            } => write!(
+                f,
+                "event=unsolicited-reply ip={} mac={} target={}",
+                ip_addr, mac_addr, target_addr
+            ),
+        }
+    }
+}
@@ -0,0 +1,488 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    io::{self, Cursor},
+    time::{Duration, Instant},
+};
+
+use crate::protocol::physical::{self, Device};
+
+use super::{
+    address::MacAddress,
+    ethernet::{EtherType, EthernetFrame, EthernetHeader, EthernetPayload},
+    llc::{LlcFrame, LlcPayload, SAP_STP},
+    stp::{self, Bpdu, Protocol, Stp},
+};
+
+// frames are padded up to this, without the FCS
+const MIN_FRAME_LEN: usize = 60;
+
+#[derive(Debug, Clone)]
+pub struct FdbEntry {
+    pub port: usize,
+    pub updated: Instant,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PortStats {
+    pub rx_errors: u64,
+    pub tx_errors: u64,
+}
+
+pub struct BridgePort {
+    dev: Box<dyn Device>,
+    stats: PortStats,
+}
+
+/// Forwards frames between devices like an IEEE 802.1D MAC bridge.
+pub struct Bridge {
+    ports: Vec<BridgePort>,
+    fdb: HashMap<MacAddress, FdbEntry>,
+    // the entries of `fdb` by when they were last updated
+    fdb_age: BTreeSet<(Instant, MacAddress)>,
+    stp: Option<Stp>,
+    pub ageing_time: Duration,
+    /// The addresses the FDB holds at most. The oldest is forgotten first.
+    pub max_entries: usize,
+}
+
+impl BridgePort {
+    pub fn name(&self) -> String {
+        self.dev.name()
+    }
+
+    pub fn stats(&self) -> &PortStats {
+        &self.stats
+    }
+}
+
+impl Default for Bridge {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bridge {
+    pub fn new() -> Self {
+        Self {
+            ports: Vec::new(),
+            fdb: HashMap::new(),
+            fdb_age: BTreeSet::new(),
+            stp: None,
+            ageing_time: Duration::from_secs(300),
+            max_entries: 8192,
+        }
+    }
+
+    /// Adds a port and returns its number.
+    pub fn add_port(&mut self, dev: Box<dyn Device>) -> usize {
+        self.ports.push(BridgePort {
+            dev,
+            stats: PortStats::default(),
+        });
+        if let Some(stp) = &mut self.stp {
+            stp.add_port();
+        }
+        self.ports.len() - 1
+    }
+
+    /// Runs the spanning tree on the ports, with the lowest port address as
+    /// the bridge address.
+    pub fn enable_stp(&mut self, protocol: Protocol) -> io::Result<()> {
+        let mut mac_addr = None;
+        for port in &self.ports {
+            let addr = port.dev.address()?;
+            if mac_addr.as_ref().is_none_or(|a| addr < *a) {
+                mac_addr = Some(addr);
+            }
+        }
+        let mac_addr = mac_addr.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "the bridge has no ports")
+        })?;
+
+        let mut stp = Stp::new(mac_addr, protocol);
+        for _ in &self.ports {
+            stp.add_port();
+        }
+        self.stp = Some(stp);
+        Ok(())
+    }
+
+    pub fn stp(&self) -> Option<&Stp> {
+        self.stp.as_ref()
+    }
+
+    pub fn stp_mut(&mut self) -> Option<&mut Stp> {
+        self.stp.as_mut()
+    }
+
+    pub fn ports(&self) -> &[BridgePort] {
+        &self.ports
+    }
+
+    pub fn fdb(&self) -> impl Iterator<Item = (&MacAddress, &FdbEntry)> {
+        self.fdb.iter()
+    }
+
+    /// Waits for frames on any port and forwards them. Returns the frames
+    /// with the ports they arrived on, e.g. for inspection. A port that fails
+    /// to read or write only has the error counted in its stats.
+    pub fn poll(&mut self, timeout: Option<Duration>) -> io::Result<Vec<(usize, Vec<u8>)>> {
+        self.age();
+        self.send_bpdus();
+
+        // wake up in time for the next hello
+        let timeout = match &self.stp {
+            Some(stp) => Some(timeout.map_or(stp.hello_time(), |t| t.min(stp.hello_time()))),
+            None => timeout,
+        };
+        let devs = self
+            .ports
+            .iter()
+            .map(|port| port.dev.as_ref())
+            .collect::<Vec<_>>();
+        let ready = physical::select(&devs, timeout)?;
+
+        let mut frames = Vec::new();
+        for port in ready {
+            let mut buf = [0; 4096];
+            let len = match self.ports[port].dev.read(&mut buf) {
+                Ok(len) => len,
+                Err(_) => {
+                    self.ports[port].stats.rx_errors += 1;
+                    continue;
+                }
+            };
+            let frame = buf[..len].to_vec();
+            self.forward(port, &frame);
+            frames.push((port, frame));
+        }
+        self.send_bpdus();
+        Ok(frames)
+    }
+
+    /// Learns the source of `frame` and sends it out of the port its
+    /// destination is on, or floods it.
+    pub fn forward(&mut self, port: usize, frame: &[u8]) {
+        if frame.len() < 14 {
+            return;
+        }
+        let dst_addr = mac_addr(&frame[0..6]);
+        let src_addr = mac_addr(&frame[6..12]);
+
+        // a multicast source is invalid, and the reserved group addresses
+        // are for the link itself. without STP, BPDUs pass like any other
+        // multicast, so that the bridges around still see each other.
+        if dst_addr == stp::MULTICAST_ADDR && self.stp.is_some() {
+            self.receive_bpdu(port, frame);
+            return;
+        }
+        if is_multicast(&src_addr) || (is_reserved(&dst_addr) && dst_addr != stp::MULTICAST_ADDR) {
+            return;
+        }
+        if self.is_learning(port) {
+            self.learn(src_addr, port);
+        }
+        if !self.is_forwarding(port) {
+            return;
+        }
+
+        match self.fdb.get(&dst_addr) {
+            Some(entry) if !is_multicast(&dst_addr) => {
+                if entry.port != port && self.is_forwarding(entry.port) {
+                    self.send(entry.port, frame);
+                }
+            }
+            _ => self.flood(port, frame),
+        }
+    }
+
+    fn flood(&mut self, in_port: usize, frame: &[u8]) {
+        for i in 0..self.ports.len() {
+            if i != in_port && self.is_forwarding(i) {
+                self.send(i, frame);
+            }
+        }
+    }
+
+    fn send(&mut self, port: usize, frame: &[u8]) {
+        let port = &mut self.ports[port];
+        if port.dev.write_all(frame).is_err() {
+            port.stats.tx_errors += 1;
+        }
+    }
+
+    fn is_learning(&self, port: usize) -> bool {
+        self.stp
+            .as_ref()
+            .is_none_or(|stp| stp.port(port).is_learning())
+    }
+
+    fn is_forwarding(&self, port: usize) -> bool {
+        self.stp
+            .as_ref()
+            .is_none_or(|stp| stp.port(port).is_forwarding())
+    }
+
+    fn receive_bpdu(&mut self, port: usize, frame: &[u8]) {
+        let stp = match &mut self.stp {
+            Some(stp) => stp,
+            None => return,
+        };
+        if let Ok(EthernetFrame {
+            payload: EthernetPayload::Llc(llc),
+            ..
+        }) = EthernetFrame::read_from(&mut Cursor::new(frame))
+        {
+            if let LlcPayload::Stp(bpdu) = llc.payload {
+                stp.receive(port, bpdu);
+            }
+        }
+    }
+
+    fn send_bpdus(&mut self) {
+        let bpdus = match &mut self.stp {
+            Some(stp) => stp.tick(),
+            None => return,
+        };
+        for (port, bpdu) in bpdus {
+            if self.send_bpdu(port, bpdu).is_err() {
+                self.ports[port].stats.tx_errors += 1;
+            }
+        }
+    }
+
+    fn send_bpdu(&mut self, port: usize, bpdu: Bpdu) -> io::Result<()> {
+        let dev = &mut self.ports[port].dev;
+        let frame = EthernetFrame {
+            header: EthernetHeader {
+                dst_addr: stp::MULTICAST_ADDR,
+                src_addr: dev.address()?,
+                typ: EtherType::Length(0),
+            },
+            payload: EthernetPayload::Llc(LlcFrame::ui(SAP_STP, SAP_STP, LlcPayload::Stp(bpdu))),
+        };
+        let mut v = Vec::new();
+        frame.write_to(&mut v)?;
+        if v.len() < MIN_FRAME_LEN {
+            v.resize(MIN_FRAME_LEN, 0);
+        }
+        dev.write_all(&v)
+    }
+
+    fn learn(&mut self, addr: MacAddress, port: usize) {
+        let now = Instant::now();
+        match self.fdb.get(&addr) {
+            Some(entry) => {
+                self.fdb_age.remove(&(entry.updated, addr.clone()));
+            }
+            None if self.fdb.len() >= self.max_entries => {
+                if let Some((_, oldest)) = self.fdb_age.pop_first() {
+                    self.fdb.remove(&oldest);
+                }
+            }
+            None => {}
+        }
+        self.fdb_age.insert((now, addr.clone()));
+        self.fdb.insert(addr, FdbEntry { port, updated: now });
+    }
+
+    fn age(&mut self) {
+        let now = Instant::now();
+        // while the tree changes, stations may have moved to other ports
+        let ageing_time = match &self.stp {
+            Some(stp) if stp.topology_change() => stp.forward_delay(),
+            _ => self.ageing_time,
+        };
+        while let Some((updated, _)) = self.fdb_age.first() {
+            if now - *updated < ageing_time {
+                break;
+            }
+            let (_, addr) = self.fdb_age.pop_first().unwrap();
+            self.fdb.remove(&addr);
+        }
+    }
+}
+
+fn mac_addr(b: &[u8]) -> MacAddress {
+    let mut addr = [0; 6];
+    addr.copy_from_slice(b);
+    MacAddress(addr)
+}
+
+fn is_multicast(addr: &MacAddress) -> bool {
+    addr.0[0] & 0x01 != 0
+}
+
+// 01-80-C2-00-00-00 to 01-80-C2-00-00-0F
+fn is_reserved(addr: &MacAddress) -> bool {
+    addr.0[..5] == [0x01, 0x80, 0xc2, 0x00, 0x00] && addr.0[5] <= 0x0f
+}
+
+impl fmt::Display for Bridge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bridge:")?;
+        for (i, port) in self.ports.iter().enumerate() {
+            write!(f, "\n  port {}: {}", i, port.name())?;
+        }
+        if let Some(stp) = &self.stp {
+            write!(f, "\n{}", stp)?;
+        }
+        for (addr, entry) in &self.fdb {
+            write!(
+                f,
+                "\n  {} port {} ({}s)",
+                addr,
+                entry.port,
+                entry.updated.elapsed().as_secs()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        os::unix::{
+            io::{AsRawFd, RawFd},
+            net::UnixDatagram,
+        },
+    };
+
+    use super::*;
+
+    struct FakeDevice {
+        n: u8,
+        sock: UnixDatagram,
+    }
+
+    impl Device for FakeDevice {
+        fn name(&self) -> String {
+            format!("port{}", self.n)
+        }
+
+        fn address(&self) -> io::Result<MacAddress> {
+            Ok(MacAddress([2, 0, 0, 0, 0, self.n]))
+        }
+    }
+
+    impl AsRawFd for FakeDevice {
+        fn as_raw_fd(&self) -> RawFd {
+            self.sock.as_raw_fd()
+        }
+    }
+
+    impl Read for FakeDevice {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.sock.recv(buf)
+        }
+    }
+
+    impl Write for FakeDevice {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sock.send(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // a bridge of `n` ports, with the other ends of their links
+    fn bridge(n: u8) -> (Bridge, Vec<UnixDatagram>) {
+        let mut bridge = Bridge::new();
+        let mut peers = Vec::new();
+        for i in 0..n {
+            let (sock, peer) = UnixDatagram::pair().unwrap();
+            peer.set_nonblocking(true).unwrap();
+            bridge.add_port(Box::new(FakeDevice { n: i, sock }));
+            peers.push(peer);
+        }
+        (bridge, peers)
+    }
+
+    fn frame(dst_addr: [u8; 6], src: u8) -> Vec<u8> {
+        let mut v = dst_addr.to_vec();
+        v.extend_from_slice(&[2, 0, 0, 0, 1, src, 0x08, 0x00]);
+        v.resize(MIN_FRAME_LEN, 0);
+        v
+    }
+
+    fn recv(peer: &UnixDatagram) -> Option<Vec<u8>> {
+        let mut buf = [0; 4096];
+        let len = peer.recv(&mut buf).ok()?;
+        Some(buf[..len].to_vec())
+    }
+
+    #[test]
+    fn a_failing_port_does_not_stop_the_others() {
+        let (mut bridge, mut peers) = bridge(3);
+        peers.truncate(2);
+        let sent = frame([0xff; 6], 1);
+        peers[0].send(&sent).unwrap();
+
+        let frames = bridge.poll(Some(Duration::from_secs(1))).unwrap();
+        assert_eq!(frames, [(0, sent.clone())]);
+        assert_eq!(recv(&peers[1]), Some(sent));
+        assert_eq!(bridge.ports()[2].stats().tx_errors, 1);
+    }
+
+    #[test]
+    fn frames_go_to_the_port_their_destination_was_learned_on() {
+        let (mut bridge, peers) = bridge(3);
+        bridge.forward(1, &frame([0xff; 6], 1));
+        recv(&peers[0]);
+        recv(&peers[2]);
+
+        let sent = frame([2, 0, 0, 0, 1, 1], 2);
+        bridge.forward(2, &sent);
+        assert_eq!(recv(&peers[1]), Some(sent));
+        assert_eq!(recv(&peers[0]), None);
+    }
+
+    #[test]
+    fn the_oldest_address_is_forgotten_when_the_fdb_is_full() {
+        let (mut bridge, _peers) = bridge(2);
+        bridge.max_entries = 2;
+        for src in 1..=3 {
+            bridge.forward(0, &frame([0xff; 6], src));
+        }
+        // relearning an address does not take a second entry
+        bridge.forward(0, &frame([0xff; 6], 3));
+
+        let mut addrs = bridge.fdb().map(|(addr, _)| addr.0[5]).collect::<Vec<_>>();
+        addrs.sort_unstable();
+        assert_eq!(addrs, [2, 3]);
+        assert_eq!(bridge.fdb_age.len(), 2);
+    }
+
+    #[test]
+    fn bpdus_are_flooded_without_stp() {
+        let (mut bridge, peers) = bridge(2);
+        let bpdu = frame(stp::MULTICAST_ADDR.0, 1);
+        bridge.forward(0, &bpdu);
+        assert_eq!(recv(&peers[1]), Some(bpdu.clone()));
+
+        bridge.enable_stp(Protocol::Rstp).unwrap();
+        while recv(&peers[1]).is_some() {}
+        bridge.forward(0, &bpdu);
+        assert_eq!(recv(&peers[1]), None);
+    }
+
+    #[test]
+    fn the_fdb_is_flushed_while_the_tree_changes() {
+        let (mut bridge, _peers) = bridge(2);
+        bridge.enable_stp(Protocol::Stp).unwrap();
+        bridge.stp_mut().unwrap().bridge_forward_delay = Duration::ZERO;
+        bridge.poll(Some(Duration::ZERO)).unwrap();
+        bridge.poll(Some(Duration::ZERO)).unwrap();
+        assert!(bridge.stp().unwrap().topology_change());
+
+        bridge.forward(0, &frame([0xff; 6], 1));
+        assert_eq!(bridge.fdb().count(), 1);
+        bridge.poll(Some(Duration::ZERO)).unwrap();
+        assert_eq!(bridge.fdb().count(), 0);
+    }
+}
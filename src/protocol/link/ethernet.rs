@@ -1,13 +1,16 @@
 use std::{
     fmt,
-    io::{self, Read, Write},
+    io::{self, Cursor, Read, Write},
 };
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
 use crate::protocol::{internet::ip::IPDatagram, link::arp::Arp};
 
-use super::address::MacAddress;
+use super::{address::MacAddress, llc::LlcFrame, lldp::Lldpdu, wol::MagicPacket};
+
+// values up to this are the length of an IEEE 802.3 frame
+const MAX_LENGTH: u16 = 1500;
 
 #[derive(Debug)]
 pub struct EthernetFrame {
@@ -23,17 +26,25 @@ pub struct EthernetHeader {
     pub typ: EtherType,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum EtherType {
     IPv4,
     Arp,
+    Rarp,
+    Lldp,
+    WakeOnLan,
+    Length(u16),
     Unknown(u16),
 }
 
 #[derive(Debug)]
 pub enum EthernetPayload {
     Arp(Arp),
+    Rarp(Arp),
     IP(IPDatagram),
+    Llc(LlcFrame),
+    Lldp(Lldpdu),
+    WakeOnLan(MagicPacket),
     Raw(Vec<u8>),
 }
 
@@ -45,22 +56,19 @@ impl EthernetFrame {
             typ: r.read_u16::<BigEndian>()?.into(),
         };
 
-        let payload = match header.typ {
-            EtherType::Arp => EthernetPayload::Arp(Arp::read_from(r)?),
-            EtherType::IPv4 => EthernetPayload::IP(IPDatagram::read_from(r)?),
-            _ => EthernetPayload::Raw({
-                let mut v = Vec::new();
-                r.read_to_end(&mut v)?;
-                v
-            }),
-        };
-
+        let payload = EthernetPayload::read_from(r, &header.typ)?;
         Ok(Self { header, payload })
     }
 
     pub fn write_to<W: Write>(self, w: &mut W) -> io::Result<()> {
         w.write_all(&self.header.dst_addr.0)?;
         w.write_all(&self.header.src_addr.0)?;
+        if let EtherType::Length(_) = self.header.typ {
+            let mut v = Vec::new();
+            self.payload.write_to(&mut v)?;
+            w.write_u16::<BigEndian>(v.len() as u16)?;
+            return w.write_all(&v);
+        }
         w.write_u16::<BigEndian>(self.header.typ.into())?;
         self.payload.write_to(w)?;
         Ok(())
@@ -68,20 +76,49 @@ impl EthernetFrame {
 }
 
 impl EthernetPayload {
+    pub fn read_from<R: Read>(r: &mut R, typ: &EtherType) -> io::Result<Self> {
+        Ok(match typ {
+            EtherType::Arp => Self::Arp(Arp::read_from(r)?),
+            EtherType::Rarp => Self::Rarp(Arp::read_from(r)?),
+            EtherType::IPv4 => Self::IP(IPDatagram::read_from(r)?),
+            EtherType::Lldp => Self::Lldp(Lldpdu::read_from(r)?),
+            EtherType::WakeOnLan => Self::WakeOnLan(MagicPacket::read_from(r)?),
+            EtherType::Length(len) => {
+                // the rest of the frame may be padding
+                let mut v = vec![0; *len as usize];
+                r.read_exact(&mut v)?;
+                Self::Llc(LlcFrame::read_from(&mut Cursor::new(v))?)
+            }
+            _ => Self::Raw({
+                let mut v = Vec::new();
+                r.read_to_end(&mut v)?;
+                v
+            }),
+        })
+    }
+
     pub fn write_to<W: Write>(self, w: &mut W) -> io::Result<()> {
         match self {
-            Self::Arp(arp) => arp.write_to(w),
+            Self::Arp(arp) | Self::Rarp(arp) => arp.write_to(w),
             Self::IP(ip) => ip.write_to(w),
+            Self::Llc(llc) => llc.write_to(w),
+            Self::Lldp(lldp) => lldp.write_to(w),
+            Self::WakeOnLan(packet) => packet.write_to(w),
             Self::Raw(v) => w.write_all(&v),
         }
     }
 
-    pub fn typ(&self) -> EtherType {
+    /// The type of the payload, which a raw payload does not know.
+    pub fn typ(&self) -> Option<EtherType> {
         match self {
-            Self::Arp(_) => EtherType::Arp,
-            Self::IP(_) => EtherType::IPv4,
-            // TODO
-            Self::Raw(_) => panic!(),
+            Self::Arp(_) => Some(EtherType::Arp),
+            Self::Rarp(_) => Some(EtherType::Rarp),
+            Self::IP(_) => Some(EtherType::IPv4),
+            // the actual length is filled in by EthernetFrame::write_to
+            Self::Llc(_) => Some(EtherType::Length(0)),
+            Self::Lldp(_) => Some(EtherType::Lldp),
+            Self::WakeOnLan(_) => Some(EtherType::WakeOnLan),
+            Self::Raw(_) => None,
         }
     }
 }
@@ -91,6 +128,10 @@ impl From<u16> for EtherType {
         match v {
             0x0800 => Self::IPv4,
             0x0806 => Self::Arp,
+            0x8035 => Self::Rarp,
+            0x88cc => Self::Lldp,
+            0x0842 => Self::WakeOnLan,
+            x if x <= MAX_LENGTH => Self::Length(x),
             x => Self::Unknown(x),
         }
     }
@@ -101,6 +142,10 @@ impl From<EtherType> for u16 {
         match v {
             EtherType::IPv4 => 0x0800,
             EtherType::Arp => 0x0806,
+            EtherType::Rarp => 0x8035,
+            EtherType::Lldp => 0x88cc,
+            EtherType::WakeOnLan => 0x0842,
+            EtherType::Length(x) => x,
             EtherType::Unknown(x) => x,
         }
     }
@@ -122,6 +167,10 @@ impl fmt::Display for EtherType {
         match self {
             IPv4 => write!(f, "IPv4(0x0800)"),
             Arp => write!(f, "ARP(0x0806)"),
+            Rarp => write!(f, "RARP(0x8035)"),
+            Lldp => write!(f, "LLDP(0x88cc)"),
+            WakeOnLan => write!(f, "Wake-on-LAN(0x0842)"),
+            Length(x) => write!(f, "802.3 Length({})", x),
             Unknown(x) => write!(f, "UNKNOWN(0x{:04x})", x),
         }
     }
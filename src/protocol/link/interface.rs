@@ -1,4 +1,8 @@
-use std::io::{self, Cursor, Read, Write};
+use std::{
+    io::{self, Cursor, Read, Write},
+    os::unix::io::{AsRawFd, RawFd},
+    time::Duration,
+};
 
 use crate::protocol::physical::Device;
 
@@ -20,28 +24,50 @@ impl Interface {
         })
     }
 
+    /// Uses `mac_addr` instead of the address of the device, e.g. for a TAP
+    /// device whose own address belongs to the host side.
+    pub fn with_mac_addr(dev: Box<dyn Device>, mac_addr: MacAddress) -> Self {
+        Self { dev, mac_addr }
+    }
+
     pub fn mac_addr(&self) -> &MacAddress {
         &self.mac_addr
     }
 
     pub fn recv(&mut self) -> io::Result<EthernetFrame> {
         let mut buf = [0; 4096];
-        let len = self.dev.read(&mut buf).unwrap();
+        let len = self.dev.read(&mut buf)?;
 
         let mut cursor = Cursor::new(&buf[0..len]);
         let frame = EthernetFrame::read_from(&mut cursor)?;
         Ok(frame)
     }
 
+    pub fn recv_timeout(&mut self, timeout: Option<Duration>) -> io::Result<Option<EthernetFrame>> {
+        if !self.dev.poll(timeout)? {
+            return Ok(None);
+        }
+        self.recv().map(Some)
+    }
+
     pub fn send(&mut self, dst_addr: MacAddress, payload: EthernetPayload) -> io::Result<()> {
+        let typ = payload.typ().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a raw payload has no EtherType",
+            )
+        })?;
         let header = EthernetHeader {
             dst_addr,
             src_addr: self.mac_addr().clone(),
-            typ: payload.typ(),
+            typ,
         };
 
+        // devices take a frame per write, so it cannot go in pieces
         let frame = EthernetFrame { header, payload };
-        frame.write_to(&mut self.dev)
+        let mut v = Vec::new();
+        frame.write_to(&mut v)?;
+        self.dev.write_all(&v)
     }
 }
 
@@ -53,6 +79,24 @@ impl Device for Interface {
     fn address(&self) -> io::Result<MacAddress> {
         self.dev.address()
     }
+
+    fn is_up(&self) -> io::Result<bool> {
+        self.dev.is_up()
+    }
+
+    fn mtu(&self) -> io::Result<usize> {
+        self.dev.mtu()
+    }
+
+    fn poll(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        self.dev.poll(timeout)
+    }
+}
+
+impl AsRawFd for Interface {
+    fn as_raw_fd(&self) -> RawFd {
+        self.dev.as_raw_fd()
+    }
 }
 
 impl Read for Interface {
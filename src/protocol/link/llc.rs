@@ -0,0 +1,303 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use super::{
+    ethernet::{EtherType, EthernetPayload},
+    stp::Bpdu,
+};
+
+pub const SAP_STP: u8 = 0x42;
+pub const SAP_SNAP: u8 = 0xaa;
+
+// the OUI of RFC 1042, under which the protocol id is an EtherType
+pub const OUI_ETHERNET: [u8; 3] = [0x00, 0x00, 0x00];
+
+/// The payload of an IEEE 802.3 frame, whose EtherType field is a length.
+// IEEE 802.2
+#[derive(Debug)]
+pub struct LlcFrame {
+    pub header: LlcHeader,
+    pub payload: LlcPayload,
+}
+
+#[derive(Debug)]
+pub struct LlcHeader {
+    pub dsap: u8,
+    pub ssap: u8,
+    /// One byte for U-format PDUs, two bytes for I- and S-format PDUs.
+    pub control: u16,
+}
+
+// https://tools.ietf.org/html/rfc1042
+#[derive(Debug)]
+pub struct Snap {
+    pub oui: [u8; 3],
+    pub protocol_id: u16,
+}
+
+#[derive(Debug)]
+pub enum LlcPayload {
+    Snap(Snap, Box<EthernetPayload>),
+    Stp(Bpdu),
+    Raw(Vec<u8>),
+}
+
+impl LlcFrame {
+    /// Unnumbered information, which is what connectionless protocols use.
+    pub fn ui(dsap: u8, ssap: u8, payload: LlcPayload) -> Self {
+        Self {
+            header: LlcHeader {
+                dsap,
+                ssap,
+                control: 0x03,
+            },
+            payload,
+        }
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        let header = LlcHeader::read_from(r)?;
+        let payload = if header.dsap == SAP_SNAP && header.ssap == SAP_SNAP {
+            let snap = Snap::read_from(r)?;
+            let payload = if snap.oui == OUI_ETHERNET {
+                EthernetPayload::read_from(r, &snap.protocol_id.into())?
+            } else {
+                let mut v = Vec::new();
+                r.read_to_end(&mut v)?;
+                EthernetPayload::Raw(v)
+            };
+            LlcPayload::Snap(snap, Box::new(payload))
+        } else if header.dsap == SAP_STP && header.ssap == SAP_STP {
+            LlcPayload::Stp(Bpdu::read_from(r)?)
+        } else {
+            let mut v = Vec::new();
+            r.read_to_end(&mut v)?;
+            LlcPayload::Raw(v)
+        };
+
+        Ok(Self { header, payload })
+    }
+
+    pub fn write_to<W: Write>(self, w: &mut W) -> io::Result<()> {
+        self.header.write_to(w)?;
+        match self.payload {
+            LlcPayload::Snap(snap, payload) => {
+                snap.write_to(w)?;
+                payload.write_to(w)
+            }
+            LlcPayload::Stp(bpdu) => bpdu.write_to(w),
+            LlcPayload::Raw(v) => w.write_all(&v),
+        }
+    }
+}
+
+impl LlcHeader {
+    pub fn is_u_format(&self) -> bool {
+        self.control & 0x03 == 0x03
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        let dsap = r.read_u8()?;
+        let ssap = r.read_u8()?;
+        let mut control = r.read_u8()? as u16;
+        if control & 0x03 != 0x03 {
+            control = (control << 8) | r.read_u8()? as u16;
+        }
+        Ok(Self {
+            dsap,
+            ssap,
+            control,
+        })
+    }
+
+    pub fn write_to<W: Write>(self, w: &mut W) -> io::Result<()> {
+        w.write_u8(self.dsap)?;
+        w.write_u8(self.ssap)?;
+        if self.is_u_format() {
+            w.write_u8(self.control as u8)
+        } else {
+            w.write_u16::<BigEndian>(self.control)
+        }
+    }
+}
+
+impl Snap {
+    pub fn new(typ: EtherType) -> Self {
+        Self {
+            oui: OUI_ETHERNET,
+            protocol_id: typ.into(),
+        }
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut oui = [0; 3];
+        r.read_exact(&mut oui)?;
+        Ok(Self {
+            oui,
+            protocol_id: r.read_u16::<BigEndian>()?,
+        })
+    }
+
+    pub fn write_to<W: Write>(self, w: &mut W) -> io::Result<()> {
+        w.write_all(&self.oui)?;
+        w.write_u16::<BigEndian>(self.protocol_id)
+    }
+}
+
+impl fmt::Display for LlcHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "LlcHeader:")?;
+        writeln!(f, "  dsp: 0x{:02x}", self.dsap)?;
+        writeln!(f, "  ssp: 0x{:02x}", self.ssap)?;
+        if self.is_u_format() {
+            write!(f, "  ctl: 0x{:02x}", self.control)?;
+        } else {
+            write!(f, "  ctl: 0x{:04x}", self.control)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Snap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Snap:")?;
+        writeln!(
+            f,
+            "  oui: {:02x}-{:02x}-{:02x}",
+            self.oui[0], self.oui[1], self.oui[2]
+        )?;
+        if self.oui == OUI_ETHERNET {
+            write!(f, "  pid: {}", EtherType::from(self.protocol_id))?;
+        } else {
+            write!(f, "  pid: 0x{:04x}", self.protocol_id)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::protocol::{
+        internet::address::IPAddress,
+        link::{
+            address::MacAddress,
+            arp::{Arp, Opcode},
+            ethernet::{EthernetFrame, EthernetHeader},
+        },
+    };
+
+    fn arp() -> Arp {
+        Arp::new(
+            Opcode::Request,
+            MacAddress([2, 0, 0, 0, 0, 1]),
+            IPAddress([10, 0, 0, 1]),
+            MacAddress([0; 6]),
+            IPAddress([10, 0, 0, 2]),
+        )
+    }
+
+    fn frame(llc: LlcFrame) -> Vec<u8> {
+        let frame = EthernetFrame {
+            header: EthernetHeader {
+                dst_addr: MacAddress::broadcast(),
+                src_addr: MacAddress([2, 0, 0, 0, 0, 1]),
+                typ: EtherType::Length(0),
+            },
+            payload: EthernetPayload::Llc(llc),
+        };
+        let mut v = Vec::new();
+        frame.write_to(&mut v).unwrap();
+        v
+    }
+
+    fn read_llc(b: &[u8]) -> LlcFrame {
+        let frame = EthernetFrame::read_from(&mut Cursor::new(b)).unwrap();
+        match frame.payload {
+            EthernetPayload::Llc(llc) => llc,
+            payload => panic!("{:?}", payload),
+        }
+    }
+
+    #[test]
+    fn snap_carries_an_ethertype_payload() {
+        let llc = LlcFrame::ui(
+            SAP_SNAP,
+            SAP_SNAP,
+            LlcPayload::Snap(
+                Snap::new(EtherType::Arp),
+                Box::new(EthernetPayload::Arp(arp())),
+            ),
+        );
+        let mut b = frame(llc);
+        // the length covers the LLC and SNAP headers and the packet
+        assert_eq!(b[12..14], [0, 3 + 5 + 28]);
+        assert_eq!(b[14..22], [0xaa, 0xaa, 0x03, 0, 0, 0, 0x08, 0x06]);
+
+        // and the padding after it is left alone
+        b.resize(60, 0xff);
+        let llc = read_llc(&b);
+        assert!(llc.header.is_u_format());
+        match llc.payload {
+            LlcPayload::Snap(snap, payload) => {
+                assert_eq!(snap.oui, OUI_ETHERNET);
+                assert_eq!(EtherType::from(snap.protocol_id), EtherType::Arp);
+                assert!(matches!(*payload, EthernetPayload::Arp(_)));
+            }
+            payload => panic!("{:?}", payload),
+        }
+        assert_eq!(frame(read_llc(&b)), b[..50]);
+    }
+
+    #[test]
+    fn snap_of_another_organization_is_kept_raw() {
+        let b = [0xaa, 0xaa, 0x03, 0x00, 0x00, 0x0c, 0x20, 0x00, 1, 2, 3];
+        let llc = LlcFrame::read_from(&mut Cursor::new(&b)).unwrap();
+        match &llc.payload {
+            LlcPayload::Snap(snap, payload) => {
+                assert_eq!(snap.oui, [0x00, 0x00, 0x0c]);
+                assert_eq!(snap.protocol_id, 0x2000);
+                assert!(matches!(**payload, EthernetPayload::Raw(ref v) if v == &[1, 2, 3]));
+                assert_eq!(payload.typ(), None);
+            }
+            payload => panic!("{:?}", payload),
+        }
+        let mut v = Vec::new();
+        llc.write_to(&mut v).unwrap();
+        assert_eq!(v, b);
+    }
+
+    #[test]
+    fn other_saps_and_two_byte_controls_are_kept_raw() {
+        // an I-format PDU, whose control field takes two bytes
+        let b = [0xf0, 0xf0, 0x02, 0x04, 1, 2];
+        let llc = LlcFrame::read_from(&mut Cursor::new(&b)).unwrap();
+        assert!(!llc.header.is_u_format());
+        assert_eq!(llc.header.control, 0x0204);
+        assert!(matches!(llc.payload, LlcPayload::Raw(ref v) if v == &[1, 2]));
+        let mut v = Vec::new();
+        llc.write_to(&mut v).unwrap();
+        assert_eq!(v, b);
+    }
+
+    #[test]
+    fn truncated_frames_are_rejected() {
+        let llc = LlcFrame::ui(
+            SAP_SNAP,
+            SAP_SNAP,
+            LlcPayload::Snap(
+                Snap::new(EtherType::Arp),
+                Box::new(EthernetPayload::Arp(arp())),
+            ),
+        );
+        let b = frame(llc);
+        let err = EthernetFrame::read_from(&mut Cursor::new(&b[..b.len() - 1])).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
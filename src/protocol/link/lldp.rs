@@ -0,0 +1,514 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, Cursor, Read, Write},
+    time::{Duration, Instant},
+};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::protocol::internet::address::IPAddress;
+
+use super::{address::MacAddress, ethernet::EthernetPayload, Interface};
+
+pub const MULTICAST_ADDR: MacAddress = MacAddress([0x01, 0x80, 0xc2, 0x00, 0x00, 0x0e]);
+
+pub const CHASSIS_ID_MAC_ADDRESS: u8 = 4;
+pub const CHASSIS_ID_LOCAL: u8 = 7;
+pub const PORT_ID_MAC_ADDRESS: u8 = 3;
+pub const PORT_ID_INTERFACE_NAME: u8 = 5;
+pub const PORT_ID_LOCAL: u8 = 7;
+
+pub const CAPABILITY_BRIDGE: u16 = 1 << 2;
+pub const CAPABILITY_ROUTER: u16 = 1 << 4;
+pub const CAPABILITY_STATION: u16 = 1 << 7;
+
+// https://www.iana.org/assignments/address-family-numbers/address-family-numbers.xhtml
+const ADDRESS_FAMILY_IPV4: u8 = 1;
+const IF_SUBTYPE_IF_INDEX: u8 = 2;
+// IEEE 802.1AB-2016 8.5.9
+const MAX_MANAGEMENT_ADDR_LEN: usize = 31;
+const MAX_OID_LEN: usize = 128;
+
+// IEEE 802.1AB
+#[derive(Debug)]
+pub struct Lldpdu(pub Vec<Tlv>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tlv {
+    End,
+    ChassisId {
+        subtype: u8,
+        id: Vec<u8>,
+    },
+    PortId {
+        subtype: u8,
+        id: Vec<u8>,
+    },
+    Ttl(u16),
+    PortDescription(String),
+    SystemName(String),
+    SystemDescription(String),
+    SystemCapabilities {
+        capabilities: u16,
+        enabled: u16,
+    },
+    ManagementAddress(ManagementAddress),
+    OrganizationSpecific {
+        oui: [u8; 3],
+        subtype: u8,
+        data: Vec<u8>,
+    },
+    Unknown {
+        typ: u8,
+        data: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManagementAddress {
+    pub subtype: u8,
+    pub addr: Vec<u8>,
+    pub if_subtype: u8,
+    pub if_number: u32,
+    pub oid: Vec<u8>,
+}
+
+impl Lldpdu {
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut tlvs = Vec::new();
+        loop {
+            let tlv = Tlv::read_from(r)?;
+            if tlv == Tlv::End {
+                return Ok(Self(tlvs));
+            }
+            tlvs.push(tlv);
+        }
+    }
+
+    pub fn write_to<W: Write>(self, w: &mut W) -> io::Result<()> {
+        for tlv in self.0 {
+            tlv.write_to(w)?;
+        }
+        Tlv::End.write_to(w)
+    }
+
+    pub fn chassis_id(&self) -> Option<(u8, &[u8])> {
+        self.0.iter().find_map(|tlv| match tlv {
+            Tlv::ChassisId { subtype, id } => Some((*subtype, id.as_slice())),
+            _ => None,
+        })
+    }
+
+    pub fn port_id(&self) -> Option<(u8, &[u8])> {
+        self.0.iter().find_map(|tlv| match tlv {
+            Tlv::PortId { subtype, id } => Some((*subtype, id.as_slice())),
+            _ => None,
+        })
+    }
+
+    pub fn ttl(&self) -> Option<u16> {
+        self.0.iter().find_map(|tlv| match tlv {
+            Tlv::Ttl(ttl) => Some(*ttl),
+            _ => None,
+        })
+    }
+
+    pub fn system_name(&self) -> Option<&str> {
+        self.0.iter().find_map(|tlv| match tlv {
+            Tlv::SystemName(name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+}
+
+impl Tlv {
+    pub fn typ(&self) -> u8 {
+        match self {
+            Self::End => 0,
+            Self::ChassisId { .. } => 1,
+            Self::PortId { .. } => 2,
+            Self::Ttl(_) => 3,
+            Self::PortDescription(_) => 4,
+            Self::SystemName(_) => 5,
+            Self::SystemDescription(_) => 6,
+            Self::SystemCapabilities { .. } => 7,
+            Self::ManagementAddress(_) => 8,
+            Self::OrganizationSpecific { .. } => 127,
+            Self::Unknown { typ, .. } => *typ,
+        }
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        let header = r.read_u16::<BigEndian>()?;
+        let typ = (header >> 9) as u8;
+        let mut data = vec![0; (header & 0x01ff) as usize];
+        r.read_exact(&mut data)?;
+
+        let mut c = Cursor::new(data.as_slice());
+        let tlv = match typ {
+            0 => Self::End,
+            1 | 2 => {
+                let subtype = c.read_u8()?;
+                let id = data[1..].to_vec();
+                match typ {
+                    1 => Self::ChassisId { subtype, id },
+                    _ => Self::PortId { subtype, id },
+                }
+            }
+            3 => Self::Ttl(c.read_u16::<BigEndian>()?),
+            4 => Self::PortDescription(string(&data)),
+            5 => Self::SystemName(string(&data)),
+            6 => Self::SystemDescription(string(&data)),
+            7 => Self::SystemCapabilities {
+                capabilities: c.read_u16::<BigEndian>()?,
+                enabled: c.read_u16::<BigEndian>()?,
+            },
+            8 => Self::ManagementAddress(ManagementAddress::read_from(&mut c)?),
+            127 => {
+                let mut oui = [0; 3];
+                c.read_exact(&mut oui)?;
+                Self::OrganizationSpecific {
+                    oui,
+                    subtype: c.read_u8()?,
+                    data: data[4..].to_vec(),
+                }
+            }
+            _ => Self::Unknown { typ, data },
+        };
+        Ok(tlv)
+    }
+
+    pub fn write_to<W: Write>(self, w: &mut W) -> io::Result<()> {
+        let typ = self.typ();
+        let mut data = Vec::new();
+        match self {
+            Self::End => {}
+            Self::ChassisId { subtype, id } | Self::PortId { subtype, id } => {
+                data.write_u8(subtype)?;
+                data.write_all(&id)?;
+            }
+            Self::Ttl(ttl) => data.write_u16::<BigEndian>(ttl)?,
+            Self::PortDescription(s) | Self::SystemName(s) | Self::SystemDescription(s) => {
+                data.write_all(s.as_bytes())?
+            }
+            Self::SystemCapabilities {
+                capabilities,
+                enabled,
+            } => {
+                data.write_u16::<BigEndian>(capabilities)?;
+                data.write_u16::<BigEndian>(enabled)?;
+            }
+            Self::ManagementAddress(addr) => addr.write_to(&mut data)?,
+            Self::OrganizationSpecific {
+                oui,
+                subtype,
+                data: v,
+            } => {
+                data.write_all(&oui)?;
+                data.write_u8(subtype)?;
+                data.write_all(&v)?;
+            }
+            Self::Unknown { data: v, .. } => data.write_all(&v)?,
+        }
+
+        if data.len() > 0x01ff {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("TLV {} is too long: {} bytes", typ, data.len()),
+            ));
+        }
+        w.write_u16::<BigEndian>((typ as u16) << 9 | data.len() as u16)?;
+        w.write_all(&data)
+    }
+}
+
+impl ManagementAddress {
+    pub fn ipv4(addr: &IPAddress, if_index: u32) -> Self {
+        Self {
+            subtype: ADDRESS_FAMILY_IPV4,
+            addr: addr.0.to_vec(),
+            if_subtype: IF_SUBTYPE_IF_INDEX,
+            if_number: if_index,
+            oid: Vec::new(),
+        }
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        let len = r.read_u8()?;
+        if len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "empty management address",
+            ));
+        }
+        let subtype = r.read_u8()?;
+        let mut addr = vec![0; len as usize - 1];
+        r.read_exact(&mut addr)?;
+        let if_subtype = r.read_u8()?;
+        let if_number = r.read_u32::<BigEndian>()?;
+        let mut oid = vec![0; r.read_u8()? as usize];
+        r.read_exact(&mut oid)?;
+
+        Ok(Self {
+            subtype,
+            addr,
+            if_subtype,
+            if_number,
+            oid,
+        })
+    }
+
+    pub fn write_to<W: Write>(self, w: &mut W) -> io::Result<()> {
+        if self.addr.is_empty() || self.addr.len() > MAX_MANAGEMENT_ADDR_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid management address length: {}", self.addr.len()),
+            ));
+        }
+        if self.oid.len() > MAX_OID_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("object identifier is too long: {} bytes", self.oid.len()),
+            ));
+        }
+        w.write_u8(self.addr.len() as u8 + 1)?;
+        w.write_u8(self.subtype)?;
+        w.write_all(&self.addr)?;
+        w.write_u8(self.if_subtype)?;
+        w.write_u32::<BigEndian>(self.if_number)?;
+        w.write_u8(self.oid.len() as u8)?;
+        w.write_all(&self.oid)
+    }
+}
+
+fn string(v: &[u8]) -> String {
+    String::from_utf8_lossy(v).into_owned()
+}
+
+/// Formats an id as a MAC address, as text if it is printable, or in hex.
+fn id_to_string(id: &[u8], is_mac: bool) -> String {
+    if is_mac && id.len() == 6 {
+        let mut addr = [0; 6];
+        addr.copy_from_slice(id);
+        return MacAddress(addr).to_string();
+    }
+    if !id.is_empty() && id.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+        return string(id);
+    }
+    id.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[derive(Debug)]
+pub struct Neighbor {
+    pub lldpdu: Lldpdu,
+    pub expires: Instant,
+}
+
+/// Advertises ourselves and keeps the neighbors we hear from.
+#[derive(Debug)]
+pub struct LldpAgent {
+    pub chassis_id: MacAddress,
+    pub port_id: String,
+    pub system_name: Option<String>,
+    pub system_description: Option<String>,
+    pub capabilities: u16,
+    pub management_addr: Option<IPAddress>,
+    pub interval: Duration,
+    pub hold: u16,
+    last_sent: Option<Instant>,
+    neighbors: HashMap<(Vec<u8>, Vec<u8>), Neighbor>,
+}
+
+impl LldpAgent {
+    pub fn new(chassis_id: MacAddress, port_id: String) -> Self {
+        Self {
+            chassis_id,
+            port_id,
+            system_name: None,
+            system_description: None,
+            capabilities: CAPABILITY_STATION,
+            management_addr: None,
+            interval: Duration::from_secs(30),
+            hold: 4,
+            last_sent: None,
+            neighbors: HashMap::new(),
+        }
+    }
+
+    pub fn lldpdu(&self) -> Lldpdu {
+        let ttl = (self.interval.as_secs() * self.hold as u64).min(u16::MAX as u64) as u16;
+        self.lldpdu_with_ttl(ttl)
+    }
+
+    fn lldpdu_with_ttl(&self, ttl: u16) -> Lldpdu {
+        let mut tlvs = vec![
+            Tlv::ChassisId {
+                subtype: CHASSIS_ID_MAC_ADDRESS,
+                id: self.chassis_id.0.to_vec(),
+            },
+            Tlv::PortId {
+                subtype: PORT_ID_INTERFACE_NAME,
+                id: self.port_id.as_bytes().to_vec(),
+            },
+            Tlv::Ttl(ttl),
+        ];
+        if let Some(name) = &self.system_name {
+            tlvs.push(Tlv::SystemName(name.clone()));
+        }
+        if let Some(description) = &self.system_description {
+            tlvs.push(Tlv::SystemDescription(description.clone()));
+        }
+        tlvs.push(Tlv::SystemCapabilities {
+            capabilities: self.capabilities,
+            enabled: self.capabilities,
+        });
+        if let Some(addr) = &self.management_addr {
+            tlvs.push(Tlv::ManagementAddress(ManagementAddress::ipv4(addr, 0)));
+        }
+        Lldpdu(tlvs)
+    }
+
+    /// Sends an advertisement if `interval` has passed since the last one.
+    pub fn poll(&mut self, link: &mut Interface) -> io::Result<()> {
+        let now = Instant::now();
+        self.neighbors.retain(|_, neighbor| neighbor.expires > now);
+
+        if let Some(last_sent) = self.last_sent {
+            if now - last_sent < self.interval {
+                return Ok(());
+            }
+        }
+        self.last_sent = Some(now);
+        link.send(MULTICAST_ADDR, EthernetPayload::Lldp(self.lldpdu()))
+    }
+
+    /// Tells the neighbors to forget us.
+    pub fn shutdown(&mut self, link: &mut Interface) -> io::Result<()> {
+        self.last_sent = None;
+        link.send(
+            MULTICAST_ADDR,
+            EthernetPayload::Lldp(self.lldpdu_with_ttl(0)),
+        )
+    }
+
+    pub fn receive(&mut self, lldpdu: Lldpdu) {
+        let key = match (lldpdu.chassis_id(), lldpdu.port_id()) {
+            (Some((_, chassis_id)), Some((_, port_id))) => (chassis_id.to_vec(), port_id.to_vec()),
+            _ => return,
+        };
+        match lldpdu.ttl() {
+            Some(0) | None => {
+                self.neighbors.remove(&key);
+            }
+            Some(ttl) => {
+                let expires = Instant::now() + Duration::from_secs(ttl as u64);
+                self.neighbors.insert(key, Neighbor { lldpdu, expires });
+            }
+        }
+    }
+
+    pub fn neighbors(&self) -> impl Iterator<Item = &Neighbor> {
+        self.neighbors.values()
+    }
+}
+
+impl fmt::Display for Lldpdu {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Lldpdu:")?;
+        for tlv in &self.0 {
+            write!(f, "\n  {}", tlv)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Tlv {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Tlv::*;
+        match self {
+            End => write!(f, "end"),
+            ChassisId { subtype, id } => write!(
+                f,
+                "chs: {} (subtype {})",
+                id_to_string(id, *subtype == CHASSIS_ID_MAC_ADDRESS),
+                subtype
+            ),
+            PortId { subtype, id } => write!(
+                f,
+                "prt: {} (subtype {})",
+                id_to_string(id, *subtype == PORT_ID_MAC_ADDRESS),
+                subtype
+            ),
+            Ttl(ttl) => write!(f, "ttl: {}", ttl),
+            PortDescription(s) => write!(f, "pds: {}", s),
+            SystemName(s) => write!(f, "nam: {}", s),
+            SystemDescription(s) => write!(f, "dsc: {}", s),
+            SystemCapabilities {
+                capabilities,
+                enabled,
+            } => write!(f, "cap: 0x{:04x} (enabled 0x{:04x})", capabilities, enabled),
+            ManagementAddress(addr) => {
+                if addr.subtype == ADDRESS_FAMILY_IPV4 && addr.addr.len() == 4 {
+                    let mut b = [0; 4];
+                    b.copy_from_slice(&addr.addr);
+                    write!(f, "mgm: {}", IPAddress(b))?;
+                } else {
+                    write!(
+                        f,
+                        "mgm: {} (family {})",
+                        id_to_string(&addr.addr, false),
+                        addr.subtype
+                    )?;
+                }
+                write!(f, " if {}", addr.if_number)
+            }
+            OrganizationSpecific { oui, subtype, data } => write!(
+                f,
+                "org: {:02x}-{:02x}-{:02x} subtype {} ({} bytes)",
+                oui[0],
+                oui[1],
+                oui[2],
+                subtype,
+                data.len()
+            ),
+            Unknown { typ, data } => write!(f, "Unknown({}) ({} bytes)", typ, data.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn management_addr(len: usize) -> ManagementAddress {
+        ManagementAddress {
+            subtype: ADDRESS_FAMILY_IPV4,
+            addr: vec![1; len],
+            if_subtype: IF_SUBTYPE_IF_INDEX,
+            if_number: 1,
+            oid: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn management_address_round_trips() {
+        let addr = ManagementAddress::ipv4(&IPAddress([10, 0, 0, 4]), 3);
+        let mut v = Vec::new();
+        addr.clone().write_to(&mut v).unwrap();
+        let read = ManagementAddress::read_from(&mut io::Cursor::new(v)).unwrap();
+        assert_eq!(read, addr);
+    }
+
+    #[test]
+    fn long_management_address_is_rejected() {
+        let mut v = Vec::new();
+        assert!(management_addr(MAX_MANAGEMENT_ADDR_LEN)
+            .write_to(&mut v)
+            .is_ok());
+        for len in [0, MAX_MANAGEMENT_ADDR_LEN + 1, 255] {
+            let err = management_addr(len).write_to(&mut Vec::new()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+}
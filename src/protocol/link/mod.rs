@@ -1,5 +1,10 @@
 pub mod address;
 pub mod arp;
+pub mod bridge;
 pub mod ethernet;
 pub mod interface;
+pub mod llc;
+pub mod lldp;
+pub mod stp;
+pub mod wol;
 pub use interface::*;
@@ -0,0 +1,1063 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    time::{Duration, Instant},
+};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use super::address::MacAddress;
+
+pub const MULTICAST_ADDR: MacAddress = MacAddress([0x01, 0x80, 0xc2, 0x00, 0x00, 0x00]);
+
+pub const FLAG_TOPOLOGY_CHANGE: u8 = 0x01;
+pub const FLAG_PROPOSAL: u8 = 0x02;
+pub const FLAG_LEARNING: u8 = 0x10;
+pub const FLAG_FORWARDING: u8 = 0x20;
+pub const FLAG_AGREEMENT: u8 = 0x40;
+pub const FLAG_TOPOLOGY_CHANGE_ACK: u8 = 0x80;
+
+// bits 2 and 3 of the flags of a RST BPDU
+const ROLE_SHIFT: u8 = 2;
+const ROLE_ALTERNATE: u8 = 1;
+const ROLE_ROOT: u8 = 2;
+const ROLE_DESIGNATED: u8 = 3;
+
+const DEFAULT_PRIORITY: u16 = 0x8000;
+const DEFAULT_PORT_PRIORITY: u16 = 0x80;
+// the recommended cost of a 100 Mb/s link
+const DEFAULT_PATH_COST: u32 = 19;
+const MESSAGE_AGE_INCREMENT: Duration = Duration::from_secs(1);
+
+// IEEE 802.1D
+#[derive(Debug, Clone)]
+pub enum Bpdu {
+    Config(ConfigBpdu),
+    TopologyChange,
+    // IEEE 802.1w
+    Rst(ConfigBpdu),
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfigBpdu {
+    pub flags: u8,
+    pub root_id: BridgeId,
+    pub root_path_cost: u32,
+    pub bridge_id: BridgeId,
+    pub port_id: u16,
+    pub message_age: Duration,
+    pub max_age: Duration,
+    pub hello_time: Duration,
+    pub forward_delay: Duration,
+}
+
+/// Lower is better, so the priority is compared first.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BridgeId {
+    pub priority: u16,
+    pub mac_addr: MacAddress,
+}
+
+// the fields of a BPDU by which bridges and ports are elected
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct PriorityVector {
+    root_id: BridgeId,
+    root_path_cost: u32,
+    bridge_id: BridgeId,
+    port_id: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Stp,
+    Rstp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortState {
+    Disabled,
+    Blocking,
+    Listening,
+    Learning,
+    Forwarding,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortRole {
+    Disabled,
+    Root,
+    Designated,
+    Alternate,
+}
+
+#[derive(Debug)]
+struct Received {
+    vector: PriorityVector,
+    message_age: Duration,
+    max_age: Duration,
+    hello_time: Duration,
+    forward_delay: Duration,
+    expires: Instant,
+}
+
+#[derive(Debug)]
+pub struct StpPort {
+    pub state: PortState,
+    pub role: PortRole,
+    pub port_id: u16,
+    pub path_cost: u32,
+    /// An edge port has no bridge behind it and forwards right away.
+    pub edge: bool,
+    received: Option<Received>,
+    changed: Instant,
+    tc_ack: bool,
+    // the neighbour only speaks 802.1D
+    legacy: bool,
+}
+
+/// The spanning tree of one bridge.
+#[derive(Debug)]
+pub struct Stp {
+    pub protocol: Protocol,
+    pub bridge_id: BridgeId,
+    pub bridge_max_age: Duration,
+    pub bridge_hello_time: Duration,
+    pub bridge_forward_delay: Duration,
+    ports: Vec<StpPort>,
+    root_id: BridgeId,
+    root_path_cost: u32,
+    root_port: Option<usize>,
+    last_hello: Option<Instant>,
+    topology_change: Option<Instant>,
+    tcn_pending: bool,
+    relay: bool,
+    pending: Vec<(usize, Bpdu)>,
+}
+
+impl Bpdu {
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        let protocol_id = r.read_u16::<BigEndian>()?;
+        if protocol_id != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown BPDU protocol: 0x{:04x}", protocol_id),
+            ));
+        }
+        let _version = r.read_u8()?;
+        match r.read_u8()? {
+            0x00 => Ok(Self::Config(ConfigBpdu::read_from(r)?)),
+            0x80 => Ok(Self::TopologyChange),
+            0x02 => {
+                let bpdu = ConfigBpdu::read_from(r)?;
+                // version 1 length, always zero
+                let _ = r.read_u8()?;
+                Ok(Self::Rst(bpdu))
+            }
+            x => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown BPDU type: 0x{:02x}", x),
+            )),
+        }
+    }
+
+    pub fn write_to<W: Write>(self, w: &mut W) -> io::Result<()> {
+        w.write_u16::<BigEndian>(0)?;
+        match self {
+            Self::Config(bpdu) => {
+                w.write_u8(0)?;
+                w.write_u8(0x00)?;
+                bpdu.write_to(w)
+            }
+            Self::TopologyChange => {
+                w.write_u8(0)?;
+                w.write_u8(0x80)
+            }
+            Self::Rst(bpdu) => {
+                w.write_u8(2)?;
+                w.write_u8(0x02)?;
+                bpdu.write_to(w)?;
+                w.write_u8(0)
+            }
+        }
+    }
+}
+
+impl ConfigBpdu {
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        Ok(Self {
+            flags: r.read_u8()?,
+            root_id: BridgeId::read_from(r)?,
+            root_path_cost: r.read_u32::<BigEndian>()?,
+            bridge_id: BridgeId::read_from(r)?,
+            port_id: r.read_u16::<BigEndian>()?,
+            message_age: read_time(r)?,
+            max_age: read_time(r)?,
+            hello_time: read_time(r)?,
+            forward_delay: read_time(r)?,
+        })
+    }
+
+    pub fn write_to<W: Write>(self, w: &mut W) -> io::Result<()> {
+        w.write_u8(self.flags)?;
+        self.root_id.write_to(w)?;
+        w.write_u32::<BigEndian>(self.root_path_cost)?;
+        self.bridge_id.write_to(w)?;
+        w.write_u16::<BigEndian>(self.port_id)?;
+        write_time(w, self.message_age)?;
+        write_time(w, self.max_age)?;
+        write_time(w, self.hello_time)?;
+        write_time(w, self.forward_delay)
+    }
+
+    pub fn role(&self) -> Option<PortRole> {
+        match (self.flags >> ROLE_SHIFT) & 0x03 {
+            ROLE_ALTERNATE => Some(PortRole::Alternate),
+            ROLE_ROOT => Some(PortRole::Root),
+            ROLE_DESIGNATED => Some(PortRole::Designated),
+            _ => None,
+        }
+    }
+
+    fn vector(&self) -> PriorityVector {
+        PriorityVector {
+            root_id: self.root_id.clone(),
+            root_path_cost: self.root_path_cost,
+            bridge_id: self.bridge_id.clone(),
+            port_id: self.port_id,
+        }
+    }
+}
+
+// timers are in units of 1/256 seconds
+fn read_time<R: Read>(r: &mut R) -> io::Result<Duration> {
+    let v = r.read_u16::<BigEndian>()?;
+    Ok(Duration::from_millis(v as u64 * 1000 / 256))
+}
+
+fn write_time<W: Write>(w: &mut W, d: Duration) -> io::Result<()> {
+    w.write_u16::<BigEndian>((d.as_millis() * 256 / 1000) as u16)
+}
+
+impl BridgeId {
+    pub fn new(priority: u16, mac_addr: MacAddress) -> Self {
+        Self { priority, mac_addr }
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        Ok(Self {
+            priority: r.read_u16::<BigEndian>()?,
+            mac_addr: MacAddress::read_from(r)?,
+        })
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_u16::<BigEndian>(self.priority)?;
+        w.write_all(&self.mac_addr.0)
+    }
+}
+
+impl StpPort {
+    fn new(number: usize) -> Self {
+        Self {
+            state: PortState::Blocking,
+            role: PortRole::Designated,
+            port_id: (DEFAULT_PORT_PRIORITY << 8) | (number as u16 + 1),
+            path_cost: DEFAULT_PATH_COST,
+            edge: false,
+            received: None,
+            changed: Instant::now(),
+            tc_ack: false,
+            legacy: false,
+        }
+    }
+
+    pub fn is_learning(&self) -> bool {
+        matches!(self.state, PortState::Learning | PortState::Forwarding)
+    }
+
+    pub fn is_forwarding(&self) -> bool {
+        self.state == PortState::Forwarding
+    }
+}
+
+impl Stp {
+    pub fn new(mac_addr: MacAddress, protocol: Protocol) -> Self {
+        let bridge_id = BridgeId::new(DEFAULT_PRIORITY, mac_addr);
+        Self {
+            protocol,
+            root_id: bridge_id.clone(),
+            bridge_id,
+            bridge_max_age: Duration::from_secs(20),
+            bridge_hello_time: Duration::from_secs(2),
+            bridge_forward_delay: Duration::from_secs(15),
+            ports: Vec::new(),
+            root_path_cost: 0,
+            root_port: None,
+            last_hello: None,
+            topology_change: None,
+            tcn_pending: false,
+            relay: false,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn add_port(&mut self) -> usize {
+        self.ports.push(StpPort::new(self.ports.len()));
+        self.update_roles(Instant::now());
+        self.ports.len() - 1
+    }
+
+    pub fn port(&self, port: usize) -> &StpPort {
+        &self.ports[port]
+    }
+
+    pub fn ports(&self) -> &[StpPort] {
+        &self.ports
+    }
+
+    pub fn set_edge(&mut self, port: usize, edge: bool) {
+        self.ports[port].edge = edge;
+        self.update_roles(Instant::now());
+    }
+
+    pub fn set_path_cost(&mut self, port: usize, path_cost: u32) {
+        self.ports[port].path_cost = path_cost;
+        self.update_roles(Instant::now());
+    }
+
+    pub fn set_enabled(&mut self, port: usize, enabled: bool) {
+        let p = &mut self.ports[port];
+        if enabled == (p.role != PortRole::Disabled) {
+            return;
+        }
+        p.received = None;
+        if enabled {
+            p.role = PortRole::Designated;
+            p.state = PortState::Blocking;
+        } else {
+            p.role = PortRole::Disabled;
+            p.state = PortState::Disabled;
+        }
+        self.update_roles(Instant::now());
+    }
+
+    pub fn root_id(&self) -> &BridgeId {
+        &self.root_id
+    }
+
+    pub fn root_path_cost(&self) -> u32 {
+        self.root_path_cost
+    }
+
+    pub fn root_port(&self) -> Option<usize> {
+        self.root_port
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.root_port.is_none()
+    }
+
+    /// The timers of the root bridge, which every bridge in the tree uses.
+    pub fn times(&self) -> (Duration, Duration, Duration) {
+        match self.root_port.and_then(|i| self.ports[i].received.as_ref()) {
+            Some(r) => (r.max_age, r.hello_time, r.forward_delay),
+            None => (
+                self.bridge_max_age,
+                self.bridge_hello_time,
+                self.bridge_forward_delay,
+            ),
+        }
+    }
+
+    pub fn hello_time(&self) -> Duration {
+        self.times().1
+    }
+
+    pub fn forward_delay(&self) -> Duration {
+        self.times().2
+    }
+
+    /// Whether the tree is changing, during which the filtering database
+    /// should age out entries after `forward_delay`.
+    pub fn topology_change(&self) -> bool {
+        self.topology_change
+            .is_some_and(|until| Instant::now() < until)
+    }
+
+    pub fn receive(&mut self, port: usize, bpdu: Bpdu) {
+        let now = Instant::now();
+        if self.ports[port].role == PortRole::Disabled {
+            return;
+        }
+        // there is a bridge behind it after all
+        self.ports[port].edge = false;
+
+        let (bpdu, rst) = match bpdu {
+            Bpdu::TopologyChange => {
+                if self.ports[port].role == PortRole::Designated {
+                    self.ports[port].tc_ack = true;
+                    self.relay = true;
+                    self.detected_topology_change(now);
+                }
+                return;
+            }
+            Bpdu::Config(bpdu) => (bpdu, false),
+            Bpdu::Rst(bpdu) => (bpdu, true),
+        };
+        self.ports[port].legacy = !rst;
+        if bpdu.message_age >= bpdu.max_age {
+            return;
+        }
+
+        let vector = bpdu.vector();
+        // an agreement carries our own information back to us
+        let agreement = rst
+            && bpdu.flags & FLAG_AGREEMENT != 0
+            && self.protocol == Protocol::Rstp
+            && bpdu.role() == Some(PortRole::Root);
+        let own = vector.bridge_id == self.bridge_id && vector.port_id == self.ports[port].port_id;
+        if own && !agreement {
+            // our own BPDU
+            return;
+        }
+        let designated = self.designated_vector(port);
+        let same_sender = self.ports[port].received.as_ref().is_some_and(|r| {
+            r.vector.bridge_id == vector.bridge_id && r.vector.port_id == vector.port_id
+        });
+        if vector >= designated && !same_sender {
+            // inferior information, which the designated port answers with
+            // its own, unless it is the agreement to our proposal
+            if self.ports[port].role == PortRole::Designated {
+                if agreement {
+                    self.set_state(port, PortState::Forwarding, now);
+                } else {
+                    self.relay = true;
+                }
+            }
+            return;
+        }
+
+        let expires = now + (bpdu.max_age - bpdu.message_age);
+        self.ports[port].received = Some(Received {
+            vector,
+            message_age: bpdu.message_age,
+            max_age: bpdu.max_age,
+            hello_time: bpdu.hello_time,
+            forward_delay: bpdu.forward_delay,
+            expires,
+        });
+        self.update_roles(now);
+
+        if self.root_port != Some(port) {
+            return;
+        }
+        if bpdu.flags & FLAG_TOPOLOGY_CHANGE != 0 {
+            let (max_age, _, forward_delay) = self.times();
+            self.topology_change = Some(now + max_age + forward_delay);
+        }
+        if bpdu.flags & FLAG_TOPOLOGY_CHANGE_ACK != 0 {
+            self.tcn_pending = false;
+        }
+        self.relay = true;
+
+        if rst && self.protocol == Protocol::Rstp && bpdu.flags & FLAG_PROPOSAL != 0 {
+            self.agree(port, now);
+        }
+    }
+
+    /// Returns the BPDUs to send, with the ports to send them on.
+    pub fn tick(&mut self) -> Vec<(usize, Bpdu)> {
+        let now = Instant::now();
+
+        let mut expired = false;
+        for p in &mut self.ports {
+            if p.received.as_ref().is_some_and(|r| r.expires <= now) {
+                p.received = None;
+                expired = true;
+            }
+        }
+        if expired {
+            self.update_roles(now);
+        }
+
+        let forward_delay = self.forward_delay();
+        for i in 0..self.ports.len() {
+            let p = &self.ports[i];
+            if now - p.changed < forward_delay {
+                continue;
+            }
+            match p.state {
+                PortState::Listening => self.set_state(i, PortState::Learning, now),
+                PortState::Learning => self.set_state(i, PortState::Forwarding, now),
+                _ => {}
+            }
+        }
+
+        let mut bpdus = std::mem::take(&mut self.pending);
+        let hello = self
+            .last_hello
+            .is_none_or(|last| now - last >= self.hello_time());
+        // 802.1D bridges other than the root only relay what they hear
+        let send = if self.is_root() || self.protocol == Protocol::Rstp {
+            hello || self.relay
+        } else {
+            self.relay
+        };
+        if send {
+            if hello {
+                self.last_hello = Some(now);
+            }
+            self.relay = false;
+            for i in 0..self.ports.len() {
+                if self.ports[i].role == PortRole::Designated {
+                    let bpdu = self.bpdu(i, 0);
+                    bpdus.push((i, bpdu));
+                }
+            }
+        }
+        if hello && self.tcn_pending {
+            if let Some(root_port) = self.root_port {
+                if self.ports[root_port].legacy || self.protocol == Protocol::Stp {
+                    bpdus.push((root_port, Bpdu::TopologyChange));
+                }
+            }
+        }
+        bpdus
+    }
+
+    // the priority vector the port would send as the designated port
+    fn designated_vector(&self, port: usize) -> PriorityVector {
+        PriorityVector {
+            root_id: self.root_id.clone(),
+            root_path_cost: self.root_path_cost,
+            bridge_id: self.bridge_id.clone(),
+            port_id: self.ports[port].port_id,
+        }
+    }
+
+    fn bpdu(&mut self, port: usize, flags: u8) -> Bpdu {
+        let (max_age, hello_time, forward_delay) = self.times();
+        let message_age = match self.root_port.and_then(|i| self.ports[i].received.as_ref()) {
+            Some(r) => r.message_age + MESSAGE_AGE_INCREMENT,
+            None => Duration::from_secs(0),
+        };
+
+        let mut flags = flags;
+        if self.topology_change() {
+            flags |= FLAG_TOPOLOGY_CHANGE;
+        }
+        let designated = self.designated_vector(port);
+
+        let p = &mut self.ports[port];
+        if p.tc_ack {
+            p.tc_ack = false;
+            flags |= FLAG_TOPOLOGY_CHANGE_ACK;
+        }
+        let rst = self.protocol == Protocol::Rstp && !p.legacy;
+        if rst {
+            let role = match p.role {
+                PortRole::Root => ROLE_ROOT,
+                PortRole::Designated => ROLE_DESIGNATED,
+                _ => ROLE_ALTERNATE,
+            };
+            flags |= role << ROLE_SHIFT;
+            if p.is_learning() {
+                flags |= FLAG_LEARNING;
+            }
+            if p.is_forwarding() {
+                flags |= FLAG_FORWARDING;
+            } else if p.role == PortRole::Designated {
+                flags |= FLAG_PROPOSAL;
+            }
+        }
+
+        let vector = match (&p.role, &p.received) {
+            // an agreement echoes the designated port's information
+            (PortRole::Root, Some(r)) => r.vector.clone(),
+            _ => designated,
+        };
+        let bpdu = ConfigBpdu {
+            flags,
+            root_id: vector.root_id,
+            root_path_cost: vector.root_path_cost,
+            bridge_id: vector.bridge_id,
+            port_id: vector.port_id,
+            message_age,
+            max_age,
+            hello_time,
+            forward_delay,
+        };
+        if rst {
+            Bpdu::Rst(bpdu)
+        } else {
+            Bpdu::Config(bpdu)
+        }
+    }
+
+    // RSTP sync: block every designated port towards the rest of the tree
+    // so the new root port can forward right away without making a loop
+    fn agree(&mut self, port: usize, now: Instant) {
+        for i in 0..self.ports.len() {
+            let p = &self.ports[i];
+            if i != port && p.role == PortRole::Designated && !p.edge && p.is_learning() {
+                self.set_state(i, PortState::Blocking, now);
+                self.set_state(i, PortState::Listening, now);
+            }
+        }
+        self.set_state(port, PortState::Forwarding, now);
+        let bpdu = self.bpdu(port, FLAG_AGREEMENT);
+        self.pending.push((port, bpdu));
+    }
+
+    fn update_roles(&mut self, now: Instant) {
+        let own = PriorityVector {
+            root_id: self.bridge_id.clone(),
+            root_path_cost: 0,
+            bridge_id: self.bridge_id.clone(),
+            port_id: 0,
+        };
+
+        // the best path to the root, ties are broken by our own port id
+        let mut best: Option<(PriorityVector, u16, usize)> = None;
+        for (i, p) in self.ports.iter().enumerate() {
+            let r = match &p.received {
+                Some(r) if p.role != PortRole::Disabled => r,
+                _ => continue,
+            };
+            if r.vector.bridge_id == self.bridge_id {
+                continue;
+            }
+            let mut vector = r.vector.clone();
+            vector.root_path_cost = vector.root_path_cost.saturating_add(p.path_cost);
+            let candidate = (vector, p.port_id, i);
+            if best
+                .as_ref()
+                .is_none_or(|b| (&candidate.0, candidate.1) < (&b.0, b.1))
+            {
+                best = Some(candidate);
+            }
+        }
+
+        let was_root = self.is_root();
+        match best {
+            Some((vector, _, i)) if vector.root_id < own.root_id => {
+                self.root_id = vector.root_id;
+                self.root_path_cost = vector.root_path_cost;
+                self.root_port = Some(i);
+            }
+            _ => {
+                self.root_id = own.root_id;
+                self.root_path_cost = 0;
+                self.root_port = None;
+            }
+        }
+        if !was_root && self.is_root() {
+            self.tcn_pending = false;
+        }
+
+        for i in 0..self.ports.len() {
+            if self.ports[i].role == PortRole::Disabled {
+                continue;
+            }
+            let role = if self.root_port == Some(i) {
+                PortRole::Root
+            } else {
+                let designated = self.designated_vector(i);
+                match &self.ports[i].received {
+                    Some(r) if r.vector < designated => PortRole::Alternate,
+                    _ => PortRole::Designated,
+                }
+            };
+            self.set_role(i, role, now);
+        }
+    }
+
+    fn set_role(&mut self, port: usize, role: PortRole, now: Instant) {
+        let p = &self.ports[port];
+        let changed = p.role != role;
+        let state = p.state;
+        let edge = p.edge;
+        self.ports[port].role = role;
+        if changed {
+            self.relay = true;
+        }
+
+        match role {
+            PortRole::Alternate => self.set_state(port, PortState::Blocking, now),
+            PortRole::Designated if edge => self.set_state(port, PortState::Forwarding, now),
+            PortRole::Root | PortRole::Designated if state == PortState::Blocking => {
+                self.set_state(port, PortState::Listening, now)
+            }
+            _ => {}
+        }
+    }
+
+    fn set_state(&mut self, port: usize, state: PortState, now: Instant) {
+        let p = &mut self.ports[port];
+        if p.state == state {
+            return;
+        }
+        let old = p.state;
+        p.state = state;
+        p.changed = now;
+        let edge = p.edge;
+
+        let forwarding_changed = state == PortState::Forwarding || old == PortState::Forwarding;
+        if forwarding_changed && !edge {
+            self.detected_topology_change(now);
+        }
+    }
+
+    fn detected_topology_change(&mut self, now: Instant) {
+        let (max_age, hello_time, forward_delay) = self.times();
+        if self.is_root() {
+            self.topology_change = Some(now + max_age + forward_delay);
+        } else if self.protocol == Protocol::Rstp {
+            // RSTP floods the change with the flag instead of notifications
+            self.topology_change = Some(now + hello_time * 2);
+            self.relay = true;
+        } else {
+            self.tcn_pending = true;
+        }
+    }
+}
+
+impl fmt::Display for Bpdu {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Config(bpdu) => {
+                writeln!(f, "Bpdu (Config):")?;
+                write!(f, "{}", bpdu)
+            }
+            Self::TopologyChange => write!(f, "Bpdu (Topology Change Notification)"),
+            Self::Rst(bpdu) => {
+                writeln!(f, "Bpdu (RST):")?;
+                write!(f, "{}", bpdu)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ConfigBpdu {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "  flg: 0x{:02x}", self.flags)?;
+        writeln!(f, "  rid: {}", self.root_id)?;
+        writeln!(f, "  rpc: {}", self.root_path_cost)?;
+        writeln!(f, "  bid: {}", self.bridge_id)?;
+        writeln!(f, "  pid: 0x{:04x}", self.port_id)?;
+        writeln!(f, "  age: {}ms", self.message_age.as_millis())?;
+        writeln!(f, "  max: {}ms", self.max_age.as_millis())?;
+        writeln!(f, "  hlo: {}ms", self.hello_time.as_millis())?;
+        write!(f, "  fwd: {}ms", self.forward_delay.as_millis())?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for BridgeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04x}.{}", self.priority, self.mac_addr)
+    }
+}
+
+impl fmt::Display for Stp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Stp:")?;
+        writeln!(f, "  bid: {}", self.bridge_id)?;
+        writeln!(f, "  rid: {}", self.root_id)?;
+        write!(f, "  rpc: {}", self.root_path_cost)?;
+        for (i, p) in self.ports.iter().enumerate() {
+            write!(f, "\n  port {}: {:?} {:?}", i, p.role, p.state)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, thread};
+
+    use super::*;
+
+    fn id(x: u8) -> BridgeId {
+        BridgeId::new(DEFAULT_PRIORITY, MacAddress([2, 0, 0, 0, 0, x]))
+    }
+
+    // bridge `x` with `ports` ports, whose timers run out at the first tick
+    fn stp(x: u8, protocol: Protocol, ports: usize) -> Stp {
+        let mut stp = Stp::new(MacAddress([2, 0, 0, 0, 0, x]), protocol);
+        stp.bridge_forward_delay = Duration::ZERO;
+        for _ in 0..ports {
+            stp.add_port();
+        }
+        stp
+    }
+
+    // what bridge `bridge` sends on its port 1 about the root `root`
+    fn config(root: u8, cost: u32, bridge: u8, flags: u8) -> ConfigBpdu {
+        ConfigBpdu {
+            flags,
+            root_id: id(root),
+            root_path_cost: cost,
+            bridge_id: id(bridge),
+            port_id: 0x8001,
+            message_age: Duration::ZERO,
+            max_age: Duration::from_secs(20),
+            hello_time: Duration::from_secs(2),
+            forward_delay: Duration::ZERO,
+        }
+    }
+
+    fn to_bytes(bpdu: Bpdu) -> Vec<u8> {
+        let mut v = Vec::new();
+        bpdu.write_to(&mut v).unwrap();
+        v
+    }
+
+    fn roles(stp: &Stp) -> Vec<(PortRole, PortState)> {
+        stp.ports().iter().map(|p| (p.role, p.state)).collect()
+    }
+
+    #[test]
+    fn bpdus_round_trip() {
+        let mut bpdu = config(1, 19, 2, FLAG_TOPOLOGY_CHANGE);
+        bpdu.message_age = Duration::from_secs(1);
+        bpdu.forward_delay = Duration::from_secs(15);
+        for (bpdu, len) in [
+            (Bpdu::Config(bpdu.clone()), 35),
+            (Bpdu::TopologyChange, 4),
+            (Bpdu::Rst(bpdu), 36),
+        ] {
+            let b = to_bytes(bpdu);
+            assert_eq!(b.len(), len);
+            let read = Bpdu::read_from(&mut Cursor::new(&b)).unwrap();
+            assert_eq!(to_bytes(read), b);
+        }
+    }
+
+    #[test]
+    fn bpdu_fields_are_where_802_1d_puts_them() {
+        let mut bpdu = config(1, 19, 2, FLAG_TOPOLOGY_CHANGE);
+        bpdu.forward_delay = Duration::from_secs(15);
+        let b = to_bytes(Bpdu::Config(bpdu));
+        assert_eq!(b[..5], [0, 0, 0, 0, FLAG_TOPOLOGY_CHANGE]);
+        assert_eq!(b[5..13], [0x80, 0, 2, 0, 0, 0, 0, 1]);
+        assert_eq!(b[13..17], 19u32.to_be_bytes());
+        assert_eq!(b[25..27], [0x80, 0x01]);
+        // timers are in 1/256 seconds
+        assert_eq!(b[29..31], (20u16 * 256).to_be_bytes());
+        assert_eq!(b[33..35], (15u16 * 256).to_be_bytes());
+
+        let read = match Bpdu::read_from(&mut Cursor::new(&b)).unwrap() {
+            Bpdu::Config(bpdu) => bpdu,
+            bpdu => panic!("{:?}", bpdu),
+        };
+        assert_eq!(read.root_id, id(1));
+        assert_eq!(read.bridge_id, id(2));
+        assert_eq!(read.max_age, Duration::from_secs(20));
+    }
+
+    #[test]
+    fn unknown_bpdus_are_rejected() {
+        for b in [[0, 1, 0, 0], [0, 0, 0, 0x42]] {
+            let err = Bpdu::read_from(&mut Cursor::new(&b)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn a_lone_bridge_is_the_root() {
+        let stp = stp(5, Protocol::Stp, 2);
+        assert!(stp.is_root());
+        assert_eq!(stp.root_id(), &id(5));
+        assert_eq!(
+            roles(&stp),
+            [(PortRole::Designated, PortState::Listening); 2]
+        );
+    }
+
+    #[test]
+    fn the_root_is_reached_by_the_cheapest_port() {
+        let mut stp = stp(5, Protocol::Stp, 3);
+        stp.receive(0, Bpdu::Config(config(1, 10, 2, 0)));
+        stp.receive(1, Bpdu::Config(config(1, 0, 1, 0)));
+
+        assert!(!stp.is_root());
+        assert_eq!(stp.root_id(), &id(1));
+        assert_eq!(stp.root_port(), Some(1));
+        assert_eq!(stp.root_path_cost(), DEFAULT_PATH_COST);
+        // bridge 2 is closer to the root than we are on the first link
+        assert_eq!(
+            roles(&stp),
+            [
+                (PortRole::Alternate, PortState::Blocking),
+                (PortRole::Root, PortState::Listening),
+                (PortRole::Designated, PortState::Listening),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_better_bridge_on_a_link_is_not_elected_root_when_it_is_not() {
+        let mut stp = stp(5, Protocol::Stp, 2);
+        stp.receive(0, Bpdu::Config(config(9, 0, 9, 0)));
+        assert!(stp.is_root());
+        assert_eq!(roles(&stp)[0].0, PortRole::Designated);
+    }
+
+    #[test]
+    fn equal_paths_are_decided_by_the_port_id() {
+        let mut stp = stp(5, Protocol::Stp, 2);
+        stp.receive(1, Bpdu::Config(config(1, 0, 1, 0)));
+        stp.receive(0, Bpdu::Config(config(1, 0, 1, 0)));
+        assert_eq!(stp.root_port(), Some(0));
+        assert_eq!(roles(&stp)[1].0, PortRole::Alternate);
+
+        stp.set_path_cost(0, 100);
+        assert_eq!(stp.root_port(), Some(1));
+    }
+
+    #[test]
+    fn ports_forward_after_listening_and_learning() {
+        let mut stp = stp(5, Protocol::Stp, 1);
+        stp.bridge_forward_delay = Duration::from_secs(15);
+        stp.tick();
+        assert_eq!(stp.port(0).state, PortState::Listening);
+
+        stp.bridge_forward_delay = Duration::ZERO;
+        stp.tick();
+        assert_eq!(stp.port(0).state, PortState::Learning);
+        assert!(stp.port(0).is_learning() && !stp.port(0).is_forwarding());
+        stp.tick();
+        assert!(stp.port(0).is_forwarding());
+    }
+
+    #[test]
+    fn edge_ports_forward_at_once_until_a_bpdu_arrives() {
+        let mut stp = stp(5, Protocol::Rstp, 2);
+        stp.set_edge(0, true);
+        assert!(stp.port(0).is_forwarding());
+        assert!(!stp.topology_change());
+
+        stp.receive(0, Bpdu::Rst(config(1, 0, 1, 0)));
+        assert!(!stp.port(0).edge);
+    }
+
+    #[test]
+    fn information_is_forgotten_after_its_max_age() {
+        let mut stp = stp(5, Protocol::Stp, 1);
+        let mut bpdu = config(1, 0, 1, 0);
+        bpdu.max_age = Duration::from_millis(1);
+        stp.receive(0, Bpdu::Config(bpdu));
+        assert!(!stp.is_root());
+
+        thread::sleep(Duration::from_millis(2));
+        stp.tick();
+        assert!(stp.is_root());
+        assert_eq!(stp.port(0).role, PortRole::Designated);
+    }
+
+    #[test]
+    fn the_root_announces_a_change_when_a_port_starts_forwarding() {
+        let mut stp = stp(5, Protocol::Stp, 1);
+        stp.tick();
+        assert!(!stp.topology_change());
+        stp.tick();
+        assert!(stp.port(0).is_forwarding());
+        assert!(stp.topology_change());
+
+        // with the next hello
+        stp.last_hello = None;
+        match stp.tick().as_slice() {
+            [(0, Bpdu::Config(bpdu))] => assert_ne!(bpdu.flags & FLAG_TOPOLOGY_CHANGE, 0),
+            bpdus => panic!("{:?}", bpdus),
+        }
+    }
+
+    #[test]
+    fn notifications_go_towards_the_root_and_are_acknowledged() {
+        let mut stp = stp(5, Protocol::Stp, 2);
+        stp.receive(0, Bpdu::Config(config(1, 0, 1, 0)));
+        stp.tick();
+
+        // the notification goes with the next hello, and the answer at once
+        stp.receive(1, Bpdu::TopologyChange);
+        stp.last_hello = None;
+        let bpdus = stp.tick();
+        assert!(bpdus
+            .iter()
+            .any(|(port, bpdu)| *port == 0 && matches!(bpdu, Bpdu::TopologyChange)));
+        assert!(bpdus.iter().any(|(port, bpdu)| *port == 1
+            && matches!(bpdu, Bpdu::Config(b) if b.flags & FLAG_TOPOLOGY_CHANGE_ACK != 0)));
+        assert!(!stp.topology_change());
+
+        // the root acknowledges, and floods the change through the tree
+        let flags = FLAG_TOPOLOGY_CHANGE | FLAG_TOPOLOGY_CHANGE_ACK;
+        stp.receive(0, Bpdu::Config(config(1, 0, 1, flags)));
+        assert!(stp.topology_change());
+        stp.last_hello = None;
+        assert!(!stp
+            .tick()
+            .iter()
+            .any(|(_, bpdu)| matches!(bpdu, Bpdu::TopologyChange)));
+    }
+
+    #[test]
+    fn a_proposal_is_agreed_to_after_syncing() {
+        let mut stp = stp(5, Protocol::Rstp, 2);
+        stp.tick();
+        stp.tick();
+        assert!(stp.port(1).is_forwarding());
+        stp.bridge_forward_delay = Duration::from_secs(15);
+
+        let mut proposal = config(1, 0, 1, FLAG_PROPOSAL | ROLE_DESIGNATED << ROLE_SHIFT);
+        proposal.forward_delay = Duration::from_secs(15);
+        stp.receive(0, Bpdu::Rst(proposal));
+
+        // the new root port forwards at once, and the other port blocks
+        // until it has agreed with the bridge behind it
+        assert_eq!(
+            roles(&stp),
+            [
+                (PortRole::Root, PortState::Forwarding),
+                (PortRole::Designated, PortState::Listening),
+            ]
+        );
+        let agreement = stp.tick().into_iter().find_map(|(port, bpdu)| match bpdu {
+            Bpdu::Rst(bpdu) if port == 0 && bpdu.flags & FLAG_AGREEMENT != 0 => Some(bpdu),
+            _ => None,
+        });
+        let agreement = agreement.unwrap();
+        assert_eq!(agreement.role(), Some(PortRole::Root));
+        assert_eq!(agreement.bridge_id, id(1));
+        assert_eq!(agreement.port_id, 0x8001);
+    }
+
+    #[test]
+    fn a_designated_port_forwards_once_its_proposal_is_agreed_to() {
+        let mut stp = stp(1, Protocol::Rstp, 1);
+        stp.bridge_forward_delay = Duration::from_secs(15);
+        let proposal = match stp.tick().as_slice() {
+            [(0, Bpdu::Rst(bpdu))] => bpdu.clone(),
+            bpdus => panic!("{:?}", bpdus),
+        };
+        assert_ne!(proposal.flags & FLAG_PROPOSAL, 0);
+        assert_eq!(proposal.role(), Some(PortRole::Designated));
+
+        let mut agreement = proposal;
+        agreement.flags = FLAG_AGREEMENT | FLAG_FORWARDING | ROLE_ROOT << ROLE_SHIFT;
+        stp.receive(0, Bpdu::Rst(agreement));
+        assert!(stp.port(0).is_forwarding());
+    }
+
+    #[test]
+    fn our_own_bpdus_are_ignored() {
+        let mut stp = stp(5, Protocol::Rstp, 2);
+        let own = match stp.tick().as_slice() {
+            [(0, Bpdu::Rst(bpdu)), ..] => bpdu.clone(),
+            bpdus => panic!("{:?}", bpdus),
+        };
+        stp.receive(0, Bpdu::Rst(own));
+        assert!(stp.is_root());
+        assert_eq!(stp.port(0).role, PortRole::Designated);
+    }
+}
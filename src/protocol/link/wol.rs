@@ -0,0 +1,186 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+};
+
+use super::address::MacAddress;
+
+const SYNC: [u8; 6] = [0xff; 6];
+const REPEAT: usize = 16;
+
+/// A Wake-on-LAN magic packet, sent with EtherType 0x0842. It may also be
+/// carried in a UDP broadcast to port 9, which needs UDP first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MagicPacket {
+    pub mac_addr: MacAddress,
+    /// The SecureOn password, of 4 or 6 bytes.
+    pub password: Option<Vec<u8>>,
+}
+
+impl MagicPacket {
+    pub fn new(mac_addr: MacAddress) -> Self {
+        Self {
+            mac_addr,
+            password: None,
+        }
+    }
+
+    pub fn with_password(mac_addr: MacAddress, password: Vec<u8>) -> io::Result<Self> {
+        check_password(&password)?;
+        Ok(Self {
+            mac_addr,
+            password: Some(password),
+        })
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut sync = [0; 6];
+        r.read_exact(&mut sync)?;
+        if sync != SYNC {
+            return Err(invalid_data("no synchronization stream"));
+        }
+
+        let mac_addr = MacAddress::read_from(r)?;
+        for _ in 1..REPEAT {
+            if MacAddress::read_from(r)? != mac_addr {
+                return Err(invalid_data("the address is not repeated"));
+            }
+        }
+
+        let mut rest = Vec::new();
+        r.read_to_end(&mut rest)?;
+        // anything else is padding
+        let password = match rest.len() {
+            4 | 6 => Some(rest),
+            _ => None,
+        };
+        Ok(Self { mac_addr, password })
+    }
+
+    pub fn write_to<W: Write>(self, w: &mut W) -> io::Result<()> {
+        w.write_all(&SYNC)?;
+        for _ in 0..REPEAT {
+            w.write_all(&self.mac_addr.0)?;
+        }
+        if let Some(password) = self.password {
+            check_password(&password)?;
+            w.write_all(&password)?;
+        }
+        Ok(())
+    }
+}
+
+fn check_password(password: &[u8]) -> io::Result<()> {
+    match password.len() {
+        4 | 6 => Ok(()),
+        len => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("the password must be 4 or 6 bytes: {} bytes", len),
+        )),
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("magic packet: {}", msg))
+}
+
+impl fmt::Display for MagicPacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "MagicPacket:")?;
+        match &self.password {
+            Some(password) => {
+                writeln!(f, "  mac: {}", self.mac_addr)?;
+                write!(
+                    f,
+                    "  pwd: {}",
+                    password
+                        .iter()
+                        .map(|i| format!("{:02x}", i))
+                        .collect::<Vec<_>>()
+                        .join(":")
+                )?;
+            }
+            None => write!(f, "  mac: {}", self.mac_addr)?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const MAC: MacAddress = MacAddress([2, 0, 0, 0, 0, 1]);
+
+    fn to_bytes(packet: MagicPacket) -> Vec<u8> {
+        let mut v = Vec::new();
+        packet.write_to(&mut v).unwrap();
+        v
+    }
+
+    fn read(b: &[u8]) -> io::Result<MagicPacket> {
+        MagicPacket::read_from(&mut Cursor::new(b))
+    }
+
+    #[test]
+    fn the_address_follows_the_sync_sixteen_times() {
+        let b = to_bytes(MagicPacket::new(MAC));
+        assert_eq!(b.len(), 6 + 16 * 6);
+        assert_eq!(b[..6], [0xff; 6]);
+        assert!(b[6..].chunks(6).all(|chunk| chunk == MAC.0));
+        assert_eq!(read(&b).unwrap(), MagicPacket::new(MAC));
+    }
+
+    #[test]
+    fn passwords_of_four_and_six_bytes_round_trip() {
+        for len in [4, 6] {
+            let packet = MagicPacket::with_password(MAC, vec![7; len]).unwrap();
+            let b = to_bytes(packet.clone());
+            assert_eq!(b.len(), 102 + len);
+            assert_eq!(read(&b).unwrap(), packet);
+        }
+    }
+
+    #[test]
+    fn other_password_lengths_are_refused() {
+        for len in [0, 3, 5, 7] {
+            let err = MagicPacket::with_password(MAC, vec![7; len]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        let packet = MagicPacket {
+            mac_addr: MAC,
+            password: Some(vec![7; 5]),
+        };
+        assert!(packet.write_to(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn trailing_bytes_of_other_lengths_are_padding() {
+        for len in [1, 2, 5, 16] {
+            let mut b = to_bytes(MagicPacket::new(MAC));
+            b.resize(b.len() + len, 0);
+            assert_eq!(read(&b).unwrap(), MagicPacket::new(MAC));
+        }
+    }
+
+    #[test]
+    fn malformed_packets_are_rejected() {
+        let b = to_bytes(MagicPacket::new(MAC));
+
+        let mut no_sync = b.clone();
+        no_sync[0] = 0;
+        assert_eq!(
+            read(&no_sync).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let mut other = b.clone();
+        other[100] ^= 1;
+        assert_eq!(read(&other).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let err = read(&b[..b.len() - 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -5,16 +5,33 @@ pub mod physical;
 use internet::ip::IPPayload;
 use link::ethernet::EthernetFrame;
 use link::ethernet::EthernetPayload;
+use link::llc::LlcPayload;
 
 pub fn dump(frame: &EthernetFrame) {
     println!("{}", frame.header);
-    match &frame.payload {
-        EthernetPayload::Arp(arp) => println!("{}", arp),
+    dump_payload(&frame.payload);
+}
+
+fn dump_payload(payload: &EthernetPayload) {
+    match payload {
+        EthernetPayload::Arp(arp) | EthernetPayload::Rarp(arp) => println!("{}", arp),
         EthernetPayload::IP(ip) => {
             println!("{}", ip.header);
-            match &ip.payload {
-                IPPayload::Icmp(icmp) => println!("{}", icmp),
-                _ => {}
+            if let IPPayload::Icmp(icmp) = &ip.payload {
+                println!("{}", icmp);
+            }
+        }
+        EthernetPayload::Lldp(lldp) => println!("{}", lldp),
+        EthernetPayload::WakeOnLan(packet) => println!("{}", packet),
+        EthernetPayload::Llc(llc) => {
+            println!("{}", llc.header);
+            match &llc.payload {
+                LlcPayload::Snap(snap, payload) => {
+                    println!("{}", snap);
+                    dump_payload(payload);
+                }
+                LlcPayload::Stp(bpdu) => println!("{}", bpdu),
+                LlcPayload::Raw(v) => println!("Raw: {} bytes", v.len()),
             }
         }
         _ => {}
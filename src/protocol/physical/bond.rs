@@ -0,0 +1,516 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    io::{self, Read, Write},
+    os::unix::io::{AsRawFd, RawFd},
+    time::{Duration, Instant},
+};
+
+use crate::protocol::{
+    internet::address::IPAddress,
+    link::{
+        address::MacAddress,
+        arp::{Arp, Opcode},
+        ethernet::{EtherType, EthernetFrame, EthernetHeader, EthernetPayload},
+    },
+};
+
+use super::{sys, Device};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BondEvent {
+    LinkDown(usize),
+    LinkUp(usize),
+    /// Nothing was received within twice the ARP interval.
+    ArpFailed(usize),
+    ArpRecovered(usize),
+    Failover {
+        from: Option<usize>,
+        to: usize,
+    },
+    /// No member is usable.
+    NoActive,
+}
+
+/// Checks the members by sending ARP requests to `target` through the active
+/// member and watching each member for received traffic.
+#[derive(Debug, Clone)]
+pub struct ArpMonitor {
+    pub target: IPAddress,
+    /// Probes are sent from 0.0.0.0 if this is unspecified.
+    pub source: IPAddress,
+    pub interval: Duration,
+}
+
+struct Member {
+    dev: Box<dyn Device>,
+    link_up: bool,
+    arp_ok: bool,
+    last_rx: Instant,
+}
+
+struct Inner {
+    members: Vec<Member>,
+    active: Option<usize>,
+    primary: Option<usize>,
+    mac_addr: MacAddress,
+    ip_addr: Option<IPAddress>,
+    miimon: Duration,
+    last_mii: Option<Instant>,
+    arp_monitor: Option<ArpMonitor>,
+    last_arp: Option<Instant>,
+    events: VecDeque<BondEvent>,
+}
+
+/// Bonds several devices into one in active-backup mode, like mode 1 of
+/// Linux bonding: frames go through the active member only, and another
+/// member takes over when it fails.
+pub struct BondDevice {
+    name: String,
+    inner: RefCell<Inner>,
+}
+
+impl BondDevice {
+    /// The first member is active at first, and its address becomes the
+    /// address of the bond.
+    pub fn new(name: String, devs: Vec<Box<dyn Device>>) -> io::Result<Self> {
+        if devs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a bond needs at least one member",
+            ));
+        }
+        let mac_addr = devs[0].address()?;
+        let now = Instant::now();
+        let members = devs
+            .into_iter()
+            .map(|dev| Member {
+                link_up: dev.is_up().unwrap_or(false),
+                dev,
+                arp_ok: true,
+                last_rx: now,
+            })
+            .collect();
+
+        let mut inner = Inner {
+            members,
+            active: None,
+            primary: None,
+            mac_addr,
+            ip_addr: None,
+            miimon: Duration::from_millis(100),
+            last_mii: None,
+            arp_monitor: None,
+            last_arp: None,
+            events: VecDeque::new(),
+        };
+        inner.failover()?;
+        Ok(Self {
+            name,
+            inner: RefCell::new(inner),
+        })
+    }
+
+    pub fn active(&self) -> Option<usize> {
+        self.inner.borrow().active
+    }
+
+    pub fn member_name(&self, member: usize) -> String {
+        self.inner.borrow().members[member].dev.name()
+    }
+
+    /// The member to go back to whenever it is usable.
+    pub fn set_primary(&mut self, primary: Option<usize>) -> io::Result<()> {
+        let inner = self.inner.get_mut();
+        inner.primary = primary;
+        inner.failover()
+    }
+
+    /// The address announced with gratuitous ARP after a failover.
+    pub fn set_ip_addr(&mut self, ip_addr: Option<IPAddress>) {
+        self.inner.get_mut().ip_addr = ip_addr;
+    }
+
+    /// How often the link state of the members is checked.
+    pub fn set_miimon(&mut self, interval: Duration) {
+        self.inner.get_mut().miimon = interval;
+    }
+
+    pub fn set_arp_monitor(&mut self, arp_monitor: Option<ArpMonitor>) {
+        let inner = self.inner.get_mut();
+        inner.arp_monitor = arp_monitor;
+        inner.last_arp = None;
+        let now = Instant::now();
+        for member in &mut inner.members {
+            member.arp_ok = true;
+            member.last_rx = now;
+        }
+    }
+
+    pub fn next_event(&mut self) -> Option<BondEvent> {
+        self.inner.get_mut().events.pop_front()
+    }
+
+    /// Checks the members and fails over if needed. This runs while polling,
+    /// reading and writing, so it only has to be called by those who do none
+    /// of them for a while.
+    pub fn monitor(&mut self) -> io::Result<()> {
+        self.inner.get_mut().monitor()
+    }
+}
+
+impl Inner {
+    fn is_usable(&self, member: usize) -> bool {
+        let m = &self.members[member];
+        m.link_up && m.arp_ok
+    }
+
+    fn monitor(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        self.drain_backups(now)?;
+
+        if self.last_mii.is_none_or(|last| now - last >= self.miimon) {
+            self.last_mii = Some(now);
+            for i in 0..self.members.len() {
+                let up = self.members[i].dev.is_up().unwrap_or(false);
+                if up != self.members[i].link_up {
+                    self.members[i].link_up = up;
+                    self.events.push_back(if up {
+                        BondEvent::LinkUp(i)
+                    } else {
+                        BondEvent::LinkDown(i)
+                    });
+                }
+            }
+        }
+
+        if let Some(arp_monitor) = self.arp_monitor.clone() {
+            if self
+                .last_arp
+                .is_none_or(|last| now - last >= arp_monitor.interval)
+            {
+                self.last_arp = Some(now);
+                for i in 0..self.members.len() {
+                    let ok = now - self.members[i].last_rx < arp_monitor.interval * 2;
+                    if ok != self.members[i].arp_ok {
+                        self.members[i].arp_ok = ok;
+                        self.events.push_back(if ok {
+                            BondEvent::ArpRecovered(i)
+                        } else {
+                            BondEvent::ArpFailed(i)
+                        });
+                    }
+                }
+                self.send_arp_probe(&arp_monitor)?;
+            }
+        }
+
+        self.failover()
+    }
+
+    // frames arriving on backups are dropped, but they still show that the
+    // member works, e.g. when it sees the probes of the active member
+    fn drain_backups(&mut self, now: Instant) -> io::Result<()> {
+        let mut buf = [0; 4096];
+        for i in 0..self.members.len() {
+            if Some(i) == self.active {
+                continue;
+            }
+            let m = &mut self.members[i];
+            while m.dev.poll(Some(Duration::from_secs(0)))? {
+                if m.dev.read(&mut buf).is_err() {
+                    break;
+                }
+                m.last_rx = now;
+            }
+        }
+        Ok(())
+    }
+
+    fn send_arp_probe(&mut self, arp_monitor: &ArpMonitor) -> io::Result<()> {
+        if self.active.is_none() {
+            return Ok(());
+        }
+        let arp = Arp::new(
+            Opcode::Request,
+            self.mac_addr.clone(),
+            arp_monitor.source.clone(),
+            MacAddress([0; 6]),
+            arp_monitor.target.clone(),
+        );
+        self.send_arp(arp)
+    }
+
+    fn send_arp(&mut self, arp: Arp) -> io::Result<()> {
+        let frame = EthernetFrame {
+            header: EthernetHeader {
+                dst_addr: MacAddress::broadcast(),
+                src_addr: self.mac_addr.clone(),
+                typ: EtherType::Arp,
+            },
+            payload: EthernetPayload::Arp(arp),
+        };
+        let mut v = Vec::new();
+        frame.write_to(&mut v)?;
+        self.write_active(&v).map(|_| ())
+    }
+
+    /// Makes the best usable member active, if it is not already.
+    fn failover(&mut self) -> io::Result<()> {
+        let primary = self.primary.filter(|&i| self.is_usable(i));
+        let current = self.active.filter(|&i| self.is_usable(i));
+        let next = primary
+            .or(current)
+            .or_else(|| (0..self.members.len()).find(|&i| self.is_usable(i)));
+        if next == self.active {
+            return Ok(());
+        }
+
+        let from = self.active;
+        self.active = next;
+        let to = match next {
+            Some(to) => to,
+            None => {
+                self.events.push_back(BondEvent::NoActive);
+                return Ok(());
+            }
+        };
+        self.events.push_back(BondEvent::Failover { from, to });
+
+        // the member takes on the address of the bond, as with
+        // fail_over_mac=none on Linux, or the NIC filters out what comes to
+        // the bond
+        let member = &self.members[to].dev;
+        if member.address()? != self.mac_addr {
+            member.set_address(&self.mac_addr)?;
+        }
+
+        // give the new member a full interval before judging it
+        self.members[to].last_rx = Instant::now();
+
+        // let the switches learn where our address has gone, and the hosts
+        // refresh their caches
+        if let Some(ip_addr) = self.ip_addr.clone() {
+            let arp = Arp::new(
+                Opcode::Request,
+                self.mac_addr.clone(),
+                ip_addr.clone(),
+                MacAddress([0; 6]),
+                ip_addr,
+            );
+            self.send_arp(arp)?;
+        }
+        Ok(())
+    }
+
+    fn write_active(&mut self, buf: &[u8]) -> io::Result<usize> {
+        loop {
+            let active = self.active.ok_or_else(no_active)?;
+            match self.members[active].dev.write(buf) {
+                Ok(len) => return Ok(len),
+                Err(e) => {
+                    // take the member out and try the next one
+                    self.members[active].link_up = false;
+                    self.events.push_back(BondEvent::LinkDown(active));
+                    self.failover()?;
+                    if self.active.is_none() {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn no_active() -> io::Error {
+    io::Error::new(io::ErrorKind::NotConnected, "no active member")
+}
+
+impl Device for BondDevice {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn address(&self) -> io::Result<MacAddress> {
+        Ok(self.inner.borrow().mac_addr.clone())
+    }
+
+    /// Changes the address of the bond and of its active member. The others
+    /// take it on when they become active.
+    fn set_address(&self, addr: &MacAddress) -> io::Result<()> {
+        let mut inner = self.inner.borrow_mut();
+        if let Some(active) = inner.active {
+            inner.members[active].dev.set_address(addr)?;
+        }
+        inner.mac_addr = addr.clone();
+        Ok(())
+    }
+
+    fn is_up(&self) -> io::Result<bool> {
+        Ok(self.inner.borrow().active.is_some())
+    }
+
+    fn mtu(&self) -> io::Result<usize> {
+        let inner = self.inner.borrow();
+        let member = inner.active.unwrap_or(0);
+        inner.members[member].dev.mtu()
+    }
+
+    /// Waits on the active member, monitoring every `miimon` meanwhile.
+    fn poll(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let mut inner = self.inner.borrow_mut();
+            inner.monitor()?;
+
+            let mut wait = inner.miimon;
+            if let Some(deadline) = deadline {
+                wait = wait.min(deadline.saturating_duration_since(Instant::now()));
+            }
+            if let Some(active) = inner.active {
+                if sys::poll(inner.members[active].dev.as_raw_fd(), Some(wait))? {
+                    return Ok(true);
+                }
+            } else {
+                std::thread::sleep(wait);
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(false);
+            }
+        }
+    }
+}
+
+/// The descriptor of the active member, which changes on failover.
+impl AsRawFd for BondDevice {
+    fn as_raw_fd(&self) -> RawFd {
+        let inner = self.inner.borrow();
+        let member = inner.active.unwrap_or(0);
+        inner.members[member].dev.as_raw_fd()
+    }
+}
+
+impl Read for BondDevice {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let inner = self.inner.get_mut();
+        inner.monitor()?;
+        let active = inner.active.ok_or_else(no_active)?;
+        let len = inner.members[active].dev.read(buf)?;
+        inner.members[active].last_rx = Instant::now();
+        Ok(len)
+    }
+}
+
+impl Write for BondDevice {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let inner = self.inner.get_mut();
+        inner.monitor()?;
+        inner.write_active(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let inner = self.inner.get_mut();
+        let active = inner.active.ok_or_else(no_active)?;
+        inner.members[active].dev.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, os::unix::net::UnixDatagram, rc::Rc};
+
+    use super::*;
+
+    // a member whose link is switched by the test
+    struct FakeDevice {
+        name: String,
+        sock: UnixDatagram,
+        addr: Rc<RefCell<MacAddress>>,
+        up: Rc<Cell<bool>>,
+    }
+
+    impl FakeDevice {
+        fn new(name: &str, addr: MacAddress) -> (Self, Rc<RefCell<MacAddress>>, Rc<Cell<bool>>) {
+            let (sock, _) = UnixDatagram::pair().unwrap();
+            let addr = Rc::new(RefCell::new(addr));
+            let up = Rc::new(Cell::new(true));
+            let dev = Self {
+                name: name.into(),
+                sock,
+                addr: addr.clone(),
+                up: up.clone(),
+            };
+            (dev, addr, up)
+        }
+    }
+
+    impl Device for FakeDevice {
+        fn name(&self) -> String {
+            self.name.clone()
+        }
+
+        fn address(&self) -> io::Result<MacAddress> {
+            Ok(self.addr.borrow().clone())
+        }
+
+        fn set_address(&self, addr: &MacAddress) -> io::Result<()> {
+            *self.addr.borrow_mut() = addr.clone();
+            Ok(())
+        }
+
+        fn is_up(&self) -> io::Result<bool> {
+            Ok(self.up.get())
+        }
+
+        fn mtu(&self) -> io::Result<usize> {
+            Ok(1500)
+        }
+    }
+
+    impl AsRawFd for FakeDevice {
+        fn as_raw_fd(&self) -> RawFd {
+            self.sock.as_raw_fd()
+        }
+    }
+
+    impl Read for FakeDevice {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.sock.recv(buf)
+        }
+    }
+
+    impl Write for FakeDevice {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn new_active_member_takes_the_bond_address() {
+        let first = MacAddress([2, 0, 0, 0, 0, 1]);
+        let (a, _, a_up) = FakeDevice::new("a", first.clone());
+        let (b, b_addr, _) = FakeDevice::new("b", MacAddress([2, 0, 0, 0, 0, 2]));
+        let mut bond = BondDevice::new("bond0".into(), vec![Box::new(a), Box::new(b)]).unwrap();
+        assert_eq!(bond.active(), Some(0));
+
+        a_up.set(false);
+        bond.monitor().unwrap();
+        assert_eq!(bond.active(), Some(1));
+        assert_eq!(*b_addr.borrow(), first);
+        assert_eq!(bond.address().unwrap(), first);
+    }
+
+    #[test]
+    fn set_address_changes_the_active_member() {
+        let (a, a_addr, _) = FakeDevice::new("a", MacAddress([2, 0, 0, 0, 0, 1]));
+        let bond = BondDevice::new("bond0".into(), vec![Box::new(a)]).unwrap();
+        let addr = MacAddress([2, 0, 0, 0, 0, 9]);
+        bond.set_address(&addr).unwrap();
+        assert_eq!(*a_addr.borrow(), addr);
+        assert_eq!(bond.address().unwrap(), addr);
+    }
+}
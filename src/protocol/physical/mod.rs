@@ -1,13 +1,46 @@
-use std::io::{self, Read, Write};
+use std::{
+    io::{self, Read, Write},
+    os::unix::io::AsRawFd,
+    time::Duration,
+};
 
 use super::link::address::MacAddress;
 
+pub mod bond;
 pub mod raw_socket;
 mod sys;
 pub mod tuntap;
 
-pub trait Device: Read + Write {
+pub trait Device: Read + Write + AsRawFd {
     fn name(&self) -> String;
 
     fn address(&self) -> io::Result<MacAddress>;
+
+    /// Tells whether the link is up.
+    fn is_up(&self) -> io::Result<bool> {
+        sys::is_running(&self.name())
+    }
+
+    /// Changes the hardware address of the device.
+    fn set_address(&self, addr: &MacAddress) -> io::Result<()> {
+        sys::set_address(&self.name(), addr)
+    }
+
+    /// The largest payload of a frame.
+    fn mtu(&self) -> io::Result<usize> {
+        sys::get_mtu(&self.name())
+    }
+
+    /// Waits until the device becomes readable. Returns false on timeout.
+    fn poll(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        sys::poll(self.as_raw_fd(), timeout)
+    }
+}
+
+/// Waits until any of `devs` becomes readable and returns the indices of the
+/// readable ones, which is empty on timeout.
+pub fn select(devs: &[&dyn Device], timeout: Option<Duration>) -> io::Result<Vec<usize>> {
+    let fds = devs.iter().map(|dev| dev.as_raw_fd()).collect::<Vec<_>>();
+    let ready = sys::poll_all(&fds, timeout)?;
+    Ok((0..devs.len()).filter(|&i| ready[i]).collect())
 }
@@ -1,9 +1,9 @@
 use crate::protocol::link::address::MacAddress;
-use libc;
 use std::{
     ffi::CString,
     io::{self, Read, Write},
     mem,
+    os::unix::io::{AsRawFd, RawFd},
 };
 
 use super::{sys, Device};
@@ -12,6 +12,9 @@ extern "C" {
     fn htons(hostshort: i32) -> i32;
 }
 
+// not in libc yet; since Linux 4.20
+const PACKET_IGNORE_OUTGOING: i32 = 23;
+
 pub struct RawSocket {
     fd: i32,
     name: String,
@@ -20,11 +23,7 @@ pub struct RawSocket {
 impl RawSocket {
     pub fn new(name: String) -> io::Result<RawSocket> {
         unsafe {
-            let fd = match libc::socket(
-                libc::AF_PACKET,
-                libc::SOCK_RAW,
-                libc::ETH_P_ALL.to_be() as i32,
-            ) {
+            let fd = match libc::socket(libc::AF_PACKET, libc::SOCK_RAW, libc::ETH_P_ALL.to_be()) {
                 -1 => return Err(io::Error::last_os_error()),
                 fd => fd,
             };
@@ -35,6 +34,36 @@ impl RawSocket {
         }
     }
 
+    /// Receives the frames destined to other hosts too.
+    pub fn set_promiscuous(&self) -> io::Result<()> {
+        unsafe {
+            let mut mreq: libc::packet_mreq = mem::zeroed();
+            mreq.mr_ifindex = self.get_if_index()? as i32;
+            mreq.mr_type = libc::PACKET_MR_PROMISC as u16;
+            self.setsockopt(libc::PACKET_ADD_MEMBERSHIP, &mreq)
+        }
+    }
+
+    /// Stops receiving the frames sent from this host, including our own.
+    pub fn ignore_outgoing(&self) -> io::Result<()> {
+        self.setsockopt(PACKET_IGNORE_OUTGOING, &1i32)
+    }
+
+    fn setsockopt<T>(&self, name: i32, value: &T) -> io::Result<()> {
+        unsafe {
+            match libc::setsockopt(
+                self.fd,
+                libc::SOL_PACKET,
+                name,
+                value as *const T as *const libc::c_void,
+                mem::size_of::<T>() as u32,
+            ) {
+                -1 => Err(io::Error::last_os_error()),
+                _ => Ok(()),
+            }
+        }
+    }
+
     fn bind(&self) -> io::Result<()> {
         unsafe {
             let mut addr: libc::sockaddr_ll = mem::zeroed();
@@ -56,7 +85,7 @@ impl RawSocket {
     fn get_if_index(&self) -> io::Result<u32> {
         unsafe {
             let name_cstr = CString::new(self.name.clone()).unwrap();
-            match libc::if_nametoindex(name_cstr.as_ptr() as *const i8) {
+            match libc::if_nametoindex(name_cstr.as_ptr()) {
                 0 => Err(io::Error::last_os_error()),
                 x => Ok(x),
             }
@@ -74,6 +103,12 @@ impl Device for RawSocket {
     }
 }
 
+impl AsRawFd for RawSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
 impl Read for RawSocket {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         unsafe {
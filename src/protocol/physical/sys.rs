@@ -1,4 +1,7 @@
-use std::{io, mem};
+use std::{
+    io, mem,
+    time::{Duration, Instant},
+};
 
 use crate::protocol::link::address::MacAddress;
 
@@ -14,10 +17,146 @@ pub fn get_address(fd: i32, name: &str) -> io::Result<MacAddress> {
         let mut addr = [0u8; 6];
         addr.clone_from_slice(
             &ifreq.ifr_ifru.ifr_hwaddr.sa_data[..6]
-                .into_iter()
+                .iter()
                 .map(|&i| i as u8)
                 .collect::<Vec<_>>(),
         );
         Ok(MacAddress(addr))
     }
 }
+
+/// Tells whether the interface is up and has a carrier.
+pub fn is_running(name: &str) -> io::Result<bool> {
+    let ifreq = ioctl(name, libc::SIOCGIFFLAGS)?;
+    let flags = ifreq.get_flags() as i32;
+    Ok(flags & libc::IFF_UP != 0 && flags & libc::IFF_RUNNING != 0)
+}
+
+pub fn get_mtu(name: &str) -> io::Result<usize> {
+    let ifreq = ioctl(name, libc::SIOCGIFMTU)?;
+    unsafe { Ok(ifreq.ifr_ifru.ifr_mtu as usize) }
+}
+
+/// Changes the hardware address, which needs `CAP_NET_ADMIN`.
+pub fn set_address(name: &str, addr: &MacAddress) -> io::Result<()> {
+    unsafe {
+        let mut ifreq: ifstructs::ifreq = mem::zeroed();
+        ifreq.set_name(name)?;
+        ifreq.ifr_ifru.ifr_hwaddr.sa_family = libc::ARPHRD_ETHER;
+        for (i, &b) in addr.0.iter().enumerate() {
+            ifreq.ifr_ifru.ifr_hwaddr.sa_data[i] = b as libc::c_char;
+        }
+        request(ifreq, libc::SIOCSIFHWADDR).map(|_| ())
+    }
+}
+
+fn ioctl(name: &str, request_code: libc::c_ulong) -> io::Result<ifstructs::ifreq> {
+    let mut ifreq: ifstructs::ifreq = unsafe { mem::zeroed() };
+    ifreq.set_name(name)?;
+    request(ifreq, request_code)
+}
+
+// an interface request on a socket of its own, as any socket will do
+fn request(mut ifreq: ifstructs::ifreq, request: libc::c_ulong) -> io::Result<ifstructs::ifreq> {
+    unsafe {
+        let fd = match libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) {
+            -1 => return Err(io::Error::last_os_error()),
+            fd => fd,
+        };
+        let ret = libc::ioctl(fd, request, &mut ifreq);
+        let err = io::Error::last_os_error();
+        libc::close(fd);
+        if ret == -1 {
+            return Err(err);
+        }
+        Ok(ifreq)
+    }
+}
+
+pub fn poll(fd: i32, timeout: Option<Duration>) -> io::Result<bool> {
+    Ok(poll_all(&[fd], timeout)?[0])
+}
+
+/// Waits until any of `fds` becomes readable and tells which ones are.
+pub fn poll_all(fds: &[i32], timeout: Option<Duration>) -> io::Result<Vec<bool>> {
+    let mut pollfds = fds
+        .iter()
+        .map(|&fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect::<Vec<_>>();
+    let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+
+    loop {
+        // rounded up, or a timeout below 1ms would not wait at all
+        let timeout = match deadline {
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                left.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32
+            }
+            None => -1,
+        };
+        let ret =
+            unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, timeout) };
+        if ret != -1 {
+            break;
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+
+    // an error or hangup is no data to read, and a pending socket error is
+    // cleared so that it does not wake us again
+    for p in pollfds.iter().filter(|p| p.revents & libc::POLLERR != 0) {
+        let mut err: libc::c_int = 0;
+        let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        unsafe {
+            libc::getsockopt(
+                p.fd,
+                libc::SOL_SOCKET,
+                libc::SO_ERROR,
+                &mut err as *mut _ as *mut libc::c_void,
+                &mut len,
+            );
+        }
+    }
+    Ok(pollfds
+        .iter()
+        .map(|p| p.revents & libc::POLLIN != 0)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        net::UdpSocket,
+        os::unix::{io::AsRawFd, net::UnixDatagram},
+    };
+
+    #[test]
+    fn timeouts_below_a_millisecond_are_waited_for() {
+        let (a, _b) = UnixDatagram::pair().unwrap();
+        let start = Instant::now();
+        let timeout = Duration::from_micros(100);
+        assert_eq!(poll_all(&[a.as_raw_fd()], Some(timeout)).unwrap(), [false]);
+        assert!(start.elapsed() >= timeout);
+    }
+
+    #[test]
+    fn socket_errors_are_not_readable() {
+        let closed = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(closed.local_addr().unwrap()).unwrap();
+        drop(closed);
+        socket.send(&[0]).unwrap();
+
+        let timeout = Some(Duration::from_millis(50));
+        assert_eq!(poll_all(&[socket.as_raw_fd()], timeout).unwrap(), [false]);
+        assert!(socket.take_error().unwrap().is_none());
+    }
+}
@@ -1,6 +1,6 @@
 use crate::protocol::link::address::MacAddress;
 use std::io::{self, Read, Write};
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
 
 use super::{sys, Device};
 
@@ -28,6 +28,12 @@ impl Device for TunTap {
     }
 }
 
+impl AsRawFd for TunTap {
+    fn as_raw_fd(&self) -> RawFd {
+        self.dev.as_raw_fd()
+    }
+}
+
 impl Read for TunTap {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         self.dev.read(buf)